@@ -0,0 +1,51 @@
+//! Times day 2's noun and verb search done as one batch against the
+//! same search done with a fresh machine per trial.
+//!
+//! Run it with `cargo bench --bench batch`.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use aoc2019::intcode::batch::{Batch, Lane};
+use aoc2019::intcode::{self, Isa};
+
+const ROUNDS: u32 = 10;
+
+// the mean time `f` takes over `ROUNDS` runs, after one to warm up.
+fn time<F: FnMut() -> usize>(mut f: F) -> (Duration, usize) {
+    let found = f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert_eq!(f(), found);
+    }
+    (start.elapsed() / ROUNDS, found)
+}
+
+fn main() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("input/2019/day2.txt");
+    let program = intcode::parse_program(fs::read_to_string(path).expect("day 2 input").trim());
+    let lanes: Vec<Lane> = (0..99)
+        .flat_map(|noun| (0..99).map(move |verb| Lane::patched(vec![(1, noun), (2, verb)], vec![])))
+        .collect();
+
+    let (batched, found) = time(|| {
+        let outcomes = Batch::new(&program, Isa::Day2).run(&lanes);
+        outcomes.iter().filter(|outcome| outcome.error.is_none()).count()
+    });
+    let (machines, _) = time(|| {
+        lanes
+            .iter()
+            .filter(|lane| {
+                let mut memory = program.clone();
+                for (address, value) in &lane.patches {
+                    memory[*address] = *value;
+                }
+                intcode::execute_with_isa(&mut memory, &lane.input, Isa::Day2).is_ok()
+            })
+            .count()
+    });
+    println!("{} lanes, {} halted", lanes.len(), found);
+    println!("batch:            {:?}", batched);
+    println!("execute_with_isa: {:?}", machines);
+}
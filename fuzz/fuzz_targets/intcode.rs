@@ -0,0 +1,19 @@
+#![no_main]
+
+//! Checks `intcode::Machine` against the reference interpreter from
+//! the test tree on programs built from fuzzer input.
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/reference/mod.rs"]
+mod reference;
+
+fuzz_target!(|data: &[u8]| {
+    let choices: Vec<u64> = data
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |acc, byte| acc << 8 | *byte as u64))
+        .collect();
+    // the first few choices pick the input, the rest the program.
+    let (input, program) = choices.split_at(choices.len().min(4));
+    reference::check(&reference::generate(program), &reference::input(input));
+});
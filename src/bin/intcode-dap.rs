@@ -0,0 +1,12 @@
+//! Serves the Debug Adapter Protocol for intcode programs over stdin
+//! and stdout.
+
+extern crate aoc2019;
+
+use std::io;
+
+use aoc2019::intcode::dap::Server;
+
+fn main() -> io::Result<()> {
+    Server::new(io::BufReader::new(io::stdin()), io::stdout()).run()
+}
@@ -1,33 +1,43 @@
 use crate::intcode;
+use crate::intcode::batch::Lane;
+use crate::intcode::search::Search;
 
 #[aoc_generator(day2)]
 fn parse_program(input: &str) -> Vec<i64> {
     intcode::parse_program(input)
 }
 
+fn run(machine: &mut intcode::Machine, noun: i64, verb: i64) -> Result<i64, intcode::IntcodeError> {
+    machine.reset();
+    machine.write(1, noun)?;
+    machine.write(2, verb)?;
+    // day 2 programs have no input or output, so they always halt.
+    machine.execute([].iter())?;
+    Ok(*machine.read(0).expect("empty program"))
+}
+
 #[aoc(day2, part1)]
 fn day2_part1(program: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
-    let mut program = program.to_vec();
-    program[1] = 12;
-    program[2] = 2;
-    intcode::execute(&mut program)?;
-    Ok(program[0])
+    let mut machine = intcode::Machine::with_isa(program, intcode::Isa::Day2);
+    Ok(run(&mut machine, 12, 2)?)
 }
 
 #[aoc(day2, part2)]
 fn day2_part2(program: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
-    for noun in 0..99 {
-        for verb in 0..99 {
-            let mut program = program.to_vec();
-            program[1] = noun;
-            program[2] = verb;
-            intcode::execute(&mut program)?;
-            if program[0] == 19690720 {
-                return Ok(100 * noun + verb);
-            }
+    let lanes: Vec<Lane> = (0..99)
+        .flat_map(|noun| (0..99).map(move |verb| Lane::patched(vec![(1, noun), (2, verb)], vec![])))
+        .collect();
+    let search = Search::new(program, intcode::Isa::Day2);
+    let found = search.first(&lanes, |_, outcome| {
+        outcome.error.is_none() && outcome.memory[0] == 19690720
+    });
+    match found {
+        Some((i, _)) => {
+            let (noun, verb) = (lanes[i].patches[0].1, lanes[i].patches[1].1);
+            Ok(100 * noun + verb)
         }
+        None => Err("no noun and verb produce 19690720".into()),
     }
-    Err(Box::new(intcode::IntcodeError::UnknownError))
 }
 
 #[cfg(test)]
@@ -38,7 +48,7 @@ mod tests {
 
     fn execute(program: Vec<i64>) -> Result<Vec<i64>, intcode::IntcodeError> {
         let mut executed = program.clone();
-        intcode::execute(&mut executed)?;
+        intcode::execute_with_isa(&mut executed, &[], intcode::Isa::Day2)?;
         Ok(executed)
     }
 
@@ -60,19 +60,16 @@ impl Move {
             let cell = &mut panel
                 .grid
                 .entry((panel.ox, panel.oy))
-                .or_insert_with(|| HashMap::new());
+                .or_default();
             cell.entry(wire_id).or_insert(*steps);
-            match cell
+            if let Some((_, other_wire_steps)) = cell
                 .iter()
                 .find(|(other_wire_id, _)| **other_wire_id != wire_id)
             {
-                Some((_, other_wire_steps)) => {
-                    panel
-                        .overlaps
-                        .insert((panel.ox, panel.oy), *steps + *other_wire_steps);
-                }
-                None => (),
-            };
+                panel
+                    .overlaps
+                    .insert((panel.ox, panel.oy), *steps + *other_wire_steps);
+            }
         }
     }
 }
@@ -120,12 +117,12 @@ impl Panel {
             panel.ox = 0;
             panel.oy = 0;
         }
-        return panel;
+        panel
     }
 
     fn closest(&self) -> i64 {
-        let ox = self.ox as i64;
-        let oy = self.oy as i64;
+        let ox = self.ox;
+        let oy = self.oy;
         self.overlaps
             .iter()
             .map(|((x, y), _)| (*x - ox).abs() + (*y - oy).abs())
@@ -141,12 +138,13 @@ impl Panel {
             .expect("no min?")
     }
 
+    #[allow(dead_code)]
     fn png(&self, path: &str) {
         let (mut min_x, mut min_y, mut max_x, mut max_y) = (
-            i64::max_value(),
-            i64::max_value(),
-            i64::min_value(),
-            i64::min_value(),
+            i64::MAX,
+            i64::MAX,
+            i64::MIN,
+            i64::MIN,
         );
 
         for ((x, y), _) in self.grid.iter() {
@@ -157,8 +155,8 @@ impl Panel {
         }
 
         let mut img: image::RgbImage = image::ImageBuffer::new(
-            (max_x - min_x + 1).abs() as u32,
-            (max_y - min_y + 1).abs() as u32,
+            (max_x - min_x + 1).unsigned_abs() as u32,
+            (max_y - min_y + 1).unsigned_abs() as u32,
         );
 
         for (x, y, pixel) in img.enumerate_pixels_mut() {
@@ -1,11 +1,10 @@
 #[aoc_generator(day4)]
 fn parse_range(input: &str) -> Result<(usize, usize), std::num::ParseIntError> {
-    let upper_lower: Vec<usize> = match input.split("-").map(|line| line.parse::<usize>()).collect()
-    {
-        Ok(vec) => vec,
-        Err(e) => return Err(e),
-    };
-    return Ok((upper_lower[0], upper_lower[1]));
+    let upper_lower: Vec<usize> = input
+        .split('-')
+        .map(|line| line.parse::<usize>())
+        .collect::<Result<_, _>>()?;
+    Ok((upper_lower[0], upper_lower[1]))
 }
 
 fn is_password<F>(input: &usize, counter: F) -> bool
@@ -8,7 +8,7 @@ fn parse_program(input: &str) -> Vec<i64> {
 #[aoc(day5, part1)]
 fn day5_part1(program: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
     let mut program = program.to_vec();
-    let outputs = intcode::execute_with_input(&mut program, &[1])?;
+    let outputs = intcode::execute_with_isa(&mut program, &[1], intcode::Isa::Day5)?;
     for output in outputs.iter() {
         println!("output: {}", output);
     }
@@ -18,7 +18,7 @@ fn day5_part1(program: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
 #[aoc(day5, part2)]
 fn day5_part2(program: &[i64]) -> Result<i64, Box<dyn std::error::Error>> {
     let mut program = program.to_vec();
-    let outputs = intcode::execute_with_input(&mut program, &[5])?;
+    let outputs = intcode::execute_with_isa(&mut program, &[5], intcode::Isa::Day5)?;
     for output in outputs.iter() {
         println!("output: {}", output);
     }
@@ -30,12 +30,12 @@ mod tests {
     use super::*;
 
     fn check(mut program: Vec<i64>, expected: Vec<i64>) {
-        intcode::execute(&mut program).expect("success");
+        intcode::execute_with_isa(&mut program, &[], intcode::Isa::Day5).expect("success");
         assert_eq!(program, expected);
     }
 
     fn check_input_output(mut program: Vec<i64>, input: Vec<i64>, expected: Vec<i64>) {
-        let output = intcode::execute_with_input(&mut program, &input).expect("failure");
+        let output = intcode::execute_with_isa(&mut program, &input, intcode::Isa::Day5).expect("failure");
         assert_eq!(output, expected);
     }
 
@@ -36,7 +36,7 @@ struct Orbits<'a> {
 }
 
 impl<'a> Orbits<'a> {
-    fn new(orbits: &[Orbit]) -> Orbits {
+    fn new(orbits: &[Orbit]) -> Orbits<'_> {
         let mut objs = collections::HashSet::new();
         let mut adj = collections::HashMap::new();
 
@@ -55,7 +55,7 @@ impl<'a> Orbits<'a> {
     }
 
     fn direct(&self, obj: &str) -> Option<&str> {
-        self.adj.get(obj).map(|r| *r)
+        self.adj.get(obj).copied()
     }
 
     fn closure(&self, obj: &str) -> Vec<&str> {
@@ -93,10 +93,11 @@ impl<'a> Orbits<'a> {
                  match self.direct(o) {
                     Some(_) => 1,
                     None => 0,
-                 } + self.indirect(o).iter().count())
+                 } + self.indirect(o).len())
             .sum::<usize>() - 1
     }
 
+    #[allow(dead_code)]
     fn debug(&self) {
         for o in self.objs.iter() {
             println!(
@@ -1,4 +1,5 @@
 use crate::intcode;
+use crate::intcode::search::Search;
 
 #[aoc_generator(day7)]
 fn parse_program(input: &str) -> Vec<i64> {
@@ -11,38 +12,41 @@ struct Amplifier {
 }
 
 impl Amplifier {
-    fn new(program: &[i64], phase: i64) -> Self {
+    fn new(pool: &mut intcode::Pool, phase: i64) -> Self {
         Amplifier{
-            machine: intcode::Machine::new(program),
-            phase: phase,
+            machine: pool.take(),
+            phase,
         }
     }
 
     fn amplify(&mut self, input: i64) -> Result<i64, intcode::IntcodeError> {
         let input = [self.phase, input];
         match self.machine.execute(input.iter())? {
-            Some(o) => Ok(o),
-            None => Err(intcode::IntcodeError::UnknownError),
+            intcode::Exit::Output(o) => Ok(o),
+            intcode::Exit::Halted { pc, .. } => Err(intcode::IntcodeError::UnexpectedHalt(pc)),
+            intcode::Exit::AwaitingInput { pc } => Err(intcode::IntcodeError::MissingInput(pc)),
         }
     }
 
-    fn reamplify(&mut self, input: i64) -> intcode::OutputResult {
+    fn reamplify(&mut self, input: i64) -> intcode::ExitResult {
         let input = [input];
-        match self.machine.execute(input.iter()) {
-            Ok(output) => Ok(output),
-            Err(e) => Err(e),
-        }
+        self.machine.execute(input.iter())
     }
 }
 
-fn series<'a>(program: &[i64], phases: &'a [i64]) -> Vec<Amplifier> {
+fn series(pool: &mut intcode::Pool, phases: &[i64]) -> Vec<Amplifier> {
     phases
         .iter()
-        // to_vec copies the program
-        .map(|phase| Amplifier::new(program, *phase))
+        .map(|phase| Amplifier::new(pool, *phase))
         .collect()
 }
 
+fn recycle(pool: &mut intcode::Pool, series: Vec<Amplifier>) {
+    for amplifier in series {
+        pool.give(amplifier.machine);
+    }
+}
+
 fn execute(series: &mut [Amplifier]) -> Result<i64, intcode::IntcodeError> {
     series.iter_mut().try_fold(0, |input, amplifier| amplifier.amplify(input))
 }
@@ -52,18 +56,20 @@ fn feedback(series: &mut [Amplifier]) -> Result<i64, intcode::IntcodeError> {
     let mut i = 0;
     loop {
         let amplifier = &mut series[i];
-        let output = amplifier.reamplify(input)?;
-        if output.is_none() {
-            return Ok(input);
-        }
-        input = output.unwrap();
+        input = match amplifier.reamplify(input)? {
+            intcode::Exit::Output(output) => output,
+            intcode::Exit::Halted { .. } => return Ok(input),
+            intcode::Exit::AwaitingInput { pc } => {
+                return Err(intcode::IntcodeError::MissingInput(pc))
+            }
+        };
         i = (i + 1) % series.len();
     }
 }
 
 // so inefficient!
 pub fn permutations(elements: Vec<i64>) -> Vec<Vec<i64>> {
-    if elements.len() == 0 {
+    if elements.is_empty() {
         vec![vec![]]
     } else {
         let mut perms = vec![];
@@ -81,30 +87,33 @@ pub fn permutations(elements: Vec<i64>) -> Vec<Vec<i64>> {
     }
 }
 
-#[aoc(day7, part1)]
-fn day7_part1(program: &[i64]) -> i64 {
-    let phases = vec![0, 1, 2, 3, 4];
-    permutations(phases)
-        .iter()
-        .map(|phases_permutation| {
-            let mut amps = series(program, &phases_permutation);
-            execute(&mut amps).expect("oops")
-        })
+fn max_signal<F>(program: &[i64], phases: Vec<i64>, run: F) -> i64
+where
+    F: Fn(&mut [Amplifier]) -> Result<i64, intcode::IntcodeError> + Sync,
+{
+    let search = Search::new(program, intcode::Isa::Day5);
+    let evaluate = |pool: &mut intcode::Pool, phases_permutation: &Vec<i64>| {
+        let mut amps = series(pool, phases_permutation);
+        let output = run(&mut amps).expect("oops");
+        recycle(pool, amps);
+        output
+    };
+    search
+        .all_with(&permutations(phases), evaluate, |_, _| true)
+        .into_iter()
+        .map(|(_, output)| output)
         .max()
         .unwrap()
 }
 
+#[aoc(day7, part1)]
+fn day7_part1(program: &[i64]) -> i64 {
+    max_signal(program, vec![0, 1, 2, 3, 4], execute)
+}
+
 #[aoc(day7, part2)]
 fn day7_part2(program: &[i64]) -> i64 {
-    let phases = vec![5, 6, 7, 8, 9];
-    permutations(phases)
-        .iter()
-        .map(|phases_permutation| {
-            let mut amps = series(program, &phases_permutation);
-            feedback(&mut amps).expect("oops")
-        })
-        .max()
-        .unwrap()
+    max_signal(program, vec![5, 6, 7, 8, 9], feedback)
 }
 
 #[cfg(test)]
@@ -127,7 +136,8 @@ mod tests {
     }
 
     fn check_execute(program: Vec<i64>, phases: Vec<i64>, max: i64) {
-        let mut amps = series(&program, &phases);
+        let mut pool = intcode::Pool::new(&program, intcode::Isa::Day5);
+        let mut amps = series(&mut pool, &phases);
         let res = execute(&mut amps).expect("failure");
         assert_eq!(res, max);
     }
@@ -159,8 +169,15 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_part1_max_signal() {
+        let program = vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+        assert_eq!(day7_part1(&program), 43210);
+    }
+
     fn check_feedback(program: Vec<i64>, phases: Vec<i64>, max: i64) {
-        let mut amps = series(&program, &phases);
+        let mut pool = intcode::Pool::new(&program, intcode::Isa::Day5);
+        let mut amps = series(&mut pool, &phases);
         let res = feedback(&mut amps).expect("failure");
         assert_eq!(res, max);
     }
@@ -182,4 +199,10 @@ mod tests {
             18216,
         )
     }
+
+    #[test]
+    fn test_part2_max_signal() {
+        let program = vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        assert_eq!(day7_part2(&program), 139629729);
+    }
 }
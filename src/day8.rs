@@ -15,6 +15,7 @@ struct Layer<'a> {
 }
 
 impl<'a> Layer<'a> {
+    #[allow(dead_code)]
     fn new(length: usize, width: usize, pixels: &'a [usize]) -> Self {
         Layer{
             length,
@@ -33,8 +34,8 @@ fn layers_from_pixels<'a>(length: usize, width: usize, pixels: &'a [usize]) -> V
     (0..pixels.len())
         .step_by(layer_size)
         .map(|i| Layer{
-            length: length,
-            width: width,
+            length,
+            width,
             pixels: &pixels[i..i+layer_size]
         })
         .collect()
@@ -50,8 +51,8 @@ impl Image {
     fn new<'a>(layers: Vec<Layer<'a>>) -> Self {
         let (length, width) = (layers[0].length, layers[0].width);
         let mut image = Image{
-            length: length,
-            width: width,
+            length,
+            width,
             pixels: vec![],
         };
 
@@ -84,8 +85,8 @@ impl Image {
         img.save(path).unwrap()
     }
 
-    fn to_string(&self) -> String {
-        let rows = (&self.pixels)
+    fn render(&self) -> String {
+        let rows = self.pixels
             .chunks(self.width)
             .map(|row| row.iter().map(|p| {
                 if *p == 1 {
@@ -114,7 +115,7 @@ fn day8_part2(pixels: &[usize]) -> String {
     let layers = layers_from_pixels(length, width, pixels);
     let img = Image::new(layers);
     img.png("/tmp/day8.png");
-    img.to_string()
+    img.render()
 }
 
 #[cfg(test)]
@@ -32,21 +32,21 @@ mod tests {
 
     #[test]
     fn test_relative() {
-        let mut relative = vec![109,3,204,-1,99];
+        let mut relative: Vec<i64> = vec![109,3,204,-1,99];
         let output = intcode::execute(&mut relative).expect("execute");
         assert_eq!(output, vec![204]);
     }
 
     #[test]
     fn test_quine() {
-        let mut quine = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
+        let mut quine: Vec<i64> = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
         let output = intcode::execute(&mut quine).expect("execute");
         assert_eq!(output, quine);
     }
 
     #[test]
     fn test_16_digit_number() {
-        let mut sixteen = vec![1102,34915192,34915192,7,4,7,99,0];
+        let mut sixteen: Vec<i64> = vec![1102,34915192,34915192,7,4,7,99,0];
         let output = intcode::execute(&mut sixteen).expect("execute");
         assert_eq!(output.len(), 1);
         assert_eq!(output[0].to_string().len(), 16);
@@ -54,7 +54,7 @@ mod tests {
 
     #[test]
     fn test_middle_number() {
-        let mut middle = vec![104,1125899906842624,99];
+        let mut middle: Vec<i64> = vec![104,1125899906842624,99];
         let output = intcode::execute(&mut middle).expect("execute");
         assert_eq!(output, vec![middle[1]]);
     }
@@ -1,28 +1,164 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
 use std::error;
 use std::fmt;
+use std::fs;
+use std::io;
 use std::result;
 use std::iter;
+use std::mem;
+use std::ops;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
 
-pub type AllOutputResult = std::result::Result<Vec<i64>, IntcodeError>;
-pub type OutputResult = std::result::Result<Option<i64>, IntcodeError>;
+use ibig::IBig;
+
+use device::{Device, Mapping};
+use sourcemap::{Location, SourceMap};
+
+pub mod assemble;
+pub mod batch;
+pub mod compile;
+pub mod conformance;
+pub mod coverage;
+pub mod dap;
+pub mod debug;
+pub mod decompile;
+pub mod device;
+pub mod disassemble;
+pub mod library;
+pub mod link;
+pub mod optimize;
+pub mod record;
+pub mod search;
+pub mod sourcemap;
+pub mod transpile;
+
+pub type AllOutputResult<W = i64> = std::result::Result<Vec<W>, Failure<W>>;
+pub type ExitResult<W = i64> = std::result::Result<Exit<W>, IntcodeError<W>>;
+
+/// Why `Machine::execute` stopped running the program.
+#[derive(Debug, PartialEq)]
+pub enum Exit<W = i64> {
+    /// the program produced a value; execute again to continue.
+    Output(W),
+    /// the program reached opcode 99 at `pc` after running `steps`
+    /// instructions.
+    Halted { pc: usize, steps: usize },
+    /// the input instruction at `pc` found no input; execute again
+    /// with more input to continue.
+    AwaitingInput { pc: usize },
+}
 
 #[derive(Debug)]
-pub enum IntcodeError {
-    UnknownError,
+pub enum IntcodeError<W = i64> {
     UnknownOpcode(usize, i64),
+    InvalidInstruction(usize, W),
     InvalidParameterType(usize, i64, &'static str),
     UnknownParameterType(usize, i64),
-    NegativePosition(usize, i64, i64),
+    NegativePosition(usize, i64, W),
     MissingInput(usize),
+    UnexpectedHalt(usize),
+    InvalidAddress(usize),
+    StepLimit(usize, usize),
+    UnsupportedOpcode(usize, i64, Isa),
+    UnsupportedParameterType(usize, i64, Isa),
+    InvalidRelativeBase(usize, i64, W),
+    ArithmeticOverflow(usize, i64, W, W),
+    /// another error, along with the state of the machine when it
+    /// happened.
+    Context(Box<Context<W>>),
+    /// a machine can't keep an undo log while devices are attached,
+    /// since what they do can't be undone.
+    DevicesAttached,
+}
+
+impl<W> IntcodeError<W> {
+    /// The error itself, without any context wrapped around it.
+    pub fn kind(&self) -> &IntcodeError<W> {
+        match self {
+            IntcodeError::Context(context) => context.error.kind(),
+            error => error,
+        }
+    }
+
+    pub fn into_kind(self) -> IntcodeError<W> {
+        match self {
+            IntcodeError::Context(context) => context.error.into_kind(),
+            error => error,
+        }
+    }
+
+    pub fn context(&self) -> Option<&Context<W>> {
+        match self {
+            IntcodeError::Context(context) => Some(context),
+            _ => None,
+        }
+    }
+}
+
+// how many cells either side of the pc an error's context shows.
+const WINDOW: usize = 4;
+
+/// The state of a machine when it failed.
+#[derive(Debug)]
+pub struct Context<W = i64> {
+    pub error: IntcodeError<W>,
+    pub pc: usize,
+    pub relative_base: i64,
+    pub steps: usize,
+    /// the raw word at the pc, if the pc is inside memory.
+    pub instruction: Option<W>,
+    /// the opcode and the three parameter modes, if the instruction
+    /// fits in an i64.
+    pub opcode: Option<i64>,
+    pub modes: Option<[i64; 3]>,
+    /// the memory around the pc, starting at `window_start`.
+    pub window_start: usize,
+    pub window: Vec<W>,
+    /// where the pc is in the source, if the machine has a source map.
+    pub location: Option<Location>,
+}
+
+impl<W: Word> fmt::Display for Context<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        write!(f, "; ")?;
+        match (&self.instruction, self.opcode, self.modes) {
+            (Some(instruction), Some(opcode), Some(modes)) => write!(
+                f,
+                "instruction {} (opcode {}, modes {} {} {})",
+                instruction, opcode, modes[0], modes[1], modes[2]
+            )?,
+            (Some(instruction), _, _) => write!(f, "instruction {}", instruction)?,
+            (None, _, _) => write!(f, "no instruction")?,
+        }
+        write!(
+            f,
+            " at step {}, relative base {}; memory from {}:",
+            self.steps, self.relative_base, self.window_start
+        )?;
+        for (i, word) in self.window.iter().enumerate() {
+            if self.window_start + i == self.pc {
+                write!(f, " [{}]", word)?;
+            } else {
+                write!(f, " {}", word)?;
+            }
+        }
+        Ok(())
+    }
 }
 
-impl fmt::Display for IntcodeError {
+impl<W: Word> fmt::Display for IntcodeError<W> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use IntcodeError::*;
         match self {
-            UnknownError => write!(f, "unknown error"),
             UnknownOpcode(pc, o) => write!(f, "pc: {}, unknown opcode {}", pc, o),
+            InvalidInstruction(pc, i) => write!(f, "pc: {}, invalid instruction {}", pc, i),
             UnknownParameterType(pc, t) => write!(f, "pc: {}, unknown parameter type {}", pc, t),
             InvalidParameterType(pc, t, operation) => write!(
                 f,
@@ -35,31 +171,605 @@ impl fmt::Display for IntcodeError {
                 pc, opcode, position
             ),
             MissingInput(pc) => write!(f, "pc: {}, input instruction but no input", pc),
+            UnexpectedHalt(pc) => write!(f, "pc: {}, halted before producing output", pc),
+            InvalidAddress(address) => write!(f, "address {} is outside memory", address),
+            StepLimit(pc, limit) => write!(f, "pc: {}, step limit of {} reached", pc, limit),
+            UnsupportedOpcode(pc, o, isa) => {
+                write!(f, "pc: {}, opcode {} is not part of the {:?} ISA", pc, o, isa)
+            }
+            UnsupportedParameterType(pc, t, isa) => write!(
+                f,
+                "pc: {}, parameter type {} is not part of the {:?} ISA",
+                pc, t, isa
+            ),
+            InvalidRelativeBase(pc, base, offset) => write!(
+                f,
+                "pc: {}, relative base {} offset by {} is out of range",
+                pc, base, offset
+            ),
+            ArithmeticOverflow(pc, opcode, a, b) => write!(
+                f,
+                "pc: {}, opcode {} overflowed with operands {} and {}",
+                pc, opcode, a, b
+            ),
+            DevicesAttached => write!(f, "a machine with devices attached can't keep an undo log"),
+            Context(context) => context.fmt(f),
+        }
+    }
+}
+
+impl<W: Word> error::Error for IntcodeError<W> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            // the context's message already includes the error's own.
+            IntcodeError::Context(context) => error::Error::source(&context.error),
+            _ => None,
+        }
+    }
+}
+
+/// Why a program, a source or one of the tools' files couldn't be
+/// read, written or parsed.
+#[derive(Debug)]
+pub enum FileError {
+    /// the file couldn't be read or written.
+    Io(PathBuf, io::Error),
+    /// the word at the given index of the program text, counting
+    /// from 0, isn't a number.
+    Word(usize, String, Box<dyn error::Error + Send + Sync>),
+    /// the given line of a source or one of the tools' files,
+    /// counting from 1, isn't valid.
+    Line(usize, String, Box<dyn error::Error + Send + Sync>),
+    /// an image couldn't be encoded in the format the file's
+    /// extension names.
+    Image(PathBuf, image::ImageError),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io(path, _) => write!(f, "couldn't read or write {}", path.display()),
+            FileError::Word(index, word, _) => write!(f, "word {}, invalid intcode word {:?}", index, word),
+            FileError::Line(number, line, _) => write!(f, "line {}, can't parse {:?}", number, line),
+            FileError::Image(path, _) => write!(f, "couldn't save an image as {}", path.display()),
+        }
+    }
+}
+
+impl error::Error for FileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FileError::Io(_, e) => Some(e),
+            FileError::Word(_, _, e) | FileError::Line(_, _, e) => Some(e.as_ref()),
+            FileError::Image(_, e) => Some(e),
+        }
+    }
+}
+
+/// Hands out freshly reset machines for one program, so searches
+/// that run it many times don't allocate its memory over and over.
+pub struct Pool<W: Word = i64> {
+    program: Vec<W>,
+    isa: Isa,
+    step_limit: Option<usize>,
+    source_map: Option<Arc<SourceMap>>,
+    free: Vec<Machine<W>>,
+}
+
+impl<W: Word> Pool<W> {
+    pub fn new(program: &[W], isa: Isa) -> Self {
+        Pool {
+            program: program.to_vec(),
+            isa,
+            step_limit: None,
+            source_map: None,
+            free: vec![],
+        }
+    }
+
+    /// Sets the step limit of every machine handed out from now on.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Gives every machine handed out from now on `map`, so that
+    /// their errors say where they happened.
+    pub fn set_source_map(&mut self, map: Option<Arc<SourceMap>>) {
+        self.source_map = map;
+    }
+
+    pub fn take(&mut self) -> Machine<W> {
+        let mut machine = match self.free.pop() {
+            Some(mut machine) => {
+                machine.reset();
+                machine
+            }
+            None => Machine::with_isa(&self.program, self.isa),
+        };
+        machine.set_step_limit(self.step_limit);
+        machine.set_source_map(self.source_map.clone());
+        machine
+    }
+
+    /// Returns a machine to the pool.  It's reset when it's next
+    /// taken, not now.
+    pub fn give(&mut self, machine: Machine<W>) {
+        self.free.push(machine);
+    }
+}
+
+/// A run that stopped with an error, along with everything it
+/// produced before it did.
+pub struct Failure<W: Word = i64> {
+    pub output: Vec<W>,
+    pub machine: Box<Machine<W>>,
+    pub error: IntcodeError<W>,
+}
+
+impl<W: Word> fmt::Debug for Failure<W> {
+    // the machine's memory is far too large to be useful here.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Failure")
+            .field("output", &self.output)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<W: Word> fmt::Display for Failure<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} after output {:?}", self.error, self.output)
+    }
+}
+
+impl<W: Word> error::Error for Failure<W> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<W: Word> From<Failure<W>> for IntcodeError<W> {
+    fn from(failure: Failure<W>) -> Self {
+        failure.error
+    }
+}
+
+/// A value stored in an intcode machine's memory.  Instructions,
+/// positions and relative base offsets must still fit in an i64, but
+/// the data a program computes with can be as wide as the word.
+pub trait Word:
+    Clone + PartialEq + PartialOrd + From<i64> + fmt::Debug + fmt::Display + 'static
+{
+    fn to_i64(&self) -> Option<i64>;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn wrapping_add(&self, other: &Self) -> Self;
+    fn wrapping_mul(&self, other: &Self) -> Self;
+    fn saturating_add(&self, other: &Self) -> Self;
+    fn saturating_mul(&self, other: &Self) -> Self;
+    fn parse_word(text: &str) -> result::Result<Self, Box<dyn error::Error + Send + Sync>>;
+}
+
+macro_rules! primitive_word {
+    ($t:ty) => {
+        impl Word for $t {
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+
+            fn wrapping_add(&self, other: &Self) -> Self {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            fn wrapping_mul(&self, other: &Self) -> Self {
+                <$t>::wrapping_mul(*self, *other)
+            }
+
+            fn saturating_add(&self, other: &Self) -> Self {
+                <$t>::saturating_add(*self, *other)
+            }
+
+            fn saturating_mul(&self, other: &Self) -> Self {
+                <$t>::saturating_mul(*self, *other)
+            }
+
+            fn parse_word(text: &str) -> result::Result<Self, Box<dyn error::Error + Send + Sync>> {
+                Ok(text.parse::<$t>()?)
+            }
+        }
+    };
+}
+
+primitive_word!(i64);
+primitive_word!(i128);
+
+// arbitrary precision words never overflow, so every policy agrees.
+impl Word for IBig {
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn saturating_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn saturating_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn parse_word(text: &str) -> result::Result<Self, Box<dyn error::Error + Send + Sync>> {
+        Ok(text.parse::<IBig>()?)
+    }
+}
+
+/// The instruction set a machine accepts.  Each puzzle extends the
+/// previous one, so restricting a machine to an earlier profile
+/// catches programs (or interpreter bugs) that reach for features
+/// the puzzle never introduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    /// add, multiply and halt in position mode.
+    Day2,
+    /// adds input, output, jumps and comparisons, and immediate mode.
+    Day5,
+    /// adds relative base adjustment and relative mode.
+    #[default]
+    Day9,
+}
+
+impl Isa {
+    pub fn supports_opcode(self, opcode: i64) -> bool {
+        match opcode {
+            1 | 2 | 99 => true,
+            3..=8 => self != Isa::Day2,
+            9 => self == Isa::Day9,
+            _ => false,
+        }
+    }
+
+    pub fn supports_parameter_type(self, parameter_type: i64) -> bool {
+        match parameter_type {
+            0 => true,
+            1 => self != Isa::Day2,
+            2 => self == Isa::Day9,
+            _ => false,
+        }
+    }
+}
+
+/// What add and multiply do when the result doesn't fit in a word.
+/// Plain arithmetic panics in debug builds and wraps in release
+/// builds; picking a policy makes both behave the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// fail with `IntcodeError::ArithmeticOverflow`.
+    #[default]
+    Checked,
+    /// wrap around at the boundary of the type.
+    Wrapping,
+    /// clamp to the type's minimum or maximum.
+    Saturating,
+}
+
+impl Overflow {
+    fn add<W: Word>(self, a: &W, b: &W) -> Option<W> {
+        match self {
+            Overflow::Checked => a.checked_add(b),
+            Overflow::Wrapping => Some(a.wrapping_add(b)),
+            Overflow::Saturating => Some(a.saturating_add(b)),
+        }
+    }
+
+    fn multiply<W: Word>(self, a: &W, b: &W) -> Option<W> {
+        match self {
+            Overflow::Checked => a.checked_mul(b),
+            Overflow::Wrapping => Some(a.wrapping_mul(b)),
+            Overflow::Saturating => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+// how many parameters an opcode takes, if it's one.
+fn parameters(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(3),
+        3 | 4 | 9 => Some(1),
+        5 | 6 => Some(2),
+        99 => Some(0),
+        _ => None,
+    }
+}
+
+// the memory instructions run against: a machine's, along with its
+// devices, or one of a batch's lanes.
+trait Memory {
+    type Word: Word;
+
+    // how many cells there are.
+    fn size(&self) -> usize;
+
+    // the word stored at `address`, as an instruction and its
+    // parameters are read.
+    fn word(&self, address: usize) -> result::Result<&Self::Word, IntcodeError<Self::Word>>;
+
+    // reads a data word, which may come from a device.
+    fn load(&mut self, address: usize) -> result::Result<Self::Word, IntcodeError<Self::Word>> {
+        self.word(address).cloned()
+    }
+
+    // stores a data word, returning the value it replaced unless it
+    // went to a device.
+    fn store(
+        &mut self,
+        address: usize,
+        value: Self::Word,
+    ) -> result::Result<Option<Self::Word>, IntcodeError<Self::Word>>;
+}
+
+impl<W: Word> Memory for [W] {
+    type Word = W;
+
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn word(&self, address: usize) -> result::Result<&W, IntcodeError<W>> {
+        self.get(address).ok_or(IntcodeError::InvalidAddress(address))
+    }
+
+    fn store(&mut self, address: usize, value: W) -> result::Result<Option<W>, IntcodeError<W>> {
+        match self.get_mut(address) {
+            Some(cell) => Ok(Some(mem::replace(cell, value))),
+            None => Err(IntcodeError::InvalidAddress(address)),
+        }
+    }
+}
+
+// a machine's memory with its devices mapped over it.
+struct Mapped<'a, W: Word> {
+    program: &'a mut [W],
+    devices: &'a mut [Mapping<W>],
+}
+
+impl<'a, W: Word> Memory for Mapped<'a, W> {
+    type Word = W;
+
+    fn size(&self) -> usize {
+        self.program.len()
+    }
+
+    fn word(&self, address: usize) -> result::Result<&W, IntcodeError<W>> {
+        self.program.word(address)
+    }
+
+    fn load(&mut self, address: usize) -> result::Result<W, IntcodeError<W>> {
+        if let Some((device, offset)) = device::mapped(self.devices, address) {
+            return Ok(device.read(offset));
+        }
+        self.program.load(address)
+    }
+
+    fn store(&mut self, address: usize, value: W) -> result::Result<Option<W>, IntcodeError<W>> {
+        if let Some((device, offset)) = device::mapped(self.devices, address) {
+            device.write(offset, value);
+            return Ok(None);
+        }
+        self.program.store(address, value)
+    }
+}
+
+// checks that `word`, found at `pc`, is an instruction `isa` has,
+// including the types of all its parameters, whether or not they're
+// used.
+fn decode<W: Word>(word: &W, pc: usize, isa: Isa) -> result::Result<i64, IntcodeError<W>> {
+    let instruction = match word.to_i64() {
+        Some(instruction) => instruction,
+        None => return Err(IntcodeError::InvalidInstruction(pc, word.clone())),
+    };
+    let opcode = instruction % 100;
+    if !isa.supports_opcode(opcode) {
+        return Err(match opcode {
+            1..=9 | 99 => IntcodeError::UnsupportedOpcode(pc, opcode, isa),
+            _ => IntcodeError::UnknownOpcode(pc, opcode),
+        });
+    }
+    let parameters = instruction / 100;
+    for n in 0..parameters_of(opcode) {
+        match parameters / 10i64.pow(n) % 10 {
+            parameter_type @ 0..=2 if !isa.supports_parameter_type(parameter_type) => {
+                return Err(IntcodeError::UnsupportedParameterType(pc, parameter_type, isa))
+            }
+            0..=2 => (),
+            parameter_type => return Err(IntcodeError::UnknownParameterType(pc, parameter_type)),
+        }
+    }
+    Ok(instruction)
+}
+
+fn parameters_of(opcode: i64) -> u32 {
+    parameters(opcode).unwrap_or(0) as u32
+}
+
+// an address an instruction wrote to and the value it replaced.
+type Write<W> = (usize, W);
+
+// what running an instruction did.
+enum Effect<W> {
+    // it ran, making the write and consuming and producing the values
+    // given.
+    Ran {
+        write: Option<Write<W>>,
+        input: Option<W>,
+        output: Option<W>,
+    },
+    // it's an input instruction and there's no more input.
+    AwaitingInput,
+    Halted,
+}
+
+// runs `instruction`, decoded from the word at `pc`, moving the pc and
+// relative base on.  Neither moves if it fails.
+fn run_instruction<'a, M, I>(
+    instruction: i64,
+    pc: &mut usize,
+    relative_base: &mut i64,
+    isa: Isa,
+    overflow: Overflow,
+    memory: &mut M,
+    input: &mut I,
+) -> result::Result<Effect<M::Word>, IntcodeError<M::Word>>
+where
+    M: Memory + ?Sized,
+    I: iter::Iterator<Item = &'a M::Word>,
+{
+    let mut instruction = Instruction::new(instruction, *pc, *relative_base, isa, memory);
+    let mut write = None;
+    let mut consumed = None;
+    let mut output = None;
+    let zero = M::Word::from(0);
+    match instruction.opcode() {
+        // add
+        1 => {
+            let (a, b) = (instruction.parameter(0)?, instruction.parameter(1)?);
+            let sum = match overflow.add(&a, &b) {
+                Some(res) => res,
+                None => return Err(IntcodeError::ArithmeticOverflow(*pc, 1, a, b)),
+            };
+            write = instruction.store(2, sum)?;
+        }
+        // multiply
+        2 => {
+            let (a, b) = (instruction.parameter(0)?, instruction.parameter(1)?);
+            let product = match overflow.multiply(&a, &b) {
+                Some(res) => res,
+                None => return Err(IntcodeError::ArithmeticOverflow(*pc, 2, a, b)),
+            };
+            write = instruction.store(2, product)?;
+        }
+        // input
+        3 => {
+            let input = match input.next() {
+                Some(input) => input.clone(),
+                None => return Ok(Effect::AwaitingInput),
+            };
+            write = instruction.store(0, input.clone())?;
+            consumed = Some(input);
+        }
+        // output
+        4 => output = Some(instruction.parameter(0)?),
+        // jump-if-true
+        5 => {
+            if instruction.parameter(0)? != zero {
+                let target = instruction.parameter(1)?;
+                *pc = instruction.intcode_index(&target)?;
+                return Ok(Effect::Ran { write, input: consumed, output });
+            }
+        }
+        // jump-if-false
+        6 => {
+            if instruction.parameter(0)? == zero {
+                let target = instruction.parameter(1)?;
+                *pc = instruction.intcode_index(&target)?;
+                return Ok(Effect::Ran { write, input: consumed, output });
+            }
         }
+        // less than
+        7 => {
+            let less = instruction.parameter(0)? < instruction.parameter(1)?;
+            write = instruction.store(2, M::Word::from(if less { 1 } else { 0 }))?;
+        }
+        // equals
+        8 => {
+            let equal = instruction.parameter(0)? == instruction.parameter(1)?;
+            write = instruction.store(2, M::Word::from(if equal { 1 } else { 0 }))?;
+        }
+        // change the relative base
+        9 => {
+            let offset = instruction.parameter(0)?;
+            *relative_base = match offset.to_i64().and_then(|o| relative_base.checked_add(o)) {
+                Some(base) if base >= 0 => base,
+                _ => return Err(IntcodeError::InvalidRelativeBase(*pc, *relative_base, offset)),
+            };
+        }
+        99 => return Ok(Effect::Halted),
+        opcode => return Err(IntcodeError::UnknownOpcode(*pc, opcode)),
     }
+    *pc += 1 + parameters_of(instruction.opcode()) as usize;
+    Ok(Effect::Ran { write, input: consumed, output })
 }
 
-impl error::Error for IntcodeError {
-    fn cause(&self) -> Option<&dyn error::Error> {
-        None
+// wraps `error` in the state of the machine or lane it happened in,
+// unless it already has context.
+fn annotate<M: Memory + ?Sized>(
+    error: IntcodeError<M::Word>,
+    pc: usize,
+    relative_base: i64,
+    steps: usize,
+    memory: &M,
+    source_map: Option<&SourceMap>,
+) -> IntcodeError<M::Word> {
+    if let IntcodeError::Context(_) = error {
+        return error;
     }
+    let instruction = memory.word(pc).ok().cloned();
+    let decoded = instruction.as_ref().and_then(|i| i.to_i64());
+    let window_start = pc.saturating_sub(WINDOW).min(memory.size());
+    let window_end = pc.saturating_add(WINDOW + 1).min(memory.size());
+    IntcodeError::Context(Box::new(Context {
+        error,
+        pc,
+        relative_base,
+        steps,
+        instruction,
+        opcode: decoded.map(|i| i % 100),
+        modes: decoded.map(|i| [i / 100 % 10, i / 1000 % 10, i / 10000 % 10]),
+        window_start,
+        window: (window_start..window_end).filter_map(|a| memory.word(a).ok().cloned()).collect(),
+        location: source_map.and_then(|map| map.locate(pc)),
+    }))
 }
 
-struct Instruction<'a> {
+struct Instruction<'a, M: Memory + ?Sized> {
     instruction: i64,
     pc: usize,
-    relative_base: usize,
-    program: &'a mut [i64],
+    relative_base: i64,
+    isa: Isa,
+    memory: &'a mut M,
 }
 
-impl<'a> Instruction<'a> {
-    fn new(instruction: i64, pc: usize, relative_base: usize, program: &'a mut [i64]) -> Instruction {
-        return Instruction {
-            instruction: instruction,
-            pc: pc,
-            relative_base: relative_base,
-            program: program,
-        };
+impl<'a, M: Memory + ?Sized> Instruction<'a, M> {
+    fn new(instruction: i64, pc: usize, relative_base: i64, isa: Isa, memory: &'a mut M) -> Instruction<'a, M> {
+        Instruction {
+            instruction,
+            pc,
+            relative_base,
+            isa,
+            memory,
+        }
     }
 
     fn opcode(&self) -> i64 {
@@ -71,201 +781,506 @@ impl<'a> Instruction<'a> {
         (parameters % 10i64.pow(n + 1)) / 10i64.pow(n)
     }
 
+    fn supported_parameter_type(&self, n: u32) -> result::Result<i64, IntcodeError<M::Word>> {
+        let parameter_type = self.parameter_type(n);
+        match parameter_type {
+            0..=2 if !self.isa.supports_parameter_type(parameter_type) => Err(
+                IntcodeError::UnsupportedParameterType(self.pc, parameter_type, self.isa),
+            ),
+            _ => Ok(parameter_type),
+        }
+    }
+
     fn parameter_index(&self, n: u32) -> usize {
         self.pc + 1 + n as usize
     }
 
-    fn intcode_index(&self, i: i64) -> result::Result<usize, IntcodeError> {
-        match usize::try_from(i) {
-            Err(_) => Err(IntcodeError::NegativePosition(self.pc, self.opcode(), i)),
-            Ok(v) => Ok(v),
+    fn intcode_index(&self, i: &M::Word) -> result::Result<usize, IntcodeError<M::Word>> {
+        if *i < M::Word::from(0) {
+            return Err(IntcodeError::NegativePosition(self.pc, self.opcode(), i.clone()));
         }
+        // positions too large to address fall off the end of memory.
+        Ok(i.to_i64()
+            .and_then(|i| usize::try_from(i).ok())
+            .unwrap_or(usize::MAX))
     }
 
-    fn parameter(&self, n: u32) -> result::Result<i64, IntcodeError> {
-        let parameter_type = self.parameter_type(n);
+    fn word(&self, idx: usize) -> result::Result<&M::Word, IntcodeError<M::Word>> {
+        self.memory.word(idx)
+    }
+
+    fn relative_index(&self, n: u32) -> result::Result<usize, IntcodeError<M::Word>> {
+        let offset = self.word(self.parameter_index(n))?;
+        match offset.to_i64().and_then(|o| self.relative_base.checked_add(o)) {
+            Some(position) => self.intcode_index(&M::Word::from(position)),
+            None => Err(IntcodeError::InvalidRelativeBase(
+                self.pc,
+                self.relative_base,
+                offset.clone(),
+            )),
+        }
+    }
+
+    fn parameter(&mut self, n: u32) -> result::Result<M::Word, IntcodeError<M::Word>> {
+        let parameter_type = self.supported_parameter_type(n)?;
         match parameter_type {
             // position
             0 => {
-                let idx = self.intcode_index(self.program[self.parameter_index(n)])?;
-                Ok(self.program[idx])
+                let idx = self.intcode_index(self.word(self.parameter_index(n))?)?;
+                self.memory.load(idx)
             }
             // immediate
-            1 => Ok(self.program[self.parameter_index(n)]),
+            1 => Ok(self.word(self.parameter_index(n))?.clone()),
             // relative
             2 => {
-                let ridx = self.intcode_index(
-                    self.relative_base as i64 + self.program[self.parameter_index(n)]
-                )?;
-                Ok(self.program[ridx])
+                let ridx = self.relative_index(n)?;
+                self.memory.load(ridx)
             }
             _ => Err(IntcodeError::UnknownParameterType(self.pc, parameter_type)),
         }
     }
 
-    fn assign(&'a mut self, n: u32) -> result::Result<&'a mut i64, IntcodeError> {
-        let parameter_type = self.parameter_type(n);
-        match parameter_type {
+    // stores `value` through parameter `n`, returning the address
+    // written and the value it replaced, unless it went to a device.
+    fn store(&mut self, n: u32, value: M::Word) -> result::Result<Option<Write<M::Word>>, IntcodeError<M::Word>> {
+        let parameter_type = self.supported_parameter_type(n)?;
+        let idx = match parameter_type {
             // position
-            0 => {
-                let idx = self.intcode_index(self.program[self.parameter_index(n)])?;
-                Ok(&mut self.program[idx])
-            }
+            0 => self.intcode_index(self.word(self.parameter_index(n))?)?,
             // immediate not supported!
-            1 => Err(IntcodeError::InvalidParameterType(
-                self.pc,
-                parameter_type,
-                "assign",
-            )),
-            2 => {
-                let ridx = self.intcode_index(
-                    self.relative_base as i64 + self.program[self.parameter_index(n)]
-                )?;
-                Ok(&mut self.program[ridx])
+            1 => {
+                return Err(IntcodeError::InvalidParameterType(
+                    self.pc,
+                    parameter_type,
+                    "assign",
+                ))
             }
-            _ => Err(IntcodeError::UnknownParameterType(self.pc, parameter_type)),
-        }
+            2 => self.relative_index(n)?,
+            _ => return Err(IntcodeError::UnknownParameterType(self.pc, parameter_type)),
+        };
+        Ok(self.memory.store(idx, value)?.map(|old| (idx, old)))
     }
 }
 
-pub struct Machine {
+// how many times the program's length a machine has in memory beyond
+// the program itself.
+const ADDITIONAL_MEMORY: usize = 100;
+
+// the memory a machine has for a program of `len` words.
+fn memory_size(len: usize) -> usize {
+    len + len * ADDITIONAL_MEMORY
+}
+
+pub struct Machine<W: Word = i64> {
     pc: usize,
-    relative_base: usize,
+    relative_base: i64,
+    steps: usize,
+    halted: bool,
+    step_limit: Option<usize>,
+    isa: Isa,
+    overflow: Overflow,
     // a machine should own its program, so that it can be re-executed
     // with different inputs without lifetime management.
-    program: Vec<i64>,
+    program: Vec<W>,
+    // the program as loaded, so that reset can restore it.
+    image: Vec<W>,
+    // only kept while `recording`.
+    history: VecDeque<Undo<W>>,
+    recording: bool,
+    history_limit: Option<usize>,
+    devices: Vec<Mapping<W>>,
+    source_map: Option<Arc<SourceMap>>,
 }
 
-impl Machine {
-    pub fn new(program: &[i64]) -> Self {
+impl<W: Word> Machine<W> {
+    pub fn new(program: &[W]) -> Self {
+        Machine::with_isa(program, Isa::default())
+    }
+
+    pub fn with_isa(program: &[W], isa: Isa) -> Self {
+        let image = program.to_vec();
         let mut program = program.to_vec();
-        // additional memory
-        program.extend(vec![0; program.len() * 100]);
+        program.resize(memory_size(program.len()), W::from(0));
         Machine {
             pc: 0,
             relative_base: 0,
-            program: program,
+            steps: 0,
+            halted: false,
+            step_limit: None,
+            isa,
+            overflow: Overflow::default(),
+            program,
+            image,
+            history: VecDeque::new(),
+            recording: false,
+            history_limit: None,
+            devices: vec![],
+            source_map: None,
+        }
+    }
+
+    /// Restores the program as it was loaded and clears the
+    /// registers, reusing the memory that's already allocated.
+    pub fn reset(&mut self) {
+        let (loaded, additional) = self.program.split_at_mut(self.image.len());
+        loaded.clone_from_slice(&self.image);
+        for cell in additional {
+            *cell = W::from(0);
+        }
+        self.pc = 0;
+        self.relative_base = 0;
+        self.steps = 0;
+        self.halted = false;
+        self.history.clear();
+        for mapping in &mut self.devices {
+            mapping.device.reset();
+        }
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Stops execution with `IntcodeError::StepLimit` once `limit`
+    /// instructions have run, so that programs which never halt can
+    /// still be tested.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    pub fn step_limit(&self) -> Option<usize> {
+        self.step_limit
+    }
+
+    /// Relates the program's addresses to its source, so that errors
+    /// say where they happened.
+    pub fn set_source_map(&mut self, map: Option<Arc<SourceMap>>) {
+        self.source_map = map;
+    }
+
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map.as_deref()
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn has_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// The whole of memory, including the space past the end of the
+    /// program.
+    pub fn memory_len(&self) -> usize {
+        self.program.len()
+    }
+
+    pub fn read(&self, address: usize) -> Option<&W> {
+        self.program.get(address)
+    }
+
+    pub fn memory(&self, range: ops::Range<usize>) -> Option<&[W]> {
+        self.program.get(range)
+    }
+
+    /// Stores `value` at `address`.  This isn't an instruction, so
+    /// it can't be undone, and clears the undo log: undoing past it
+    /// would leave memory inconsistent.
+    pub fn write(&mut self, address: usize, value: W) -> result::Result<(), IntcodeError<W>> {
+        match self.program.get_mut(address) {
+            Some(cell) => {
+                *cell = value;
+                self.history.clear();
+                Ok(())
+            }
+            None => Err(IntcodeError::InvalidAddress(address)),
+        }
+    }
+
+    /// Moves execution to `pc`.  A halted machine resumes from there
+    /// the next time it's executed.
+    pub fn set_pc(&mut self, pc: usize) -> result::Result<(), IntcodeError<W>> {
+        if pc >= self.program.len() {
+            return Err(IntcodeError::InvalidAddress(pc));
+        }
+        self.pc = pc;
+        self.halted = false;
+        Ok(())
+    }
+
+    pub fn set_relative_base(&mut self, base: usize) -> result::Result<(), IntcodeError<W>> {
+        match i64::try_from(base) {
+            Ok(base) => {
+                self.relative_base = base;
+                Ok(())
+            }
+            Err(_) => Err(IntcodeError::InvalidAddress(base)),
+        }
+    }
+
+    /// Sets how many instructions the machine counts as having run,
+    /// which is what the step limit is checked against.  Clears the
+    /// undo log, whose entries each count one step.
+    pub fn set_steps(&mut self, steps: usize) {
+        self.steps = steps;
+        self.history.clear();
+    }
+
+    /// Maps `device` into memory from `address` on.  Fails if it would
+    /// run past the end of memory or overlap another device, or if the
+    /// machine is keeping an undo log.
+    pub fn attach<D>(&mut self, address: usize, device: D) -> result::Result<(), IntcodeError<W>>
+    where D: Device<W> + 'static {
+        if self.recording {
+            return Err(IntcodeError::DevicesAttached);
+        }
+        let end = address.saturating_add(device.size());
+        if end > self.program.len() {
+            return Err(IntcodeError::InvalidAddress(end - 1));
         }
+        if let Some(other) = self.devices.iter().find(|m| m.start < end && address < m.end) {
+            return Err(IntcodeError::InvalidAddress(other.start.max(address)));
+        }
+        self.devices.push(Mapping {
+            start: address,
+            end,
+            device: Box::new(device),
+        });
+        Ok(())
+    }
+
+    /// Runs until the program produces output, halts, needs input or
+    /// fails.  Failures carry the machine's state as their context.
+    pub fn execute<'a, I>(&mut self, input: I) -> ExitResult<W>
+    where I: iter::Iterator<Item = &'a W> {
+        self.run(input).map_err(|error| self.annotate(error))
     }
 
-    fn consume_parameters(&mut self, parameters: usize) {
-        self.pc += 1 + parameters;
+    /// Wraps `error` in the machine's current state, unless it already
+    /// has context.
+    pub fn annotate(&self, error: IntcodeError<W>) -> IntcodeError<W> {
+        annotate(
+            error,
+            self.pc,
+            self.relative_base,
+            self.steps,
+            &self.program[..],
+            self.source_map.as_deref(),
+        )
     }
 
-    pub fn execute<'a, I>(&mut self, mut input: I) -> result::Result<Option<i64>, IntcodeError>
-    where I: iter::Iterator<Item = &'a i64> {
+    /// Runs a single instruction.  Returns `None` if the program
+    /// carries on afterwards.
+    pub fn step<'a, I>(&mut self, mut input: I) -> result::Result<Option<Exit<W>>, IntcodeError<W>>
+    where I: iter::Iterator<Item = &'a W> {
+        self.advance(&mut input).map_err(|error| self.annotate(error))
+    }
+
+    fn run<'a, I>(&mut self, mut input: I) -> ExitResult<W>
+    where I: iter::Iterator<Item = &'a W> {
         loop {
-            let mut instruction = Instruction::new(
-                self.program[self.pc],
-                self.pc,
-                self.relative_base,
-                &mut self.program,
-            );
-            match instruction.opcode() {
-                // add
-                1 => {
-                    let res = instruction.parameter(0)? + instruction.parameter(1)?;
-                    *instruction.assign(2)? = res;
-                    self.consume_parameters(3);
-                }
-                // multiply
-                2 => {
-                    *instruction.assign(2)? =
-                        instruction.parameter(0)? * instruction.parameter(1)?;
-                    self.consume_parameters(3);
-                }
-                // input
-                3 => {
-                    let input = match input.next() {
-                        Some(input) => *input,
-                        None => return Err(IntcodeError::MissingInput(self.pc)),
-                    };
-                    *instruction.assign(0)? = input;
-                    self.consume_parameters(1);
-                }
-                // output
-                4 => {
-                    let output = instruction.parameter(0)?;
-                    self.consume_parameters(1);
-                    return Ok(Some(output));
-                }
-                // jump-if-true
-                5 => {
-                    if instruction.parameter(0)? != 0 {
-                        self.pc = instruction.intcode_index(instruction.parameter(1)?)?;
-                    } else {
-                        self.consume_parameters(2);
-                    }
-                }
-                // jump-if-false
-                6 => {
-                    if instruction.parameter(0)? == 0 {
-                        self.pc = instruction.intcode_index(instruction.parameter(1)?)?;
-                    } else {
-                        self.consume_parameters(2);
-                    }
-                }
-                // less than
-                7 => {
-                    *instruction.assign(2)? =
-                        if instruction.parameter(0)? < instruction.parameter(1)? {
-                            1
-                        } else {
-                            0
-                        };
-                    self.consume_parameters(3);
-                }
-                // change the relative base
-                9 => {
-                    let offset = instruction.parameter(0)?;
-                    self.relative_base = (self.relative_base as i64 + offset) as usize;
-                    self.consume_parameters(1);
-                }
-                // equals
-                8 => {
-                    *instruction.assign(2)? =
-                        if instruction.parameter(0)? == instruction.parameter(1)? {
-                            1
-                        } else {
-                            0
-                        };
-                    self.consume_parameters(3);
-                }
-                99 => return Ok(None),
-                _ => return Err(IntcodeError::UnknownOpcode(self.pc, instruction.opcode())),
+            if let Some(exit) = self.advance(&mut input)? {
+                return Ok(exit);
+            }
+        }
+    }
+
+    fn advance<'a, I>(&mut self, input: &mut I) -> result::Result<Option<Exit<W>>, IntcodeError<W>>
+    where I: iter::Iterator<Item = &'a W> {
+        if let Some(limit) = self.step_limit {
+            if self.steps >= limit {
+                return Err(IntcodeError::StepLimit(self.pc, limit));
+            }
+        }
+        let instruction = decode(self.program.word(self.pc)?, self.pc, self.isa)?;
+        let (pc, relative_base) = (self.pc, self.relative_base);
+        let mut memory = Mapped {
+            program: &mut self.program,
+            devices: &mut self.devices,
+        };
+        let effect = run_instruction(
+            instruction,
+            &mut self.pc,
+            &mut self.relative_base,
+            self.isa,
+            self.overflow,
+            &mut memory,
+            input,
+        )?;
+        let (write, consumed, output) = match effect {
+            Effect::Ran { write, input, output } => (write, input, output),
+            Effect::AwaitingInput => return Ok(Some(Exit::AwaitingInput { pc: self.pc })),
+            Effect::Halted => {
+                self.halted = true;
+                return Ok(Some(Exit::Halted {
+                    pc: self.pc,
+                    steps: self.steps,
+                }));
+            }
+        };
+        self.steps += 1;
+        for mapping in &mut self.devices {
+            mapping.device.tick();
+        }
+        if self.recording {
+            if self.history_limit == Some(self.history.len()) {
+                self.history.pop_front();
             }
+            if self.history_limit != Some(0) {
+                self.history.push_back(Undo {
+                    pc,
+                    relative_base,
+                    write,
+                    input: consumed,
+                    output: output.clone(),
+                });
+            }
+        }
+        Ok(output.map(Exit::Output))
+    }
+
+    /// Keeps an undo log of every instruction executed from now on,
+    /// so that the machine can be stepped backwards with `undo`.
+    /// Turning it off discards the log.  A machine with devices
+    /// attached can't keep one.
+    pub fn set_history(&mut self, enabled: bool) -> result::Result<(), IntcodeError<W>> {
+        if enabled && !self.devices.is_empty() {
+            return Err(IntcodeError::DevicesAttached);
+        }
+        if !enabled {
+            self.history.clear();
+        }
+        self.recording = enabled;
+        Ok(())
+    }
+
+    /// Keeps at most `limit` entries in the undo log, forgetting the
+    /// oldest instructions first; `None` lets it grow without bound.
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        if let Some(limit) = limit {
+            let excess = self.history.len().saturating_sub(limit);
+            self.history.drain(..excess);
+        }
+        self.history_limit = limit;
+    }
+
+    /// The undo log, oldest instruction first.
+    pub fn history(&self) -> &VecDeque<Undo<W>> {
+        &self.history
+    }
+
+    /// Empties the undo log, returning what it held.  The machine goes
+    /// on keeping a log if it was.
+    pub fn take_history(&mut self) -> Vec<Undo<W>> {
+        mem::take(&mut self.history).into()
+    }
+
+    /// Reverts the most recently executed instruction, returning its
+    /// undo entry, or `None` if there's nothing left to undo.
+    pub fn undo(&mut self) -> Option<Undo<W>> {
+        let undo = self.history.pop_back()?;
+        if let Some((address, old)) = &undo.write {
+            self.program[*address] = old.clone();
         }
+        self.pc = undo.pc;
+        self.relative_base = undo.relative_base;
+        self.steps = self.steps.saturating_sub(1);
+        self.halted = false;
+        Some(undo)
     }
 }
 
+/// What it takes to reverse one executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Undo<W = i64> {
+    /// the pc and relative base before the instruction ran.
+    pub pc: usize,
+    pub relative_base: i64,
+    /// the address the instruction wrote to and the value it held
+    /// beforehand.
+    pub write: Option<(usize, W)>,
+    /// the input the instruction consumed.
+    pub input: Option<W>,
+    /// the output the instruction produced.
+    pub output: Option<W>,
+}
+
 pub fn parse_program(input: &str) -> Vec<i64> {
+    parse_program_as(input)
+}
+
+/// Like `parse_program`, for machines with words other than i64.
+pub fn parse_program_as<W: Word>(input: &str) -> Vec<W> {
+    match try_parse_program(input) {
+        Ok(program) => program,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+pub fn try_parse_program<W: Word>(input: &str) -> result::Result<Vec<W>, FileError> {
     input
-        .split(",")
-        .map(|i| i.parse::<i64>().unwrap())
+        .trim_end()
+        .split(',')
+        .enumerate()
+        .map(|(index, word)| {
+            W::parse_word(word).map_err(|e| FileError::Word(index, word.to_string(), e))
+        })
         .collect()
 }
 
-pub fn execute<'a>(program: &'a mut [i64]) -> AllOutputResult {
+pub fn read_program<W: Word, P: AsRef<Path>>(path: P) -> result::Result<Vec<W>, FileError> {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(text) => try_parse_program(&text),
+        Err(e) => Err(FileError::Io(path.to_path_buf(), e)),
+    }
+}
+
+pub fn execute<W: Word>(program: &mut [W]) -> AllOutputResult<W> {
     execute_with_input(program, &[])
 }
 
-pub fn execute_with_input<'a>(program: &'a mut [i64], input: &'a [i64]) -> AllOutputResult {
+pub fn execute_with_input<W: Word>(program: &mut [W], input: &[W]) -> AllOutputResult<W> {
+    execute_with_isa(program, input, Isa::default())
+}
+
+pub fn execute_with_isa<W: Word>(program: &mut [W], input: &[W], isa: Isa) -> AllOutputResult<W> {
     let mut input = input.iter();
-    let mut machine = Machine::new(program);
+    let mut machine = Machine::with_isa(program, isa);
     let mut output = vec![];
-    loop {
-        match machine.execute(&mut input)? {
-            Some(o) => output.push(o),
-            None => {
-                // copy the program back into the slice so tests can
-                // inspect it.
-                program.copy_from_slice(&machine.program[..program.len()]);
-                return Ok(output)
-            },
+    let error = loop {
+        match machine.execute(&mut input) {
+            Ok(Exit::Output(o)) => output.push(o),
+            Ok(Exit::Halted { .. }) => break None,
+            Ok(Exit::AwaitingInput { pc }) => {
+                break Some(machine.annotate(IntcodeError::MissingInput(pc)))
+            }
+            Err(e) => break Some(e),
         }
+    };
+    // copy the program back into the slice so tests can inspect it,
+    // even if the program failed partway through.
+    program.clone_from_slice(&machine.program[..program.len()]);
+    match error {
+        None => Ok(output),
+        Some(error) => Err(Failure {
+            output,
+            machine: Box::new(machine),
+            error,
+        }),
     }
 }
 
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -275,4 +1290,392 @@ mod tests {
         assert_eq!(parse_program("1,0,0,0,99"), vec![1, 0, 0, 0, 99])
     }
 
+    #[test]
+    fn test_i128_words() {
+        let mut program: Vec<i128> = parse_program_as("1102,34915192000,34915192000,7,4,7,99,0");
+        let output = execute(&mut program).expect("execute");
+        assert_eq!(output, vec![34915192000 * 34915192000]);
+
+        let mut program: Vec<i128> = vec![1102, i64::MAX as i128, i64::MAX as i128, 7, 4, 7, 99, 0];
+        let output = execute(&mut program).expect("execute");
+        assert_eq!(output, vec![i64::MAX as i128 * i64::MAX as i128]);
+    }
+
+    #[test]
+    fn test_ibig_words() {
+        let mut program: Vec<IBig> = parse_program_as("1102,34915192000,34915192000,11,2,11,11,11,4,11,99,0");
+        let output = execute(&mut program).expect("execute");
+        let square = IBig::from(34915192000i64) * IBig::from(34915192000i64);
+        assert_eq!(output, vec![&square * &square]);
+    }
+
+    #[test]
+    fn test_i64_overflow_fits_wider_words() {
+        let program = "1102,9223372036854775807,2,7,4,7,99,0";
+        match execute(&mut parse_program(program)).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::ArithmeticOverflow(0, 2, i64::MAX, 2)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let output = execute(&mut parse_program_as::<i128>(program)).expect("execute");
+        assert_eq!(output, vec![i64::MAX as i128 * 2]);
+    }
+
+    #[test]
+    fn test_word_too_wide_for_instruction() {
+        let mut program: Vec<i128> = vec![i64::MAX as i128 + 1, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidInstruction(0, i)) => assert_eq!(i, i64::MAX as i128 + 1),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_access() {
+        // memory is the program and 100 times as much again.
+        let mut program: Vec<i64> = vec![4, 303, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidAddress(303)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut program: Vec<i64> = vec![1105, 1, 1000];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidAddress(1000)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut machine: Machine = Machine::new(&[1105, 1, 0]);
+        machine.set_step_limit(Some(10));
+        match machine.execute([].iter()).map_err(IntcodeError::into_kind) {
+            Err(IntcodeError::StepLimit(0, 10)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(machine.steps(), 10);
+    }
+
+    #[test]
+    fn test_machines_and_errors_are_send() {
+        fn send_and_sync<T: Send + Sync>() {}
+        send_and_sync::<Machine>();
+        send_and_sync::<Failure>();
+        send_and_sync::<IntcodeError>();
+    }
+
+    #[test]
+    fn test_exit_halted() {
+        let mut machine: Machine = Machine::new(&[1101, 1, 2, 5, 99, 0]);
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Halted { pc: 4, steps: 1 });
+        // a halted machine stays halted.
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Halted { pc: 4, steps: 1 });
+    }
+
+    #[test]
+    fn test_exit_awaiting_input() {
+        let mut machine: Machine = Machine::new(&[3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::AwaitingInput { pc: 0 });
+        assert_eq!(machine.execute([41].iter()).expect("execute"), Exit::Output(42));
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Halted { pc: 8, steps: 3 });
+    }
+
+    #[test]
+    fn test_failure_keeps_partial_output() {
+        let mut program: Vec<i64> = vec![104, 7, 1101, 2, 3, 12, 4, 12, 3, 12, 99, 0, 0];
+        let failure = execute(&mut program).expect_err("missing input");
+        assert_eq!(failure.output, vec![7, 5]);
+        match failure.error.kind() {
+            IntcodeError::MissingInput(8) => (),
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(failure.machine.pc(), 8);
+        // memory is copied back even though the run failed.
+        assert_eq!(program[12], 5);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let mut machine: Machine = Machine::new(&[109, 7, 21101, 2, 3, 0, 99]);
+        assert_eq!(machine.memory_len(), 707);
+        assert!(!machine.has_halted());
+        machine.execute([].iter()).expect("execute");
+        assert!(machine.has_halted());
+        assert_eq!(machine.pc(), 6);
+        assert_eq!(machine.relative_base(), 7);
+        assert_eq!(machine.steps(), 2);
+        assert_eq!(machine.read(7), Some(&5));
+        assert_eq!(machine.memory(5..8), Some(&[0, 99, 5][..]));
+        assert_eq!(machine.read(707), None);
+        assert_eq!(machine.memory(700..708), None);
+    }
+
+    #[test]
+    fn test_poke() {
+        let mut machine: Machine = Machine::new(&[1, 0, 0, 0, 99]);
+        machine.write(1, 4).expect("write");
+        machine.write(2, 4).expect("write");
+        machine.execute([].iter()).expect("execute");
+        assert_eq!(machine.read(0), Some(&198));
+
+        // resume a halted machine from the start with a new base.
+        machine.set_pc(0).expect("set_pc");
+        assert!(!machine.has_halted());
+        machine.set_relative_base(3).expect("set_relative_base");
+        assert_eq!(machine.relative_base(), 3);
+
+        match machine.write(505, 1) {
+            Err(IntcodeError::InvalidAddress(505)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match machine.set_pc(505) {
+            Err(IntcodeError::InvalidAddress(505)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match machine.set_relative_base(usize::MAX) {
+            Err(IntcodeError::InvalidAddress(usize::MAX)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // the step count is what the limit checks.
+        machine.set_step_limit(Some(3));
+        assert_eq!(machine.step_limit(), Some(3));
+        machine.set_steps(3);
+        match machine.execute([].iter()).map_err(|e| e.into_kind()) {
+            Err(IntcodeError::StepLimit(0, 3)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pokes_clear_history() {
+        let mut machine: Machine = Machine::new(&[1101, 2, 3, 5, 1101, 1, 1, 6, 99]);
+        machine.set_history(true).expect("history");
+        assert_eq!(machine.step([].iter()).expect("step"), None);
+        machine.set_steps(0);
+        assert!(machine.undo().is_none());
+        assert_eq!(machine.steps(), 0);
+
+        assert_eq!(machine.step([].iter()).expect("step"), None);
+        machine.write(5, 9).expect("write");
+        assert!(machine.history().is_empty());
+        assert!(machine.undo().is_none());
+        assert_eq!(machine.read(5), Some(&9));
+    }
+
+    #[test]
+    fn test_take_history() {
+        let mut machine: Machine = Machine::new(&[1101, 2, 3, 5, 99, 0]);
+        assert!(machine.take_history().is_empty());
+        machine.set_history(true).expect("history");
+        machine.execute([].iter()).expect("execute");
+        let history = machine.take_history();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].write, Some((5, 0)));
+        assert!(machine.history().is_empty());
+        machine.reset();
+        machine.execute([].iter()).expect("execute");
+        assert_eq!(machine.history().len(), 1);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut machine: Machine = Machine::new(&[3, 0, 109, 3, 21101, 2, 3, 0, 4, 0, 99]);
+        assert_eq!(machine.execute([9].iter()).expect("execute"), Exit::Output(9));
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Halted { pc: 10, steps: 4 });
+        assert_eq!(machine.read(3), Some(&5));
+
+        machine.reset();
+        assert_eq!(machine.pc(), 0);
+        assert_eq!(machine.relative_base(), 0);
+        assert_eq!(machine.steps(), 0);
+        assert!(!machine.has_halted());
+        assert_eq!(machine.memory(0..11), Some(&[3, 0, 109, 3, 21101, 2, 3, 0, 4, 0, 99][..]));
+        assert_eq!(machine.execute([8].iter()).expect("execute"), Exit::Output(8));
+    }
+
+    #[test]
+    fn test_pool_reuses_machines() {
+        let mut pool: Pool = Pool::new(&[1101, 1, 1, 100, 99], Isa::Day5);
+        let mut machine = pool.take();
+        machine.execute([].iter()).expect("execute");
+        assert_eq!(machine.read(100), Some(&2));
+        let memory = machine.memory(0..1).expect("memory").as_ptr();
+        pool.give(machine);
+
+        let machine = pool.take();
+        assert_eq!(machine.memory(0..1).expect("memory").as_ptr(), memory);
+        assert_eq!(machine.read(100), Some(&0));
+        assert!(!machine.has_halted());
+    }
+
+    #[test]
+    fn test_isa_rejects_later_opcodes() {
+        let mut program: Vec<i64> = vec![3, 0, 99];
+        match execute_with_isa(&mut program, &[1], Isa::Day2).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnsupportedOpcode(0, 3, Isa::Day2)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut program: Vec<i64> = vec![109, 1, 99];
+        match execute_with_isa(&mut program, &[], Isa::Day5).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnsupportedOpcode(0, 9, Isa::Day5)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_isa_rejects_later_parameter_types() {
+        let mut program: Vec<i64> = vec![1101, 1, 1, 0, 99];
+        match execute_with_isa(&mut program, &[], Isa::Day2).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnsupportedParameterType(0, 1, Isa::Day2)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut program: Vec<i64> = vec![1201, 0, 0, 0, 99];
+        match execute_with_isa(&mut program, &[], Isa::Day5).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnsupportedParameterType(0, 2, Isa::Day5)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // the jump isn't taken, so its target is never read.
+        let mut program: Vec<i64> = vec![2106, 1, 0, 99];
+        match execute_with_isa(&mut program, &[], Isa::Day5).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnsupportedParameterType(0, 2, Isa::Day5)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut program: Vec<i64> = vec![3106, 1, 0, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnknownParameterType(0, 3)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_negative_relative_base() {
+        let mut program: Vec<i64> = vec![109, 5, 109, -6, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidRelativeBase(2, 5, -6)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_relative_base_overflow() {
+        let mut program: Vec<i64> = vec![109, i64::MAX, 109, 1, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidRelativeBase(2, i64::MAX, 1)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut program: Vec<i64> = vec![109, i64::MAX, 204, 1, 99];
+        match execute(&mut program).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::InvalidRelativeBase(2, i64::MAX, 1)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn run_with_overflow(program: &[i64], overflow: Overflow) -> ExitResult {
+        let mut machine = Machine::new(program);
+        machine.set_overflow(overflow);
+        machine.execute([].iter()).map_err(IntcodeError::into_kind)
+    }
+
+    #[test]
+    fn test_checked_overflow() {
+        match run_with_overflow(&[1101, i64::MAX, 1, 0, 99], Overflow::Checked) {
+            Err(IntcodeError::ArithmeticOverflow(0, 1, i64::MAX, 1)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match run_with_overflow(&[1102, i64::MIN, -1, 0, 99], Overflow::Checked) {
+            Err(IntcodeError::ArithmeticOverflow(0, 2, i64::MIN, -1)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wrapping_overflow() {
+        let output = run_with_overflow(&[1101, i64::MAX, 1, 7, 4, 7, 99, 0], Overflow::Wrapping)
+            .expect("execute");
+        assert_eq!(output, Exit::Output(i64::MIN));
+        let output = run_with_overflow(&[1102, i64::MAX, 2, 7, 4, 7, 99, 0], Overflow::Wrapping)
+            .expect("execute");
+        assert_eq!(output, Exit::Output(-2));
+    }
+
+    #[test]
+    fn test_saturating_overflow() {
+        let output = run_with_overflow(&[1101, i64::MAX, 1, 7, 4, 7, 99, 0], Overflow::Saturating)
+            .expect("execute");
+        assert_eq!(output, Exit::Output(i64::MAX));
+        let output = run_with_overflow(&[1102, i64::MIN, 2, 7, 4, 7, 99, 0], Overflow::Saturating)
+            .expect("execute");
+        assert_eq!(output, Exit::Output(i64::MIN));
+    }
+
+    #[test]
+    fn test_isa_unknown_opcode_is_still_unknown() {
+        let mut program: Vec<i64> = vec![42, 99];
+        match execute_with_isa(&mut program, &[], Isa::Day2).map_err(|f| f.error.into_kind()) {
+            Err(IntcodeError::UnknownOpcode(0, 42)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_context() {
+        let mut program: Vec<i64> = vec![104, 5, 1101, 1, 2, 12, 1002, 12, 2, 12, 42, 99, 0];
+        let failure = execute(&mut program).expect_err("unknown opcode");
+        let context = failure.error.context().expect("context");
+        match context.error {
+            IntcodeError::UnknownOpcode(10, 42) => (),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(context.pc, 10);
+        assert_eq!(context.steps, 3);
+        assert_eq!(context.instruction, Some(42));
+        assert_eq!(context.opcode, Some(42));
+        assert_eq!(context.modes, Some([0, 0, 0]));
+        assert_eq!(context.window_start, 6);
+        assert_eq!(context.window, vec![1002, 12, 2, 12, 42, 99, 6, 0, 0]);
+        assert_eq!(
+            failure.error.to_string(),
+            "pc: 10, unknown opcode 42; instruction 42 (opcode 42, modes 0 0 0) \
+             at step 3, relative base 0; memory from 6: 1002 12 2 12 [42] 99 6 0 0"
+        );
+        assert!(error::Error::source(&failure.error).is_none());
+    }
+
+    #[test]
+    fn test_error_context_modes() {
+        let mut program: Vec<i64> = vec![21101, 1, 1, 0, 99];
+        let failure = execute_with_isa(&mut program, &[], Isa::Day5).expect_err("relative mode");
+        let context = failure.error.context().expect("context");
+        assert_eq!(context.opcode, Some(1));
+        assert_eq!(context.modes, Some([1, 1, 2]));
+        assert_eq!(context.window_start, 0);
+        match failure.error.into_kind() {
+            IntcodeError::UnsupportedParameterType(0, 2, Isa::Day5) => (),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_source() {
+        assert_eq!(try_parse_program::<i64>("1,0,0,0,99\n").expect("parse"), vec![1, 0, 0, 0, 99]);
+        let error = try_parse_program::<i64>("1,x,99").expect_err("invalid word");
+        match error {
+            FileError::Word(1, ref word, _) if word == "x" => (),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        let source = error::Error::source(&error).expect("source");
+        assert_eq!(source.to_string(), "x".parse::<i64>().unwrap_err().to_string());
+        assert!(try_parse_program::<IBig>("1,2.5").is_err());
+    }
+
+    #[test]
+    fn test_io_error_source() {
+        let error = read_program::<i64, _>("/nonexistent/intcode/program").expect_err("no such file");
+        match error {
+            FileError::Io(_, ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+        let source = error::Error::source(&error).expect("source");
+        assert!(source.downcast_ref::<io::Error>().is_some());
+    }
 }
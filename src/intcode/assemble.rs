@@ -0,0 +1,424 @@
+//! Assembles intcode from source text.
+//!
+//! The syntax is the disassembler's: one instruction per line, with
+//! operands written `5` for immediate mode, `[5]` for position mode
+//! and `[rb+5]` or `[rb-5]` for relative mode.  `data` lays out words
+//! as they are, and `name:` labels the address of whatever follows
+//! it.  Anywhere a number can go, a label can too, optionally plus or
+//! minus a number.  `;` starts a comment.  `export` lines name the
+//! labels that other modules can use when the source is assembled
+//! as a module for linking; a program assembled on its own can use
+//! all of its labels anyway.
+//!
+//! ```text
+//! loop:   in [n]
+//!         jz [n], done
+//!         out [n]
+//!         jnz 1, loop
+//! done:   halt
+//! n:      data 0
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::result;
+
+use super::{FileError, Word};
+
+/// An assembled program, along with what's needed to relate its
+/// addresses back to the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly<W = i64> {
+    pub program: Vec<W>,
+    /// labels and the addresses they name.
+    pub symbols: BTreeMap<String, usize>,
+    /// the source line, counting from 1, of each instruction and
+    /// `data` directive, by the address it starts at.
+    pub lines: BTreeMap<usize, usize>,
+}
+
+impl<W> Assembly<W> {
+    /// The source line that assembled into `address`.
+    pub fn line(&self, address: usize) -> Option<usize> {
+        if address >= self.program.len() {
+            return None;
+        }
+        self.lines.range(..=address).next_back().map(|(_, line)| *line)
+    }
+
+    /// The first address assembled from `line` or any line after it.
+    pub fn address(&self, line: usize) -> Option<usize> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| **l >= line)
+            .min_by_key(|(address, l)| (**l, **address))
+            .map(|(address, _)| *address)
+    }
+
+    /// The closest label at or before `address`, and how far past it
+    /// `address` is.
+    pub fn symbol(&self, address: usize) -> Option<(&str, usize)> {
+        self.symbols
+            .iter()
+            .filter(|(_, a)| **a <= address)
+            .max_by_key(|(_, a)| **a)
+            .map(|(name, a)| (name.as_str(), address - a))
+    }
+}
+
+pub(super) fn opcode(mnemonic: &str) -> Option<(i64, usize)> {
+    Some(match mnemonic {
+        "add" => (1, 3),
+        "mul" => (2, 3),
+        "in" => (3, 1),
+        "out" => (4, 1),
+        "jnz" => (5, 2),
+        "jz" => (6, 2),
+        "lt" => (7, 3),
+        "eq" => (8, 3),
+        "arb" => (9, 1),
+        "halt" => (99, 0),
+        _ => return None,
+    })
+}
+
+// the parameter an opcode writes through.
+pub(super) fn destination(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(2),
+        3 => Some(0),
+        _ => None,
+    }
+}
+
+/// A number, or a label plus or minus a number.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Expression {
+    pub(super) label: Option<String>,
+    pub(super) offset: i64,
+}
+
+pub(super) fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn number(text: &str) -> result::Result<i64, String> {
+    text.trim().parse().map_err(|_| format!("expected a number, got {:?}", text.trim()))
+}
+
+pub(super) fn expression(text: &str) -> result::Result<Expression, String> {
+    let text = text.trim();
+    if let Ok(offset) = text.parse() {
+        return Ok(Expression { label: None, offset });
+    }
+    let (label, offset) = match text.find(['+', '-']) {
+        Some(i) if text[i..].starts_with('+') => (&text[..i], number(&text[i + 1..])?),
+        Some(i) => (&text[..i], -number(&text[i + 1..])?),
+        None => (text, 0),
+    };
+    let label = label.trim();
+    if !is_label(label) {
+        return Err(format!("expected a number or label, got {:?}", text));
+    }
+    Ok(Expression {
+        label: Some(label.to_string()),
+        offset,
+    })
+}
+
+/// An operand and the mode it's used in.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Operand {
+    pub(super) mode: i64,
+    pub(super) value: Expression,
+}
+
+pub(super) fn operand(text: &str) -> result::Result<Operand, String> {
+    let text = text.trim();
+    let inner = match text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Some(inner) => inner.trim(),
+        None => {
+            return Ok(Operand {
+                mode: 1,
+                value: expression(text)?,
+            })
+        }
+    };
+    let relative = match inner.strip_prefix("rb") {
+        Some("") => Some(Expression { label: None, offset: 0 }),
+        Some(rest) if rest.trim_start().starts_with('+') => Some(expression(&rest.trim_start()[1..])?),
+        Some(rest) if rest.trim_start().starts_with('-') => {
+            let mut offset = expression(&rest.trim_start()[1..])?;
+            if offset.label.is_some() {
+                return Err(format!("can't negate a label in {:?}", text));
+            }
+            offset.offset = -offset.offset;
+            Some(offset)
+        }
+        _ => None,
+    };
+    Ok(match relative {
+        Some(value) => Operand { mode: 2, value },
+        None => Operand {
+            mode: 0,
+            value: expression(inner)?,
+        },
+    })
+}
+
+/// One line of source, with its label split off.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Statement {
+    Instruction(i64, Vec<Operand>),
+    Data(Vec<Expression>),
+}
+
+impl Statement {
+    pub(super) fn len(&self) -> usize {
+        match self {
+            Statement::Instruction(_, operands) => 1 + operands.len(),
+            Statement::Data(words) => words.len(),
+        }
+    }
+}
+
+// splits a comma separated list.
+fn operands(text: &str) -> Vec<&str> {
+    if text.trim().is_empty() {
+        return vec![];
+    }
+    text.split(',').collect()
+}
+
+/// Parses one line into its labels and statement.
+pub(super) fn statement(line: &str) -> result::Result<(Vec<String>, Option<Statement>), String> {
+    let mut rest = match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+    .trim();
+    let mut labels = vec![];
+    while let Some(colon) = rest.find(':') {
+        let label = rest[..colon].trim();
+        if !is_label(label) {
+            return Err(format!("invalid label {:?}", label));
+        }
+        labels.push(label.to_string());
+        rest = rest[colon + 1..].trim();
+    }
+    if rest.is_empty() {
+        return Ok((labels, None));
+    }
+    let (mnemonic, arguments) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    if mnemonic == "data" {
+        let words = operands(arguments).into_iter().map(expression).collect::<result::Result<_, _>>()?;
+        return Ok((labels, Some(Statement::Data(words))));
+    }
+    let (opcode, count) = opcode(mnemonic).ok_or_else(|| format!("unknown instruction {:?}", mnemonic))?;
+    let operands: Vec<Operand> = operands(arguments).into_iter().map(operand).collect::<result::Result<_, _>>()?;
+    if operands.len() != count {
+        return Err(format!("{} takes {} operands, got {}", mnemonic, count, operands.len()));
+    }
+    if let Some(n) = destination(opcode) {
+        if operands[n].mode == 1 {
+            return Err(format!("{} can't write to an immediate operand", mnemonic));
+        }
+    }
+    Ok((labels, Some(Statement::Instruction(opcode, operands))))
+}
+
+// encodes a statement whose expressions have been resolved to
+// `values`.
+pub(super) fn encode<W: Word>(statement: &Statement, values: &[i64]) -> Vec<W> {
+    match statement {
+        Statement::Instruction(opcode, operands) => {
+            let modes = operands
+                .iter()
+                .enumerate()
+                .map(|(n, o)| o.mode * 10i64.pow(n as u32 + 2))
+                .sum::<i64>();
+            let mut words = vec![W::from(opcode + modes)];
+            words.extend(values.iter().map(|v| W::from(*v)));
+            words
+        }
+        Statement::Data(_) => values.iter().map(|v| W::from(*v)).collect(),
+    }
+}
+
+pub(super) fn expressions(statement: &Statement) -> Vec<&Expression> {
+    match statement {
+        Statement::Instruction(_, operands) => operands.iter().map(|o| &o.value).collect(),
+        Statement::Data(words) => words.iter().collect(),
+    }
+}
+
+// the labels an `export` line names, if that's what `line` is.
+fn exports(line: &str) -> Option<result::Result<Vec<String>, String>> {
+    let rest = match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+    .trim();
+    let names = rest.strip_prefix("export")?;
+    if !names.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(
+        operands(names)
+            .into_iter()
+            .map(|name| match name.trim() {
+                name if is_label(name) => Ok(name.to_string()),
+                name => Err(format!("invalid label {:?}", name)),
+            })
+            .collect(),
+    )
+}
+
+/// The statements of some source, with where each came from, and the
+/// labels it defines.
+pub(super) struct Parsed<'a> {
+    pub(super) symbols: BTreeMap<String, usize>,
+    /// the labels named by `export` lines.
+    pub(super) exports: BTreeSet<String>,
+    pub(super) lines: BTreeMap<usize, usize>,
+    /// each statement with its line number and text.
+    pub(super) statements: Vec<(usize, &'a str, Statement)>,
+    pub(super) len: usize,
+}
+
+pub(super) fn error(number: usize, line: &str, reason: String) -> FileError {
+    FileError::Line(number, line.to_string(), reason.into())
+}
+
+// lays out `source`, giving each label its address.
+pub(super) fn parse(source: &str) -> result::Result<Parsed<'_>, FileError> {
+    let mut parsed = Parsed {
+        symbols: BTreeMap::new(),
+        exports: BTreeSet::new(),
+        lines: BTreeMap::new(),
+        statements: vec![],
+        len: 0,
+    };
+    // where each export was, to say if its label isn't defined.
+    let mut exported = vec![];
+    for (i, line) in source.lines().enumerate() {
+        if let Some(names) = exports(line) {
+            for name in names.map_err(|e| error(i + 1, line, e))? {
+                exported.push((i + 1, line, name.clone()));
+                parsed.exports.insert(name);
+            }
+            continue;
+        }
+        let (labels, statement) = statement(line).map_err(|e| error(i + 1, line, e))?;
+        for label in labels {
+            if parsed.symbols.insert(label.clone(), parsed.len).is_some() {
+                return Err(error(i + 1, line, format!("{} is already defined", label)));
+            }
+        }
+        if let Some(statement) = statement {
+            parsed.lines.insert(parsed.len, i + 1);
+            parsed.len += statement.len();
+            parsed.statements.push((i + 1, line, statement));
+        }
+    }
+    for (number, line, name) in exported {
+        if !parsed.symbols.contains_key(&name) {
+            return Err(error(number, line, format!("{} isn't defined", name)));
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn assemble<W: Word>(source: &str) -> result::Result<Assembly<W>, FileError> {
+    let Parsed {
+        symbols,
+        lines,
+        statements,
+        len,
+        ..
+    } = parse(source)?;
+    let mut program = Vec::with_capacity(len);
+    for (number, line, statement) in &statements {
+        let values = expressions(statement)
+            .into_iter()
+            .map(|e| match &e.label {
+                None => Ok(e.offset),
+                Some(label) => match symbols.get(label) {
+                    Some(address) => Ok(*address as i64 + e.offset),
+                    None => Err(error(*number, line, format!("{} isn't defined", label))),
+                },
+            })
+            .collect::<result::Result<Vec<i64>, _>>()?;
+        program.extend(encode(statement, &values));
+    }
+    Ok(Assembly { program, symbols, lines })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disassemble::disassemble;
+    use crate::intcode::{execute_with_input, parse_program};
+
+    const ECHO: &str = "\
+; echoes its input until it reads a zero.
+loop:   in [n]
+        jz [n], done
+        out [n]
+        jnz 1, loop
+
+done:   halt
+n:      data 0
+";
+
+    #[test]
+    fn test_assemble() {
+        let assembly = assemble::<i64>(ECHO).expect("assemble");
+        assert_eq!(assembly.program, vec![3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0]);
+        assert_eq!(assembly.symbols["done"], 10);
+        assert_eq!(assembly.line(8), Some(5));
+        assert_eq!(assembly.address(6), Some(10));
+        assert_eq!(assembly.symbol(8), Some(("loop", 8)));
+        // exports only matter to the linker.
+        assert_eq!(assemble::<i64>("export done\ndone: halt").expect("assemble").program, vec![99]);
+        let mut program = assembly.program.clone();
+        assert_eq!(execute_with_input(&mut program, &[4, 5, 0]).expect("execute"), vec![4, 5]);
+    }
+
+    #[test]
+    fn test_relative_and_offsets() {
+        let assembly = assemble::<i64>("arb 3\nout [rb-1]\nadd [rb], x+1, [rb+x]\nx: halt").expect("assemble");
+        assert_eq!(assembly.program, vec![109, 3, 204, -1, 21201, 0, 9, 8, 99]);
+    }
+
+    #[test]
+    fn test_disassembly_reassembles() {
+        let program: Vec<i64> = parse_program(include_str!("../../input/2019/day9.txt").trim());
+        let source: String = disassemble(&program)
+            .lines()
+            .map(|line| line.trim_start().split_once("  ").expect("address").1)
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble::<i64>(&source).expect("assemble").program, program);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| match assemble::<i64>(source) {
+            Err(e) => e.to_string() + ": " + &std::error::Error::source(&e).expect("source").to_string(),
+            Ok(_) => panic!("assembled {:?}", source),
+        };
+        assert_eq!(error("halt\nfrob 1"), "line 2, can't parse \"frob 1\": unknown instruction \"frob\"");
+        assert!(error("add 1, 2").ends_with("add takes 3 operands, got 2"));
+        assert!(error("in 5").ends_with("in can't write to an immediate operand"));
+        assert!(error("jnz 1, nowhere").ends_with("nowhere isn't defined"));
+        assert!(error("a: halt\na: halt").ends_with("a is already defined"));
+        assert!(error("data 1, [2]").ends_with("expected a number or label, got \"[2]\""));
+        assert!(error("export a, b\na: halt").ends_with("b isn't defined"));
+        assert!(error("export 9").ends_with("invalid label \"9\""));
+    }
+}
@@ -0,0 +1,391 @@
+//! Runs one program over many inputs in lockstep.
+//!
+//! Every lane starts from the same program image, possibly patched,
+//! and the batch advances all of them one instruction at a time.
+//! Instructions are decoded once per address and reused by every
+//! lane that reaches that address without having rewritten it, and
+//! lanes only allocate the memory they actually touch, so a search
+//! over thousands of trials costs far less than running a fresh
+//! `Machine` for each.  `cargo bench --bench batch` compares the two
+//! on day 2.
+//!
+//! Lanes run instructions with the same code as `Machine`, and fail
+//! with the same errors and context, so a batch only adds the lanes'
+//! storage and the scheduling between them.  It keeps the machine's
+//! ISA, overflow policy, step limit and source map, but has no devices
+//! or undo log.
+
+use std::mem;
+use std::result;
+use std::sync::Arc;
+
+use super::sourcemap::SourceMap;
+use super::{annotate, decode, memory_size, run_instruction, Effect, IntcodeError, Isa, Memory, Overflow, Word};
+
+/// One trial: cells to overwrite before running, and the input to
+/// feed the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lane<W = i64> {
+    pub patches: Vec<(usize, W)>,
+    pub input: Vec<W>,
+}
+
+impl<W: Word> Lane<W> {
+    pub fn new(input: Vec<W>) -> Self {
+        Lane {
+            patches: vec![],
+            input,
+        }
+    }
+
+    pub fn patched(patches: Vec<(usize, W)>, input: Vec<W>) -> Self {
+        Lane { patches, input }
+    }
+}
+
+/// How a lane finished.  Like `execute_with_input`, a lane that runs
+/// out of input fails with `IntcodeError::MissingInput`, and output
+/// and memory are kept even when it fails.
+#[derive(Debug)]
+pub struct Outcome<W = i64> {
+    pub output: Vec<W>,
+    /// the first `program.len()` cells of memory when the lane stopped.
+    pub memory: Vec<W>,
+    pub error: Option<IntcodeError<W>>,
+}
+
+// a lane's memory, which grows as the lane touches it, up to the same
+// limit a Machine allocates up front.
+struct Cells<W> {
+    memory: Vec<W>,
+    limit: usize,
+    // what the cells the lane hasn't touched hold.
+    zero: W,
+}
+
+impl<W: Word> Memory for Cells<W> {
+    type Word = W;
+
+    fn size(&self) -> usize {
+        self.limit
+    }
+
+    fn word(&self, address: usize) -> result::Result<&W, IntcodeError<W>> {
+        if address >= self.limit {
+            return Err(IntcodeError::InvalidAddress(address));
+        }
+        Ok(self.memory.get(address).unwrap_or(&self.zero))
+    }
+
+    fn store(&mut self, address: usize, value: W) -> result::Result<Option<W>, IntcodeError<W>> {
+        if address >= self.limit {
+            return Err(IntcodeError::InvalidAddress(address));
+        }
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, W::from(0));
+        }
+        Ok(Some(mem::replace(&mut self.memory[address], value)))
+    }
+}
+
+enum Status {
+    Running,
+    Halted,
+    Failed,
+}
+
+struct State<W> {
+    pc: usize,
+    relative_base: i64,
+    cells: Cells<W>,
+    steps: usize,
+    input: usize,
+    output: Vec<W>,
+    status: Status,
+    error: Option<IntcodeError<W>>,
+}
+
+pub struct Batch<W: Word = i64> {
+    program: Vec<W>,
+    isa: Isa,
+    overflow: Overflow,
+    step_limit: Option<usize>,
+    source_map: Option<Arc<SourceMap>>,
+    // decoded instructions from the program image, by address.
+    decoded: Vec<Option<i64>>,
+}
+
+impl<W: Word> Batch<W> {
+    pub fn new(program: &[W], isa: Isa) -> Self {
+        Batch {
+            program: program.to_vec(),
+            isa,
+            overflow: Overflow::default(),
+            step_limit: None,
+            source_map: None,
+            decoded: vec![None; program.len()],
+        }
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Fails each lane with `IntcodeError::StepLimit` once it has run
+    /// `limit` instructions, as `Machine::set_step_limit` does, so one
+    /// lane that never halts can't hold up the rest.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Relates the program's addresses to its source, so that the
+    /// lanes' errors say where they happened.
+    pub fn set_source_map(&mut self, map: Option<Arc<SourceMap>>) {
+        self.source_map = map;
+    }
+
+    fn lane_state(&self, lane: &Lane<W>) -> State<W> {
+        let mut state = State {
+            pc: 0,
+            relative_base: 0,
+            cells: Cells {
+                memory: self.program.clone(),
+                limit: memory_size(self.program.len()),
+                zero: W::from(0),
+            },
+            steps: 0,
+            input: 0,
+            output: vec![],
+            status: Status::Running,
+            error: None,
+        };
+        for (address, value) in &lane.patches {
+            if let Err(e) = state.cells.store(*address, value.clone()) {
+                state.status = Status::Failed;
+                state.error = Some(e);
+                break;
+            }
+        }
+        state
+    }
+
+    fn decoded(&mut self, state: &State<W>) -> result::Result<i64, IntcodeError<W>> {
+        let word = state.cells.word(state.pc)?;
+        // lanes that rewrote this instruction decode their own.
+        let shared = self.program.get(state.pc) == Some(word);
+        if let (true, Some(instruction)) = (shared, self.decoded[state.pc]) {
+            return Ok(instruction);
+        }
+        let instruction = decode(word, state.pc, self.isa)?;
+        if shared {
+            self.decoded[state.pc] = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    // runs the instruction at the lane's pc.
+    fn step(&mut self, state: &mut State<W>, lane: &Lane<W>) -> result::Result<(), IntcodeError<W>> {
+        if let Some(limit) = self.step_limit {
+            if state.steps >= limit {
+                return Err(IntcodeError::StepLimit(state.pc, limit));
+            }
+        }
+        let instruction = self.decoded(state)?;
+        let effect = run_instruction(
+            instruction,
+            &mut state.pc,
+            &mut state.relative_base,
+            self.isa,
+            self.overflow,
+            &mut state.cells,
+            &mut lane.input[state.input..].iter(),
+        )?;
+        match effect {
+            Effect::Ran { input, output, .. } => {
+                if input.is_some() {
+                    state.input += 1;
+                }
+                state.output.extend(output);
+                state.steps += 1;
+            }
+            Effect::AwaitingInput => return Err(IntcodeError::MissingInput(state.pc)),
+            Effect::Halted => state.status = Status::Halted,
+        }
+        Ok(())
+    }
+
+    /// Runs every lane until it halts or fails, and returns an
+    /// outcome per lane in the same order.
+    pub fn run(&mut self, lanes: &[Lane<W>]) -> Vec<Outcome<W>> {
+        let mut states: Vec<State<W>> = lanes.iter().map(|lane| self.lane_state(lane)).collect();
+        let mut running = states
+            .iter()
+            .filter(|state| matches!(state.status, Status::Running))
+            .count();
+        while running > 0 {
+            for (state, lane) in states.iter_mut().zip(lanes) {
+                if !matches!(state.status, Status::Running) {
+                    continue;
+                }
+                if let Err(e) = self.step(state, lane) {
+                    state.status = Status::Failed;
+                    state.error = Some(annotate(
+                        e,
+                        state.pc,
+                        state.relative_base,
+                        state.steps,
+                        &state.cells,
+                        self.source_map.as_deref(),
+                    ));
+                }
+                if !matches!(state.status, Status::Running) {
+                    running -= 1;
+                }
+            }
+        }
+        let len = self.program.len();
+        states
+            .into_iter()
+            .map(|mut state| {
+                state.cells.memory.truncate(len);
+                Outcome {
+                    output: state.output,
+                    memory: state.cells.memory,
+                    error: state.error,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble::assemble;
+    use crate::intcode::{Exit, Machine};
+
+    fn check_against_machine(program: &[i64], isa: Isa, lanes: Vec<Lane<i64>>) {
+        check_with_step_limit(program, isa, None, lanes)
+    }
+
+    // runs each lane on its own machine, the way execute_with_isa
+    // does, and checks the batch agrees.
+    fn check_with_step_limit(program: &[i64], isa: Isa, limit: Option<usize>, lanes: Vec<Lane<i64>>) {
+        let mut batch = Batch::new(program, isa);
+        batch.set_step_limit(limit);
+        let outcomes = batch.run(&lanes);
+        assert_eq!(outcomes.len(), lanes.len());
+        for (lane, outcome) in lanes.iter().zip(outcomes) {
+            let mut patched = program.to_vec();
+            for (address, value) in &lane.patches {
+                patched[*address] = *value;
+            }
+            let mut machine = Machine::with_isa(&patched, isa);
+            machine.set_step_limit(limit);
+            let mut input = lane.input.iter();
+            let mut output = vec![];
+            let error = loop {
+                match machine.execute(&mut input) {
+                    Ok(Exit::Output(o)) => output.push(o),
+                    Ok(Exit::Halted { .. }) => break None,
+                    Ok(Exit::AwaitingInput { pc }) => break Some(machine.annotate(IntcodeError::MissingInput(pc))),
+                    Err(e) => break Some(e),
+                }
+            };
+            assert_eq!(format!("{:?}", outcome.error), format!("{:?}", error));
+            assert_eq!(outcome.output, output);
+            assert_eq!(outcome.memory, machine.memory(0..program.len()).expect("memory"));
+        }
+    }
+
+    #[test]
+    fn test_patched_lanes() {
+        let program = vec![1, 0, 0, 0, 99, 7, 11];
+        let lanes = (0..7)
+            .flat_map(|noun| (0..7).map(move |verb| Lane::patched(vec![(1, noun), (2, verb)], vec![])))
+            .collect();
+        check_against_machine(&program, Isa::Day2, lanes);
+    }
+
+    #[test]
+    fn test_input_lanes() {
+        let big_program = vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        let lanes = (0..20).map(|i| Lane::new(vec![i])).collect();
+        check_against_machine(&big_program, Isa::Day5, lanes);
+    }
+
+    #[test]
+    fn test_self_modifying_lanes() {
+        // lanes rewrite the instruction at 4 before reaching it, so
+        // only the first can reuse the shared decoding.
+        let program = vec![3, 4, 3, 7, 1101, 2, 3, 0, 4, 0, 99];
+        let lanes = vec![
+            Lane::new(vec![1101, 0]),
+            Lane::new(vec![1102, 0]),
+            Lane::new(vec![1107, 0]),
+            Lane::new(vec![42, 0]),
+            Lane::new(vec![1101]),
+        ];
+        check_against_machine(&program, Isa::Day5, lanes);
+    }
+
+    #[test]
+    fn test_relative_lanes() {
+        let quine = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        check_against_machine(&quine, Isa::Day9, vec![Lane::new(vec![]); 3]);
+        check_against_machine(&quine, Isa::Day5, vec![Lane::new(vec![])]);
+    }
+
+    #[test]
+    fn test_unused_parameter_types() {
+        // the jump isn't taken, so only decoding sees its target's type.
+        let program = vec![3, 1, 0, 0, 99];
+        let lanes = vec![Lane::new(vec![2106]), Lane::new(vec![3106]), Lane::new(vec![1106])];
+        check_against_machine(&program, Isa::Day5, lanes.clone());
+        check_against_machine(&program, Isa::Day9, lanes);
+    }
+
+    #[test]
+    fn test_step_limit() {
+        // loops forever at 2 unless its input is zero.
+        let program = vec![3, 9, 1005, 9, 2, 4, 9, 99, 0, 0];
+        let lanes = vec![Lane::new(vec![0]), Lane::new(vec![1]), Lane::new(vec![0]), Lane::new(vec![2])];
+        for limit in &[0, 3, 4, 50] {
+            check_with_step_limit(&program, Isa::Day5, Some(*limit), lanes.clone());
+        }
+        let mut batch = Batch::new(&program, Isa::Day5);
+        batch.set_step_limit(Some(1000));
+        let outcomes = batch.run(&lanes);
+        assert_eq!(outcomes[0].output, vec![0]);
+        match outcomes[1].error.as_ref().map(IntcodeError::kind) {
+            Some(IntcodeError::StepLimit(2, 1000)) => (),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors_have_context() {
+        let assembly = assemble::<i64>("        in [n]\n        out [n]\n        halt\nn:      data 0\n").expect("assemble");
+        let mut batch = Batch::new(&assembly.program, Isa::Day5);
+        batch.set_source_map(Some(Arc::new(SourceMap::new("input.asm", &assembly))));
+        let outcomes = batch.run(&[Lane::new(vec![]), Lane::new(vec![5])]);
+        let context = outcomes[0].error.as_ref().and_then(IntcodeError::context).expect("context");
+        assert!(matches!(context.error, IntcodeError::MissingInput(0)));
+        assert_eq!(context.location.as_ref().map(|location| location.line), Some(1));
+        assert_eq!(context.window, vec![3, 5, 4, 5, 99]);
+        assert!(outcomes[1].error.is_none());
+    }
+
+    #[test]
+    fn test_patch_outside_memory() {
+        let outcomes = Batch::<i64>::new(&[99], Isa::Day2).run(&[Lane::patched(vec![(101, 1)], vec![])]);
+        match outcomes[0].error {
+            Some(IntcodeError::InvalidAddress(101)) => (),
+            ref other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,653 @@
+//! Compiles a small C-like language to intcode.
+//!
+//! A program is a list of functions, and runs by calling `main`:
+//!
+//! ```text
+//! fn main() {
+//!     let n = input();
+//!     let i = 0;
+//!     while i < n {
+//!         output(fib(i));
+//!         i = i + 1;
+//!     }
+//! }
+//!
+//! fn fib(n) {
+//!     if n < 2 { return n; }
+//!     return fib(n - 1) + fib(n - 2);
+//! }
+//! ```
+//!
+//! Everything is a word.  There are `+`, `-`, `*`, the comparisons
+//! `<`, `<=`, `>`, `>=`, `==` and `!=`, which give 1 or 0, and `!`,
+//! `&&` and `||`, which treat anything but 0 as true and only
+//! evaluate their right operand if they need to.  `input()` reads a
+//! word and `output(x)` writes one.  `let` declares a variable for the
+//! rest of its block, `return` without a value returns 0, as does
+//! falling off the end of a function, and `//` starts a comment.
+//!
+//! The compiler produces assembler source, which `build` assembles.
+//! Functions follow the calling convention in `library`: arguments,
+//! then locals and temporaries, go in the frame after the return
+//! address and saved base, and the result is left at `[rb+2]`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::result;
+
+use super::assemble::{self, Assembly};
+use super::library;
+use super::{FileError, Word};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "<=", ">=", "==", "!=", "&&", "||", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "<", ">", "!",
+];
+
+const KEYWORDS: [&str; 6] = ["fn", "let", "if", "else", "while", "return"];
+
+// a source position and what went wrong there.
+struct Error {
+    line: usize,
+    text: String,
+    reason: String,
+}
+
+type Result<T> = result::Result<T, Error>;
+
+fn error<T>(line: usize, text: &str, reason: String) -> Result<T> {
+    Err(Error {
+        line,
+        text: text.to_string(),
+        reason,
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let line = match line.find("//") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let length = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let length = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                match rest[..length].parse() {
+                    Ok(n) => tokens.push((i + 1, Token::Number(n))),
+                    Err(_) => return error(i + 1, &rest[..length], "number is too large".to_string()),
+                }
+                length
+            } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let length = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push((i + 1, Token::Name(rest[..length].to_string())));
+                length
+            } else {
+                match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                    Some(symbol) => {
+                        tokens.push((i + 1, Token::Symbol(symbol)));
+                        symbol.len()
+                    }
+                    None => {
+                        let c = rest.chars().next().expect("not empty");
+                        return error(i + 1, &c.to_string(), "unexpected character".to_string());
+                    }
+                }
+            };
+            rest = rest[length..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(i64),
+    Variable(usize, String),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(usize, String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Let(usize, String, Expr),
+    Assign(usize, String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+struct Function {
+    line: usize,
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+// binary operators from loosest to tightest.
+const PRECEDENCE: [&[&str]; 5] = [&["||"], &["&&"], &["==", "!=", "<", "<=", ">", ">="], &["+", "-"], &["*"]];
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.next).or_else(|| self.tokens.last()) {
+            Some((line, _)) => *line,
+            None => 1,
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        let found = match self.peek() {
+            Some(Token::Number(n)) => n.to_string(),
+            Some(Token::Name(name)) => name.clone(),
+            Some(Token::Symbol(symbol)) => symbol.to_string(),
+            None => "end of input".to_string(),
+        };
+        error(self.line(), &found, format!("expected {}", expected))
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = match self.peek() {
+            Some(Token::Symbol(s)) => *s == symbol,
+            Some(Token::Name(name)) => name == symbol,
+            _ => false,
+        };
+        if matches {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", symbol))
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Name(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.next += 1;
+                Ok(name)
+            }
+            _ => self.unexpected("a name"),
+        }
+    }
+
+    // a comma separated list, up to and including `)`.
+    fn list<T, F: FnMut(&mut Self) -> Result<T>>(&mut self, mut item: F) -> Result<Vec<T>> {
+        let mut items = vec![];
+        if self.eat(")") {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(")") {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn function(&mut self) -> Result<Function> {
+        let line = self.line();
+        self.expect("fn")?;
+        let name = self.name()?;
+        self.expect("(")?;
+        let params = self.list(Parser::name)?;
+        let body = self.block()?;
+        Ok(Function {
+            line,
+            name,
+            params,
+            body,
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        self.expect("{")?;
+        let mut statements = vec![];
+        while !self.eat("}") {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        let line = self.line();
+        if self.eat("let") {
+            let name = self.name()?;
+            self.expect("=")?;
+            let value = self.expression(0)?;
+            self.expect(";")?;
+            return Ok(Stmt::Let(line, name, value));
+        }
+        if self.eat("if") {
+            let condition = self.expression(0)?;
+            let then = self.block()?;
+            let otherwise = if !self.eat("else") {
+                vec![]
+            } else if self.peek() == Some(&Token::Name("if".to_string())) {
+                vec![self.statement()?]
+            } else {
+                self.block()?
+            };
+            return Ok(Stmt::If(condition, then, otherwise));
+        }
+        if self.eat("while") {
+            let condition = self.expression(0)?;
+            return Ok(Stmt::While(condition, self.block()?));
+        }
+        if self.eat("return") {
+            if self.eat(";") {
+                return Ok(Stmt::Return(None));
+            }
+            let value = self.expression(0)?;
+            self.expect(";")?;
+            return Ok(Stmt::Return(Some(value)));
+        }
+        let is_assignment = matches!(self.tokens.get(self.next + 1), Some((_, Token::Symbol("="))));
+        let statement = if is_assignment {
+            let name = self.name()?;
+            self.expect("=")?;
+            Stmt::Assign(line, name, self.expression(0)?)
+        } else {
+            Stmt::Expr(self.expression(0)?)
+        };
+        self.expect(";")?;
+        Ok(statement)
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.expression(level + 1)?;
+        while let Some(operator) = PRECEDENCE[level].iter().find(|op| self.peek() == Some(&Token::Symbol(op))) {
+            self.next += 1;
+            let right = self.expression(level + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let line = self.line();
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(match self.unary()? {
+                Expr::Number(n) => Expr::Number(-n),
+                operand => Expr::Negate(Box::new(operand)),
+            });
+        }
+        if self.eat("(") {
+            let inner = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(inner);
+        }
+        if let Some(Token::Number(n)) = self.peek() {
+            let n = *n;
+            self.next += 1;
+            return Ok(Expr::Number(n));
+        }
+        let name = self.name()?;
+        if self.eat("(") {
+            let arguments = self.list(|p| p.expression(0))?;
+            return Ok(Expr::Call(line, name, arguments));
+        }
+        Ok(Expr::Variable(line, name))
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Function>> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        next: 0,
+    };
+    let mut functions = vec![];
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+    Ok(functions)
+}
+
+// how many variables a block declares, including nested blocks.
+fn locals(statements: &[Stmt]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match statement {
+            Stmt::Let(..) => 1,
+            Stmt::If(_, then, otherwise) => locals(then) + locals(otherwise),
+            Stmt::While(_, body) => locals(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+// the first slot after the return address and saved base.
+const ARGUMENTS: usize = 2;
+
+struct Generator {
+    out: String,
+    labels: usize,
+    // the number of parameters each function takes.
+    arities: BTreeMap<String, usize>,
+    // the function being generated, and its variables by scope.
+    function: String,
+    scopes: Vec<Vec<(String, usize)>>,
+    next_local: usize,
+    temporaries: usize,
+}
+
+fn slot(n: usize) -> String {
+    format!("[rb+{}]", n)
+}
+
+impl Generator {
+    fn emit(&mut self, text: &str) {
+        let _ = writeln!(self.out, "        {}", text);
+    }
+
+    fn label(&mut self, what: &str) -> String {
+        self.labels += 1;
+        format!("{}.{}{}", self.function, what, self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        let _ = writeln!(self.out, "{}:", label);
+    }
+
+    fn variable(&self, line: usize, name: &str) -> Result<usize> {
+        for scope in self.scopes.iter().rev() {
+            if let Some((_, slot)) = scope.iter().rev().find(|(n, _)| n == name) {
+                return Ok(*slot);
+            }
+        }
+        error(line, name, format!("{} isn't declared", name))
+    }
+
+    fn function(&mut self, function: &Function) -> Result<()> {
+        self.function = function.name.clone();
+        self.scopes = vec![function.params.iter().cloned().zip(ARGUMENTS..).collect()];
+        self.next_local = ARGUMENTS + function.params.len();
+        self.temporaries = self.next_local + locals(&function.body);
+        let _ = writeln!(self.out, "\n; fn {}({})", function.name, function.params.join(", "));
+        self.place(&function.name);
+        self.block(&function.body)?;
+        self.ret("0");
+        Ok(())
+    }
+
+    fn ret(&mut self, value: &str) {
+        self.emit(&format!("add {}, 0, [rb+2]", value));
+        self.emit("add [rb+0], 0, [rt.return]");
+        self.emit("arb [rb+1]");
+        self.emit("jnz 1, [rt.return]");
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<()> {
+        self.scopes.push(vec![]);
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<()> {
+        let top = self.temporaries;
+        match statement {
+            Stmt::Let(_, name, value) => {
+                let value = self.value(value, top)?;
+                let slot = self.next_local;
+                self.next_local += 1;
+                self.emit(&format!("add {}, 0, {}", value, self::slot(slot)));
+                self.scopes.last_mut().expect("scope").push((name.clone(), slot));
+            }
+            Stmt::Assign(line, name, value) => {
+                let slot = self.variable(*line, name)?;
+                let value = self.value(value, top)?;
+                self.emit(&format!("add {}, 0, {}", value, self::slot(slot)));
+            }
+            Stmt::If(condition, then, otherwise) => {
+                let (other, end) = (self.label("else"), self.label("end"));
+                let condition = self.value(condition, top)?;
+                self.emit(&format!("jz {}, {}", condition, other));
+                self.block(then)?;
+                self.emit(&format!("jnz 1, {}", end));
+                self.place(&other);
+                self.block(otherwise)?;
+                self.place(&end);
+            }
+            Stmt::While(condition, body) => {
+                let (head, end) = (self.label("while"), self.label("end"));
+                self.place(&head);
+                let condition = self.value(condition, top)?;
+                self.emit(&format!("jz {}, {}", condition, end));
+                self.block(body)?;
+                self.emit(&format!("jnz 1, {}", head));
+                self.place(&end);
+            }
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.value(value, top)?,
+                    None => "0".to_string(),
+                };
+                self.ret(&value);
+            }
+            Stmt::Expr(value) => {
+                self.value(value, top)?;
+            }
+        }
+        Ok(())
+    }
+
+    // generates `expr`, using slots from `top` up for temporaries, and
+    // returns an operand for its value.
+    fn value(&mut self, expr: &Expr, top: usize) -> Result<String> {
+        let result = slot(top);
+        match expr {
+            Expr::Number(n) => return Ok(n.to_string()),
+            Expr::Variable(line, name) => return Ok(slot(self.variable(*line, name)?)),
+            Expr::Not(operand) => {
+                let operand = self.value(operand, top)?;
+                self.emit(&format!("eq {}, 0, {}", operand, result));
+            }
+            Expr::Negate(operand) => {
+                let operand = self.value(operand, top)?;
+                self.emit(&format!("mul {}, -1, {}", operand, result));
+            }
+            Expr::Binary(operator @ ("&&" | "||"), left, right) => {
+                let end = self.label("end");
+                let left = self.value(left, top)?;
+                // the result is the left operand's truth if that settles it.
+                self.emit(&format!("eq {}, 0, {}", left, result));
+                if *operator == "&&" {
+                    self.emit(&format!("eq {}, 0, {}", result, result));
+                    self.emit(&format!("jz {}, {}", result, end));
+                } else {
+                    self.emit(&format!("eq {}, 0, {}", result, result));
+                    self.emit(&format!("jnz {}, {}", result, end));
+                }
+                let right = self.value(right, top)?;
+                self.emit(&format!("eq {}, 0, {}", right, result));
+                self.emit(&format!("eq {}, 0, {}", result, result));
+                self.place(&end);
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.value(left, top)?;
+                let right = self.value(right, top + 1)?;
+                let (l, r) = (&left, &right);
+                match *operator {
+                    "+" => self.emit(&format!("add {}, {}, {}", l, r, result)),
+                    "-" => {
+                        self.emit(&format!("mul {}, -1, {}", r, slot(top + 1)));
+                        self.emit(&format!("add {}, {}, {}", l, slot(top + 1), result));
+                    }
+                    "*" => self.emit(&format!("mul {}, {}, {}", l, r, result)),
+                    "<" => self.emit(&format!("lt {}, {}, {}", l, r, result)),
+                    ">" => self.emit(&format!("lt {}, {}, {}", r, l, result)),
+                    "==" => self.emit(&format!("eq {}, {}, {}", l, r, result)),
+                    _ => {
+                        let test = match *operator {
+                            "<=" => format!("lt {}, {}, {}", r, l, result),
+                            ">=" => format!("lt {}, {}, {}", l, r, result),
+                            _ => format!("eq {}, {}, {}", l, r, result),
+                        };
+                        self.emit(&test);
+                        self.emit(&format!("eq {}, 0, {}", result, result));
+                    }
+                }
+            }
+            Expr::Call(line, name, arguments) => self.call(*line, name, arguments, top)?,
+        }
+        Ok(result)
+    }
+
+    // leaves the result of a call in `[rb+top]`.
+    fn call(&mut self, line: usize, name: &str, arguments: &[Expr], top: usize) -> Result<()> {
+        let builtin = match name {
+            "input" => Some(0),
+            "output" => Some(1),
+            _ => None,
+        };
+        let arity = match builtin.or_else(|| self.arities.get(name).copied()) {
+            Some(arity) => arity,
+            None => return error(line, name, format!("{} isn't a function", name)),
+        };
+        if arguments.len() != arity {
+            let reason = format!("{} takes {} arguments, got {}", name, arity, arguments.len());
+            return error(line, name, reason);
+        }
+        match name {
+            "input" => self.emit(&format!("in {}", slot(top))),
+            "output" => {
+                let value = self.value(&arguments[0], top)?;
+                self.emit(&format!("out {}", value));
+                self.emit(&format!("add 0, 0, {}", slot(top)));
+            }
+            _ => {
+                // the callee's frame starts past everything in use.
+                let frame = top;
+                let scratch = frame + ARGUMENTS + arguments.len();
+                for (i, argument) in arguments.iter().enumerate() {
+                    let value = self.value(argument, scratch)?;
+                    self.emit(&format!("add {}, 0, {}", value, slot(frame + ARGUMENTS + i)));
+                }
+                let back = self.label("return");
+                self.emit(&format!("add {}, 0, {}", back, slot(frame)));
+                self.emit(&format!("add -{}, 0, {}", frame, slot(frame + 1)));
+                self.emit(&format!("arb {}", frame));
+                self.emit(&format!("jnz 1, {}", name));
+                self.place(&back);
+                self.emit(&format!("add {}, 0, {}", slot(frame + 2), slot(top)));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn generate(functions: &[Function]) -> Result<String> {
+    let mut arities = BTreeMap::new();
+    for function in functions {
+        if arities.insert(function.name.clone(), function.params.len()).is_some() {
+            let reason = format!("{} is already defined", function.name);
+            return error(function.line, &function.name, reason);
+        }
+    }
+    if arities.get("main") != Some(&0) {
+        return error(1, "main", "there must be a main function without parameters".to_string());
+    }
+    let mut generator = Generator {
+        out: String::new(),
+        labels: 0,
+        arities,
+        function: String::new(),
+        scopes: vec![],
+        next_local: 0,
+        temporaries: 0,
+    };
+    generator.out.push_str(library::START);
+    for function in functions {
+        generator.function(function)?;
+    }
+    generator.out.push_str(library::END);
+    Ok(generator.out)
+}
+
+/// Compiles `source` to assembler source.
+pub fn compile(source: &str) -> result::Result<String, FileError> {
+    parse(source)
+        .and_then(|functions| generate(&functions))
+        .map_err(|e| FileError::Line(e.line, e.text, e.reason.into()))
+}
+
+/// Compiles and assembles `source`.
+pub fn build<W: Word>(source: &str) -> result::Result<Assembly<W>, FileError> {
+    assemble::assemble(&compile(source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        match compile(source) {
+            Err(e) => format!("{}: {}", e, std::error::Error::source(&e).expect("source")),
+            Ok(_) => panic!("compiled {:?}", source),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let functions = parse("fn main() { let x = 1 + 2 * -y(3); x = !x || x; }").ok().expect("parse");
+        let y = Expr::Call(1, "y".to_string(), vec![Expr::Number(3)]);
+        let product = Expr::Binary("*", Box::new(Expr::Number(2)), Box::new(Expr::Negate(Box::new(y))));
+        assert_eq!(
+            functions[0].body[0],
+            Stmt::Let(1, "x".to_string(), Expr::Binary("+", Box::new(Expr::Number(1)), Box::new(product)))
+        );
+        assert_eq!(locals(&functions[0].body), 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error("fn main() {\n  x = 1;\n}"), "line 2, can't parse \"x\": x isn't declared");
+        assert!(error("fn main() { f(1); }").ends_with("f isn't a function"));
+        assert!(error("fn main() { output(); }").ends_with("output takes 1 arguments, got 0"));
+        assert!(error("fn main() {}\nfn main() {}").ends_with("main is already defined"));
+        assert!(error("fn start() {}").ends_with("there must be a main function without parameters"));
+        assert!(error("fn main() { let = 3; }").ends_with("expected a name"));
+        assert!(error("fn main() { 1 + ; }").ends_with("expected a name"));
+        assert!(error("fn main() { return 1 }").ends_with("expected `;`"));
+        assert!(error("fn main() { # }").ends_with("unexpected character"));
+    }
+}
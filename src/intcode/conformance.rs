@@ -0,0 +1,148 @@
+//! The intcode conformance cases in `tests/conformance`, which the
+//! interpreter is tested against here and translations are tested
+//! against in the `aoc2019-transpiled` crate.
+//!
+//! Each `.case` file describes one run, one `key: value` per line,
+//! with `#` starting a comment:
+//!
+//! ```text
+//! # adds 1 and 1 into address 0
+//! program: 1,0,0,0,99
+//! isa: Day2
+//! input:
+//! output:
+//! memory: 2,0,0,0,99
+//! ```
+//!
+//! `program` is required.  `isa` defaults to `Day9` and `input` to
+//! nothing.  `output` and `memory` are only checked when given, and
+//! `memory` only covers as many cells as it lists.  `error` is the
+//! expected `IntcodeError` in its `Debug` form, without its context;
+//! without it the run must halt.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{execute_with_isa, parse_program, Isa};
+
+#[derive(Debug, Default)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub isa: Isa,
+    pub input: Vec<i64>,
+    pub output: Option<Vec<i64>>,
+    pub memory: Option<Vec<i64>>,
+    pub error: Option<String>,
+}
+
+fn words(value: &str) -> Vec<i64> {
+    if value.is_empty() {
+        vec![]
+    } else {
+        parse_program(value)
+    }
+}
+
+pub fn parse_case(text: &str) -> Result<Case, String> {
+    let mut case = Case::default();
+    let mut program = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.find(':') {
+            Some(colon) => (line[..colon].trim(), line[colon + 1..].trim()),
+            None => return Err(format!("line {}: expected key: value", number + 1)),
+        };
+        match key {
+            "program" => program = Some(words(value)),
+            "isa" => {
+                case.isa = match value {
+                    "Day2" => Isa::Day2,
+                    "Day5" => Isa::Day5,
+                    "Day9" => Isa::Day9,
+                    _ => return Err(format!("line {}: unknown isa {}", number + 1, value)),
+                }
+            }
+            "input" => case.input = words(value),
+            "output" => case.output = Some(words(value)),
+            "memory" => case.memory = Some(words(value)),
+            "error" => case.error = Some(value.to_string()),
+            _ => return Err(format!("line {}: unknown key {}", number + 1, key)),
+        }
+    }
+    case.program = program.ok_or("missing program")?;
+    Ok(case)
+}
+
+/// Runs `case` on the interpreter, saying how it went wrong if it did.
+pub fn run_case(case: &Case) -> Result<(), String> {
+    let mut memory = case.program.clone();
+    let (output, error) = match execute_with_isa(&mut memory, &case.input, case.isa) {
+        Ok(output) => (output, None),
+        Err(failure) => (failure.output, Some(format!("{:?}", failure.error.kind()))),
+    };
+    if error != case.error {
+        return Err(format!("expected error {:?}, got {:?}", case.error, error));
+    }
+    if let Some(expected) = &case.output {
+        if output != *expected {
+            return Err(format!("expected output {:?}, got {:?}", expected, output));
+        }
+    }
+    if let Some(expected) = &case.memory {
+        if expected.len() > memory.len() || memory[..expected.len()] != expected[..] {
+            return Err(format!("expected memory {:?}, got {:?}", expected, memory));
+        }
+    }
+    Ok(())
+}
+
+/// The paths of the cases, in order.
+pub fn cases() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let mut cases: Vec<PathBuf> = fs::read_dir(&directory)
+        .expect("conformance directory")
+        .map(|entry| entry.expect("conformance entry").path())
+        .filter(|path| path.extension().is_some_and(|e| e == "case"))
+        .collect();
+    cases.sort();
+    cases
+}
+
+#[test]
+fn test_conformance() {
+    let cases = cases();
+    assert!(!cases.is_empty(), "no conformance cases found");
+    let failures: Vec<String> = cases
+        .iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(path).expect("read case");
+            match parse_case(&text).and_then(|case| run_case(&case)) {
+                Ok(()) => None,
+                Err(e) => Some(format!("{}: {}", path.display(), e)),
+            }
+        })
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn test_parse_case() {
+    let case = parse_case("# comment\nprogram: 3,0,4,0,99\nisa: Day5\ninput: 7\noutput: 7\nmemory: 7\n")
+        .expect("parse");
+    assert_eq!(case.program, vec![3, 0, 4, 0, 99]);
+    assert_eq!(case.isa, Isa::Day5);
+    assert_eq!(case.input, vec![7]);
+    assert_eq!(case.output, Some(vec![7]));
+    assert_eq!(case.memory, Some(vec![7]));
+    assert_eq!(case.error, None);
+    assert!(run_case(&case).is_ok());
+
+    assert!(parse_case("input: 1\n").is_err());
+    assert!(parse_case("program: 99\nisa: Day10\n").is_err());
+    assert!(parse_case("program: 99\nwhatever\n").is_err());
+    assert!(run_case(&parse_case("program: 104,1,99\noutput: 2\n").expect("parse")).is_err());
+    assert!(run_case(&parse_case("program: 3,0,99\n").expect("parse")).is_err());
+}
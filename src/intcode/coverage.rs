@@ -0,0 +1,363 @@
+//! Measures how much of an intcode program a set of runs exercises.
+//!
+//! A `Coverage` is built for one program and fed runs of it with
+//! `run`.  It counts how often each instruction executed, which cells
+//! were read and written as data, which mode each parameter of each
+//! instruction ran in, and which way each jump (opcodes 5 and 6) and
+//! comparison (opcodes 7 and 8) went.  `report` summarises that as
+//! text, and `annotate` puts it alongside a disassembly:
+//!
+//! ```text
+//!      3      0  in [9]
+//!      3      2  eq [9], [10], [9]  ; true 1, false 2
+//!      3      6  out [9]
+//!      3      8  halt
+//!     *r      9  data -1, 8
+//! ```
+//!
+//! The first column is an instruction's execution count, or `-` if
+//! it never ran.  For data it has one character per cell: `x` for
+//! executed, `r` for read, `w` for written, `*` for both read and
+//! written, and `-` for untouched.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::result;
+
+use super::decompile::Op;
+use super::disassemble::{self, mnemonic};
+use super::{Exit, IntcodeError, Machine, Word};
+
+const MODES: [&str; 3] = ["position", "immediate", "relative"];
+
+// a parameter of an instruction site, in one mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Site {
+    opcode: i64,
+    parameter: usize,
+    mode: i64,
+    address: usize,
+}
+
+pub struct Coverage<W: Word = i64> {
+    program: Vec<W>,
+    runs: usize,
+    executed: BTreeMap<usize, usize>,
+    read: BTreeSet<usize>,
+    written: BTreeSet<usize>,
+    modes: BTreeMap<Site, usize>,
+    // how often each jump or comparison went each way, false first.
+    branches: BTreeMap<usize, [usize; 2]>,
+}
+
+// the cell a position or relative mode parameter refers to.
+fn cell<W: Word>(mode: i64, word: &W, relative_base: i64) -> Option<usize> {
+    let word = word.to_i64()?;
+    let cell = match mode {
+        0 => word,
+        2 => relative_base.checked_add(word)?,
+        _ => return None,
+    };
+    if cell < 0 {
+        None
+    } else {
+        Some(cell as usize)
+    }
+}
+
+// collapses addresses into ranges, like `19, 31-37`.
+fn ranges(addresses: &[usize]) -> String {
+    let mut out: Vec<String> = vec![];
+    let mut i = 0;
+    while i < addresses.len() {
+        let mut j = i;
+        while j + 1 < addresses.len() && addresses[j + 1] == addresses[j] + 1 {
+            j += 1;
+        }
+        out.push(if i == j {
+            addresses[i].to_string()
+        } else {
+            format!("{}-{}", addresses[i], addresses[j])
+        });
+        i = j + 1;
+    }
+    out.join(", ")
+}
+
+impl<W: Word> Coverage<W> {
+    pub fn new(program: &[W]) -> Self {
+        Coverage {
+            program: program.to_vec(),
+            runs: 0,
+            executed: BTreeMap::new(),
+            read: BTreeSet::new(),
+            written: BTreeSet::new(),
+            modes: BTreeMap::new(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// Runs `machine` to completion on `input`, recording what it
+    /// does.  The machine should be running the program the coverage
+    /// was made for.  Returns the run's output.
+    pub fn run(&mut self, machine: &mut Machine<W>, input: &[W]) -> result::Result<Vec<W>, IntcodeError<W>> {
+        self.runs += 1;
+        let mut input = input.iter();
+        let mut output = vec![];
+        loop {
+            let (pc, relative_base) = (machine.pc(), machine.relative_base());
+            let op = Op::decode(&machine.program, pc);
+            let exit = machine.step(&mut input)?;
+            if let Some(Exit::AwaitingInput { pc }) = exit {
+                return Err(machine.annotate(IntcodeError::MissingInput(pc)));
+            }
+            *self.executed.entry(pc).or_insert(0) += 1;
+            if let Some(op) = op {
+                self.record(machine, &op, relative_base);
+            }
+            match exit {
+                Some(Exit::Output(value)) => output.push(value),
+                Some(_) => return Ok(output),
+                None => (),
+            }
+        }
+    }
+
+    fn record(&mut self, machine: &Machine<W>, op: &Op<W>, relative_base: i64) {
+        let taken = machine.pc() != op.next();
+        // the parameters the instruction read, and the one it wrote.
+        let (reads, write) = match op.opcode {
+            1 | 2 | 7 | 8 => (2, Some(2)),
+            3 => (0, Some(0)),
+            4 | 9 => (1, None),
+            5 | 6 if taken => (2, None),
+            5 | 6 => (1, None),
+            _ => (0, None),
+        };
+        for n in (0..reads).chain(write) {
+            let site = Site {
+                opcode: op.opcode,
+                parameter: n,
+                mode: op.modes[n],
+                address: op.address,
+            };
+            *self.modes.entry(site).or_insert(0) += 1;
+            if let Some(cell) = cell(op.modes[n], &op.params[n], relative_base) {
+                if Some(n) == write {
+                    self.written.insert(cell);
+                } else {
+                    self.read.insert(cell);
+                }
+            }
+        }
+        let outcome = match op.opcode {
+            5 | 6 => taken,
+            7 | 8 => {
+                let destination = cell(op.modes[2], &op.params[2], relative_base);
+                destination.and_then(|cell| machine.read(cell)) != Some(&W::from(0))
+            }
+            _ => return,
+        };
+        self.branches.entry(op.address).or_insert([0, 0])[outcome as usize] += 1;
+    }
+
+    /// How many times the instruction at `address` executed.
+    pub fn executed(&self, address: usize) -> usize {
+        self.executed.get(&address).copied().unwrap_or(0)
+    }
+
+    fn ops(&self) -> Vec<Op<W>> {
+        disassemble::ops(&self.program, &self.executed.keys().copied().collect())
+    }
+
+    // every cell that ran as part of an instruction.
+    fn executed_cells(&self) -> BTreeSet<usize> {
+        self.ops()
+            .iter()
+            .filter(|op| self.executed.contains_key(&op.address))
+            .flat_map(|op| op.address..op.next())
+            .chain(self.executed.keys().copied())
+            .collect()
+    }
+
+    fn branch(&self, opcode: i64, address: usize) -> Option<String> {
+        let [no, yes] = self.branches.get(&address).copied().unwrap_or([0, 0]);
+        match opcode {
+            5 | 6 => Some(format!("taken {}, not taken {}", yes, no)),
+            7 | 8 => Some(format!("true {}, false {}", yes, no)),
+            _ => None,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let cells = self.program.len();
+        let executed = self.executed_cells();
+        let untouched: Vec<usize> = (0..cells)
+            .filter(|c| !executed.contains(c) && !self.read.contains(c) && !self.written.contains(c))
+            .collect();
+        let in_program = |set: &BTreeSet<usize>| set.range(..cells).count();
+        let percent = |n: usize| (n * 100).checked_div(cells).unwrap_or(0);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{} runs over {} cells", self.runs, cells);
+        let executed = in_program(&executed);
+        let _ = writeln!(out, "executed   {} cells ({}%)", executed, percent(executed));
+        let _ = writeln!(out, "read       {} cells", in_program(&self.read));
+        let _ = writeln!(out, "written    {} cells", in_program(&self.written));
+        let _ = write!(out, "untouched  {} cells ({}%)", untouched.len(), percent(untouched.len()));
+        if !untouched.is_empty() {
+            let _ = write!(out, ": {}", ranges(&untouched));
+        }
+        out.push('\n');
+
+        // every site in the program, whether it ran or not.
+        let mut sites: BTreeMap<Site, usize> = BTreeMap::new();
+        for op in self.ops() {
+            for (n, mode) in op.modes.iter().enumerate().take(op.params.len()) {
+                let site = Site {
+                    opcode: op.opcode,
+                    parameter: n,
+                    mode: *mode,
+                    address: op.address,
+                };
+                sites.insert(site, 0);
+            }
+        }
+        sites.extend(self.modes.iter().map(|(site, count)| (*site, *count)));
+        let mut totals: BTreeMap<(i64, usize), BTreeMap<i64, (usize, usize)>> = BTreeMap::new();
+        for (site, count) in &sites {
+            let total = totals
+                .entry((site.opcode, site.parameter))
+                .or_default()
+                .entry(site.mode)
+                .or_insert((0, 0));
+            total.0 += (*count > 0) as usize;
+            total.1 += 1;
+        }
+        let _ = writeln!(out, "\nparameter modes, sites run of sites:");
+        for ((opcode, parameter), modes) in &totals {
+            let modes: Vec<String> = modes
+                .iter()
+                .map(|(mode, (run, all))| format!("{} {}/{}", MODES[*mode as usize], run, all))
+                .collect();
+            let _ = writeln!(out, "  {:<4} param {}  {}", mnemonic(*opcode), parameter + 1, modes.join(", "));
+        }
+
+        // jumps on an immediate condition only ever go one way.
+        let branches: Vec<Op<W>> = self.ops().into_iter().filter(|op| (5..=8).contains(&op.opcode)).collect();
+        let (mut covered, mut directions) = (0, 0);
+        for op in &branches {
+            let [no, yes] = self.branches.get(&op.address).copied().unwrap_or([0, 0]);
+            covered += (no > 0) as usize + (yes > 0) as usize;
+            directions += if op.taken().is_some() { 1 } else { 2 };
+        }
+        let _ = writeln!(out, "\nbranch directions, {} of {}:", covered, directions);
+        for op in &branches {
+            let line = disassemble::instruction(op);
+            let branch = self.branch(op.opcode, op.address).unwrap_or_default();
+            let _ = writeln!(out, "  {:>5}  {:<24}  {}", op.address, line.text, branch);
+        }
+        out
+    }
+
+    /// A disassembly of the program with its coverage alongside.
+    pub fn annotate(&self) -> String {
+        let executed = self.executed_cells();
+        let mut out = String::new();
+        for line in disassemble::lines(&self.program, &self.executed.keys().copied().collect()) {
+            let (count, note) = match line.opcode {
+                Some(opcode) => {
+                    let count = match self.executed(line.address) {
+                        0 => "-".to_string(),
+                        n => n.to_string(),
+                    };
+                    let note = self.branch(opcode, line.address).map(|b| format!("  ; {}", b));
+                    (count, note.unwrap_or_default())
+                }
+                None => {
+                    let cells = (line.address..line.address + line.len).map(|c| {
+                        match (executed.contains(&c), self.read.contains(&c), self.written.contains(&c)) {
+                            (true, _, _) => 'x',
+                            (_, true, true) => '*',
+                            (_, true, false) => 'r',
+                            (_, false, true) => 'w',
+                            _ => '-',
+                        }
+                    });
+                    (cells.collect(), String::new())
+                }
+            };
+            let _ = writeln!(out, "{:>8}  {:>5}  {}{}", count, line.address, line.text, note);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::parse_program;
+
+    // outputs 999 below 8, 1000 at 8 and 1001 above it.
+    const COMPARE_TO_8: &str = "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,\
+                                1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,\
+                                1105,1,46,98,99";
+
+    fn coverage(inputs: &[i64]) -> Coverage {
+        let program: Vec<i64> = parse_program(COMPARE_TO_8);
+        let mut coverage = Coverage::new(&program);
+        for input in inputs {
+            coverage.run(&mut Machine::new(&program), &[*input]).expect("run");
+        }
+        coverage
+    }
+
+    #[test]
+    fn test_branches() {
+        let partial = coverage(&[7]);
+        assert_eq!(partial.executed(0), 1);
+        assert_eq!(partial.executed(22), 0);
+        let report = partial.report();
+        assert!(report.contains("branch directions, 5 of 12:"), "{}", report);
+        assert!(report.contains("    6  jnz [20], 22              taken 0, not taken 1"), "{}", report);
+
+        let full = coverage(&[7, 8, 9]);
+        let report = full.report();
+        assert!(report.contains("branch directions, 12 of 12:"), "{}", report);
+        assert!(report.contains("untouched  2 cells (4%): 19, 45"), "{}", report);
+        assert!(report.contains("  lt   param 1  immediate 1/1"), "{}", report);
+        assert!(report.contains("  out  param 1  position 2/2, immediate 1/1"), "{}", report);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let annotated = coverage(&[7, 9]).annotate();
+        let lines: Vec<&str> = annotated.lines().collect();
+        assert_eq!(lines[0], "       2      0  in [21]");
+        assert_eq!(lines[1], "       2      2  eq [21], 8, [20]  ; true 0, false 2");
+        assert!(lines.contains(&"       1     36  add 1000, 1, [20]"), "{}", annotated);
+        assert!(lines.contains(&"       -     22  mul [21], 125, [20]"), "{}", annotated);
+        assert!(lines.contains(&"     -**     19  data 98, 0, 0"), "{}", annotated);
+    }
+
+    #[test]
+    fn test_day5() {
+        let program: Vec<i64> = parse_program(include_str!("../../input/2019/day5.txt").trim());
+        let mut coverage = Coverage::new(&program);
+        coverage.run(&mut Machine::new(&program), &[1]).expect("run");
+        let first = coverage.executed_cells().len();
+        coverage.run(&mut Machine::new(&program), &[5]).expect("run");
+        assert!(coverage.executed_cells().len() > first);
+        assert!(coverage.report().starts_with(&format!("2 runs over {} cells\n", program.len())));
+    }
+
+    #[test]
+    fn test_missing_input() {
+        let program: Vec<i64> = parse_program(COMPARE_TO_8);
+        let mut coverage = Coverage::new(&program);
+        match coverage.run(&mut Machine::new(&program), &[]).map_err(|e| e.into_kind()) {
+            Err(IntcodeError::MissingInput(0)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
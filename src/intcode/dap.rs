@@ -0,0 +1,590 @@
+//! A Debug Adapter Protocol server for intcode programs.
+//!
+//! The server speaks DAP over any reader and writer, which for an
+//! editor are the adapter's stdin and stdout (see the `intcode-dap`
+//! binary).  It debugs one program per session, run by a `Debugger`,
+//! so it can step backwards as well as forwards.
+//!
+//! `launch` takes the path of the program as `program`.  A path
+//! ending in `.asm` is assembled, and breakpoints can then be set on
+//! its lines; anything else is read as comma separated intcode, along
+//! with its source map if it has one, so that breakpoints can be set
+//! on the lines of the files the map names.
+//! Breakpoints on addresses are instruction breakpoints.  `input`
+//! optionally gives the program's input up front, and `stopOnEntry`
+//! stops before the first instruction.
+//!
+//! Continuing runs the program a slice of instructions at a time,
+//! answering requests in between, so `pause` can stop a program that
+//! never halts.  Stepping in runs a single instruction.  Stepping over
+//! a call, or out of the routine running, follows the calling
+//! convention in `library` (see `Debugger::step_in`), and runs in
+//! slices too.  Stepping back reverts an instruction, going back as
+//! far as the debugger's history limit allows.
+//!
+//! The only thread is the machine.  Its single frame has a Registers
+//! scope, with the pc, relative base and step count, and a Memory
+//! scope with the cells around the pc.  `readMemory` reads any cell,
+//! each as eight little-endian bytes, with the memory reference being
+//! the address of a cell.  When the program needs input it stops with
+//! reason `input`, and numbers typed into the debug console are
+//! queued as its input.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::result;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use serde_json::{json, Value};
+
+use super::assemble;
+use super::debug::{Debugger, Frame, Stop};
+use super::sourcemap::SourceMap;
+use super::{read_program, FileError, IntcodeError, Machine};
+
+const THREAD: i64 = 1;
+const REGISTERS: i64 = 1;
+const MEMORY: i64 = 2;
+// how many cells either side of the pc the Memory scope shows.
+const MEMORY_WINDOW: usize = 8;
+// how many instructions run between checks for requests while the
+// program is running.
+const SLICE: usize = 10_000;
+
+// a failed request, reported back to the client as its message.
+type Response = result::Result<Value, String>;
+
+struct Session {
+    debugger: Debugger,
+    source: Option<Arc<SourceMap>>,
+    // the addresses of line breakpoints, by the source they're in.
+    lines: BTreeMap<PathBuf, BTreeSet<usize>>,
+    addresses: BTreeSet<usize>,
+    stop_on_entry: bool,
+    // whether the program is running after a `continue` or a step
+    // over or out of a call.
+    running: bool,
+    // the frame of the call being stepped over or out of.
+    finishing: Option<Frame>,
+    // how much of the program's output has been sent to the client.
+    emitted: usize,
+}
+
+pub struct Server<O> {
+    // messages read by a thread of their own, so that they can be
+    // looked for while the program runs.
+    requests: Receiver<io::Result<Option<Value>>>,
+    writer: O,
+    seq: i64,
+    session: Option<Session>,
+    // events to send once the current request has been answered.
+    events: Vec<(String, Value)>,
+}
+
+/// Reads one message, or `None` at the end of the stream.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|e| invalid(e.to_string()))?);
+        }
+    }
+    let length = length.ok_or_else(|| invalid("missing Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| invalid(e.to_string()))
+}
+
+pub fn write_message<O: Write>(writer: &mut O, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn address(reference: &Value) -> result::Result<i64, String> {
+    reference
+        .as_str()
+        .and_then(|r| r.trim().parse().ok())
+        .ok_or_else(|| format!("invalid memory reference {}", reference))
+}
+
+impl<O: Write> Server<O> {
+    pub fn new<R: BufRead + Send + 'static>(mut reader: R, writer: O) -> Self {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || loop {
+            let message = read_message(&mut reader);
+            let more = matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || !more {
+                break;
+            }
+        });
+        Server {
+            requests,
+            writer,
+            seq: 0,
+            session: None,
+            events: vec![],
+        }
+    }
+
+    /// Serves requests until the client disconnects or closes the
+    /// stream.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let message = if self.running() {
+                match self.requests.try_recv() {
+                    Ok(message) => message,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice();
+                        self.send_events()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => Ok(None),
+                }
+            } else {
+                self.requests.recv().unwrap_or(Ok(None))
+            };
+            let request = match message? {
+                Some(request) => request,
+                None => break,
+            };
+            let command = request["command"].as_str().unwrap_or("").to_string();
+            let response = self.handle(&command, &request["arguments"]);
+            let mut message = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+                "success": response.is_ok(),
+            });
+            match response {
+                Ok(body) => message["body"] = body,
+                Err(error) => message["message"] = Value::String(error),
+            }
+            self.send(message)?;
+            self.send_events()?;
+            if command == "disconnect" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_events(&mut self) -> io::Result<()> {
+        for (event, body) in std::mem::take(&mut self.events) {
+            self.send(json!({ "type": "event", "event": event, "body": body }))?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.events.push((event.to_string(), body));
+    }
+
+    fn session(&mut self) -> result::Result<&mut Session, String> {
+        self.session.as_mut().ok_or_else(|| "no program has been launched".to_string())
+    }
+
+    fn running(&self) -> bool {
+        self.session.as_ref().is_some_and(|session| session.running)
+    }
+
+    // runs the program for a slice, and stops it if it stopped of its
+    // own accord.
+    fn run_slice(&mut self) {
+        let session = self.session.as_mut().expect("session");
+        let stop = match session.finishing {
+            Some(frame) => session.debugger.finish_for(frame, SLICE),
+            None => session.debugger.resume_for(SLICE),
+        };
+        if let Ok(Stop::Stepped) = stop {
+            return;
+        }
+        session.running = false;
+        self.stopped(stop);
+    }
+
+    fn handle(&mut self, command: &str, arguments: &Value) -> Response {
+        match command {
+            "initialize" => {
+                self.event("initialized", json!({}));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsStepBack": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                }))
+            }
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                let session = self.session()?;
+                if session.stop_on_entry {
+                    self.stopped(Ok(Stop::Start));
+                } else {
+                    session.run(None);
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "intcode" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY, "expensive": false },
+            ] })),
+            "variables" => self.variables(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "evaluate" => self.evaluate(arguments),
+            "continue" => {
+                self.session()?.run(None);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => self.next(),
+            "stepIn" => self.resume(Debugger::step),
+            "stepOut" => {
+                let session = self.session()?;
+                let frame = session.debugger.frame().ok_or("the relative base isn't at a frame")?;
+                session.run(Some(frame));
+                Ok(json!({}))
+            }
+            "stepBack" => self.resume(|debugger| Ok(debugger.reverse_step())),
+            "reverseContinue" => self.resume(|debugger| Ok(debugger.reverse_resume())),
+            "pause" => {
+                if self.running() {
+                    self.session()?.running = false;
+                    self.emit_output();
+                    self.event("stopped", json!({ "reason": "pause", "threadId": THREAD, "allThreadsStopped": true }));
+                }
+                Ok(json!({}))
+            }
+            "disconnect" => Ok(json!({})),
+            _ => Err(format!("unsupported request {}", command)),
+        }
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Response {
+        let path = PathBuf::from(arguments["program"].as_str().ok_or("launch needs a program")?);
+        let (program, source) = if path.extension().is_some_and(|e| e == "asm") {
+            let text = fs::read_to_string(&path).map_err(|e| FileError::Io(path.clone(), e).to_string())?;
+            let assembly = assemble::assemble(&text).map_err(|e| e.to_string())?;
+            let map = SourceMap::new(&path.to_string_lossy(), &assembly);
+            (assembly.program, Some(map))
+        } else {
+            let program = read_program(&path).map_err(|e| e.to_string())?;
+            let mut map = SourceMap::load_sidecar(&path).map_err(|e| e.to_string())?;
+            // the map's files are relative to where it's kept.
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            for span in map.iter_mut().flat_map(|map| &mut map.spans) {
+                span.file = directory.join(&span.file).to_string_lossy().into_owned();
+            }
+            (program, map)
+        };
+        let source = source.map(Arc::new);
+        let mut machine = Machine::new(&program);
+        machine.set_source_map(source.clone());
+        let mut debugger = Debugger::new(machine).map_err(|e| e.to_string())?;
+        for value in arguments["input"].as_array().into_iter().flatten() {
+            debugger.push_input(value.as_i64().ok_or("input must be numbers")?);
+        }
+        self.session = Some(Session {
+            debugger,
+            source,
+            lines: BTreeMap::new(),
+            addresses: BTreeSet::new(),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+            running: false,
+            finishing: None,
+            emitted: 0,
+        });
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Response {
+        let session = self.session()?;
+        let requested: Vec<usize> = arguments["breakpoints"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|b| b["line"].as_u64().map(|l| l as usize))
+            .collect();
+        let path = PathBuf::from(arguments["source"]["path"].as_str().unwrap_or(""));
+        let mut lines = BTreeSet::new();
+        let mut breakpoints = vec![];
+        for line in requested {
+            let found = session.source.as_ref().and_then(|map| {
+                let address = map.address(&path, line)?;
+                Some((address, map.span(address)?.line))
+            });
+            breakpoints.push(match found {
+                Some((address, actual)) => {
+                    lines.insert(address);
+                    json!({ "verified": true, "line": actual, "instructionReference": address.to_string() })
+                }
+                None => json!({ "verified": false, "line": line, "message": "no code on or after this line" }),
+            });
+        }
+        // each request replaces the breakpoints of one source only.
+        let old = session.breakpoints();
+        if lines.is_empty() {
+            session.lines.remove(&path);
+        } else {
+            session.lines.insert(path, lines);
+        }
+        session.sync_breakpoints(old);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Response {
+        let session = self.session()?;
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = vec![];
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let base = address(&breakpoint["instructionReference"])?;
+            let address = base
+                .checked_add(breakpoint["offset"].as_i64().unwrap_or(0))
+                .and_then(|address| usize::try_from(address).ok())
+                .filter(|address| *address < session.debugger.machine().memory_len());
+            if let Some(address) = address {
+                addresses.insert(address);
+            }
+            let reference = address.map_or_else(|| base.to_string(), |address| address.to_string());
+            breakpoints.push(json!({ "verified": address.is_some(), "instructionReference": reference }));
+        }
+        let old = session.breakpoints();
+        session.addresses = addresses;
+        session.sync_breakpoints(old);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&mut self) -> Response {
+        let session = self.session()?;
+        let pc = session.debugger.machine().pc();
+        let mut frame = json!({
+            "id": 1,
+            "name": format!("pc {}", pc),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": pc.to_string(),
+        });
+        if let Some(location) = session.source.as_ref().and_then(|map| map.locate(pc)) {
+            if let Some((symbol, offset)) = location.symbol {
+                frame["name"] = json!(if offset == 0 { symbol } else { format!("{}+{}", symbol, offset) });
+            }
+            frame["line"] = json!(location.line);
+            frame["column"] = json!(1);
+            frame["source"] = json!({ "path": location.file });
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Response {
+        let machine = self.session()?.debugger.machine();
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let variables: Vec<Value> = match arguments["variablesReference"].as_i64() {
+            Some(REGISTERS) => vec![
+                variable("pc".to_string(), machine.pc().to_string()),
+                variable("relative_base".to_string(), machine.relative_base().to_string()),
+                variable("steps".to_string(), machine.steps().to_string()),
+            ],
+            Some(MEMORY) => {
+                let start = machine.pc().saturating_sub(MEMORY_WINDOW);
+                let end = (machine.pc() + MEMORY_WINDOW + 1).min(machine.memory_len());
+                (start..end)
+                    .filter_map(|a| machine.read(a).map(|w| variable(format!("[{}]", a), w.to_string())))
+                    .collect()
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Response {
+        let machine = self.session()?.debugger.machine();
+        let reference = &arguments["memoryReference"];
+        let start = address(reference)?
+            .checked_mul(8)
+            .and_then(|start| start.checked_add(arguments["offset"].as_i64().unwrap_or(0)))
+            .ok_or_else(|| format!("memory reference {} is out of range", reference))?;
+        let too_many = || format!("can't read {} bytes from {}", arguments["count"], reference);
+        let count = i64::try_from(arguments["count"].as_u64().unwrap_or(0)).map_err(|_| too_many())?;
+        let end = start.checked_add(count).ok_or_else(too_many)?;
+        // bytes before the start of memory are left out, so the data
+        // starts at the first cell.
+        let first = start.max(0);
+        let bytes: Vec<u8> = (first..end.min(machine.memory_len() as i64 * 8))
+            .map(|byte| machine.read(byte as usize / 8).map_or(0, |w| w.to_le_bytes()[byte as usize % 8]))
+            .collect();
+        Ok(json!({
+            "address": first.to_string(),
+            "data": base64(&bytes),
+            "unreadableBytes": end.max(first) - first - bytes.len() as i64,
+        }))
+    }
+
+    fn evaluate(&mut self, arguments: &Value) -> Response {
+        let expression = arguments["expression"].as_str().unwrap_or("").trim();
+        let session = self.session()?;
+        if let Some(cell) = expression.strip_prefix('[').and_then(|e| e.strip_suffix(']')) {
+            let value = cell.trim().parse().ok().and_then(|a| session.debugger.machine().read(a));
+            let value = value.ok_or_else(|| format!("{} isn't in memory", expression))?;
+            return Ok(json!({ "result": value.to_string(), "variablesReference": 0 }));
+        }
+        let values = expression
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<i64>().map_err(|_| format!("expected numbers to input, got {:?}", word)))
+            .collect::<result::Result<Vec<_>, _>>()?;
+        for value in &values {
+            session.debugger.push_input(*value);
+        }
+        let pending = session.debugger.pending_input().count();
+        Ok(json!({ "result": format!("queued {} ({} pending)", values.len(), pending), "variablesReference": 0 }))
+    }
+
+    // steps, and if that called a routine, runs until it returns.
+    fn next(&mut self) -> Response {
+        let session = self.session()?;
+        session.running = false;
+        let stop = session.debugger.step_in();
+        match stop {
+            Ok((Stop::Stepped, Some(frame))) => session.run(Some(frame)),
+            _ => self.stopped(stop.map(|(stop, _)| stop)),
+        }
+        Ok(json!({}))
+    }
+
+    fn resume<F>(&mut self, action: F) -> Response
+    where F: FnOnce(&mut Debugger) -> result::Result<Stop, IntcodeError> {
+        let session = self.session()?;
+        session.running = false;
+        let stop = action(&mut session.debugger);
+        self.stopped(stop);
+        Ok(json!({}))
+    }
+
+    // tells the client about output it hasn't seen yet.
+    fn emit_output(&mut self) {
+        let session = self.session.as_mut().expect("session");
+        let output = session.debugger.output();
+        let fresh: Vec<String> = output.iter().skip(session.emitted).map(|v| format!("{}\n", v)).collect();
+        session.emitted = output.len();
+        for text in fresh {
+            self.event("output", json!({ "category": "stdout", "output": text }));
+        }
+    }
+
+    // tells the client about new output and why the program stopped.
+    fn stopped(&mut self, stop: result::Result<Stop, IntcodeError>) {
+        self.emit_output();
+        let (reason, text) = match stop {
+            Ok(Stop::Halted) => {
+                self.event("exited", json!({ "exitCode": 0 }));
+                self.event("terminated", json!({}));
+                return;
+            }
+            Ok(Stop::Stepped) | Ok(Stop::Returned) => ("step", None),
+            Ok(Stop::Breakpoint(_)) => ("breakpoint", None),
+            Ok(Stop::Watchpoint(_)) => ("data breakpoint", None),
+            Ok(Stop::Start) => ("entry", None),
+            Ok(Stop::AwaitingInput) => {
+                let prompt = "the program is waiting for input; enter numbers in the debug console\n";
+                self.event("output", json!({ "category": "console", "output": prompt }));
+                ("input", Some("Awaiting input".to_string()))
+            }
+            Err(error) => {
+                self.event("output", json!({ "category": "stderr", "output": format!("{}\n", error) }));
+                ("exception", Some(error.to_string()))
+            }
+        };
+        let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+        if let Some(text) = text {
+            body["description"] = json!(text);
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body);
+    }
+}
+
+impl Session {
+    // runs the program, until the routine with `frame` returns if
+    // there is one.
+    fn run(&mut self, frame: Option<Frame>) {
+        self.running = true;
+        self.finishing = frame;
+    }
+
+    fn breakpoints(&self) -> BTreeSet<usize> {
+        self.lines.values().flatten().chain(&self.addresses).copied().collect()
+    }
+
+    // moves the debugger's breakpoints from `old` to the current ones.
+    fn sync_breakpoints(&mut self, old: BTreeSet<usize>) {
+        for address in old {
+            self.debugger.clear_breakpoint(address);
+        }
+        for address in self.breakpoints() {
+            self.debugger.set_breakpoint(address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(&[255, 0, 1, 2]), "/wABAg==");
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &json!({ "seq": 1 })).expect("write");
+        assert_eq!(buffer, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        let mut reader = &buffer[..];
+        assert_eq!(read_message(&mut reader).expect("read"), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut reader).expect("read"), None);
+    }
+}
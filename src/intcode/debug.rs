@@ -0,0 +1,459 @@
+//! Steps an intcode machine forwards and backwards.
+//!
+//! The debugger keeps the machine's undo log switched on, so any
+//! instruction it has run can be reverted, and it owns the machine's
+//! input and output so that reversing over an input or output
+//! instruction puts the value back where it came from.  Running
+//! forwards again after reversing replays the same inputs, so the
+//! program retraces its steps exactly.  What a device does can't be
+//! undone, so a machine with devices attached can't be debugged.
+//!
+//! Programs that follow the calling convention in `library` can also
+//! be stepped a call at a time: a call is a taken jump that leaves the
+//! relative base at a frame returning to the instruction after the
+//! jump, and a routine has returned once the relative base is below
+//! its frame and the pc is at its return address.
+//!
+//! Only instructions run since the debugger took over the machine can
+//! be reverted, and only the most recent `HISTORY_LIMIT` of those
+//! unless the limit is changed: a program that never halts would
+//! otherwise fill memory with its undo log.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::convert::TryFrom;
+use std::result;
+
+use super::{Exit, IntcodeError, Machine, Word};
+
+/// How many instructions a debugger can reverse by default.
+pub const HISTORY_LIMIT: usize = 1_000_000;
+
+/// Why the debugger stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// a single instruction ran or was reverted.
+    Stepped,
+    /// the pc reached a breakpoint.
+    Breakpoint(usize),
+    /// an instruction that writes to a watched address ran or was
+    /// reverted.
+    Watchpoint(usize),
+    /// the program reached opcode 99.
+    Halted,
+    /// the program needs more input than has been pushed.
+    AwaitingInput,
+    /// there's nothing left to reverse.
+    Start,
+    /// the routine being finished returned.
+    Returned,
+}
+
+/// A routine's frame under the calling convention in `library`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// the relative base while the routine runs.
+    pub base: i64,
+    /// the address the routine returns to, kept at `[rb+0]`.
+    pub return_address: usize,
+}
+
+pub struct Debugger<W: Word = i64> {
+    machine: Machine<W>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    input: VecDeque<W>,
+    output: Vec<W>,
+}
+
+impl<W: Word> Debugger<W> {
+    /// Takes over `machine`, which mustn't have devices attached.
+    pub fn new(mut machine: Machine<W>) -> result::Result<Self, IntcodeError<W>> {
+        machine.set_history(true)?;
+        machine.set_history_limit(Some(HISTORY_LIMIT));
+        Ok(Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            input: VecDeque::new(),
+            output: vec![],
+        })
+    }
+
+    pub fn machine(&self) -> &Machine<W> {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> Machine<W> {
+        self.machine
+    }
+
+    /// Keeps only the most recent `limit` instructions reversible,
+    /// forgetting older ones now if there are more.  The last
+    /// instruction run can always be reversed.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.machine.set_history_limit(Some(limit.max(1)));
+    }
+
+    pub fn push_input(&mut self, value: W) {
+        self.input.push_back(value);
+    }
+
+    /// Input that's been pushed but not yet consumed.
+    pub fn pending_input(&self) -> impl Iterator<Item = &W> {
+        self.input.iter()
+    }
+
+    pub fn output(&self) -> &[W] {
+        &self.output
+    }
+
+    pub fn set_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn clear_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn watch(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    pub fn unwatch(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    fn watched(&self, write: &Option<(usize, W)>) -> Option<usize> {
+        match write {
+            Some((address, _)) if self.watchpoints.contains(address) => Some(*address),
+            _ => None,
+        }
+    }
+
+    /// Runs the instruction at the pc.
+    pub fn step(&mut self) -> result::Result<Stop, IntcodeError<W>> {
+        if self.machine.has_halted() {
+            return Ok(Stop::Halted);
+        }
+        match self.machine.step(self.input.front().into_iter())? {
+            Some(Exit::Halted { .. }) => return Ok(Stop::Halted),
+            Some(Exit::AwaitingInput { .. }) => return Ok(Stop::AwaitingInput),
+            Some(Exit::Output(output)) => self.output.push(output),
+            None => (),
+        }
+        let undo = self.machine.history().back().expect("instruction was recorded");
+        let stop = match self.watched(&undo.write) {
+            Some(address) => Stop::Watchpoint(address),
+            None => Stop::Stepped,
+        };
+        if undo.input.is_some() {
+            self.input.pop_front();
+        }
+        Ok(stop)
+    }
+
+    /// Runs the instruction at the pc like `step`, also giving the
+    /// frame of the routine it called if it was a call.
+    pub fn step_in(&mut self) -> result::Result<(Stop, Option<Frame>), IntcodeError<W>> {
+        let pc = self.machine.pc();
+        let jump = self.machine.read(pc).and_then(Word::to_i64).is_some_and(|i| i % 100 == 5 || i % 100 == 6);
+        let stop = self.step()?;
+        let frame = self.frame().filter(|frame| {
+            let next = pc + 3;
+            jump && self.machine.pc() != next && frame.return_address == next
+        });
+        Ok((stop, frame))
+    }
+
+    /// The frame the relative base points at, if it can be one.
+    pub fn frame(&self) -> Option<Frame> {
+        let base = self.machine.relative_base();
+        let return_address = self.machine.read(usize::try_from(base).ok()?)?.to_i64()?;
+        Some(Frame {
+            base,
+            return_address: usize::try_from(return_address).ok()?,
+        })
+    }
+
+    /// Runs until the routine with `frame` returns, stopping early
+    /// like `resume`.
+    pub fn finish(&mut self, frame: Frame) -> result::Result<Stop, IntcodeError<W>> {
+        loop {
+            match self.finish_for(frame, usize::MAX)? {
+                Stop::Stepped => (),
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    /// Like `finish`, but stops with `Stop::Stepped` once `steps`
+    /// instructions have run.
+    pub fn finish_for(&mut self, frame: Frame, steps: usize) -> result::Result<Stop, IntcodeError<W>> {
+        self.run_for(steps, Some(frame))
+    }
+
+    /// Runs until the pc reaches a breakpoint, a watched address is
+    /// written, or the program halts or needs input.  The instruction
+    /// at the pc always runs, even if there's a breakpoint on it.
+    pub fn resume(&mut self) -> result::Result<Stop, IntcodeError<W>> {
+        loop {
+            match self.resume_for(usize::MAX)? {
+                Stop::Stepped => (),
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    /// Like `resume`, but stops with `Stop::Stepped` once `steps`
+    /// instructions have run, so that a program that never halts can
+    /// be run a little at a time.
+    pub fn resume_for(&mut self, steps: usize) -> result::Result<Stop, IntcodeError<W>> {
+        self.run_for(steps, None)
+    }
+
+    // runs up to `steps` instructions, stopping once the routine with
+    // `frame` has returned if there is one.
+    fn run_for(&mut self, steps: usize, frame: Option<Frame>) -> result::Result<Stop, IntcodeError<W>> {
+        for _ in 0..steps {
+            match self.step()? {
+                Stop::Stepped if self.breakpoints.contains(&self.machine.pc()) => {
+                    return Ok(Stop::Breakpoint(self.machine.pc()))
+                }
+                Stop::Stepped if frame.is_some_and(|frame| self.returned(frame)) => return Ok(Stop::Returned),
+                Stop::Stepped => (),
+                stop => return Ok(stop),
+            }
+        }
+        Ok(Stop::Stepped)
+    }
+
+    fn returned(&self, frame: Frame) -> bool {
+        self.machine.relative_base() < frame.base && self.machine.pc() == frame.return_address
+    }
+
+    /// Reverts the most recently run instruction.
+    pub fn reverse_step(&mut self) -> Stop {
+        let undo = match self.machine.undo() {
+            Some(undo) => undo,
+            None => return Stop::Start,
+        };
+        if let Some(input) = &undo.input {
+            self.input.push_front(input.clone());
+        }
+        if undo.output.is_some() {
+            self.output.pop();
+        }
+        match self.watched(&undo.write) {
+            Some(address) => Stop::Watchpoint(address),
+            None => Stop::Stepped,
+        }
+    }
+
+    /// Reverts instructions until the pc reaches a breakpoint, a
+    /// write to a watched address is undone, or the program is back
+    /// at its start.  Stopping on a watchpoint leaves the pc at the
+    /// instruction that did the write.
+    pub fn reverse_resume(&mut self) -> Stop {
+        loop {
+            match self.reverse_step() {
+                Stop::Stepped if self.breakpoints.contains(&self.machine.pc()) => {
+                    return Stop::Breakpoint(self.machine.pc())
+                }
+                Stop::Stepped => (),
+                stop => return stop,
+            }
+        }
+    }
+
+    /// Moves forwards or backwards until exactly `step` instructions
+    /// have run, ignoring breakpoints and watchpoints.  Going forwards
+    /// stops early if the program halts or needs input, and going
+    /// backwards stops with `Stop::Start` at the first instruction the
+    /// debugger saw run.
+    pub fn seek(&mut self, step: usize) -> result::Result<Stop, IntcodeError<W>> {
+        while self.machine.steps() > step {
+            if self.reverse_step() == Stop::Start {
+                return Ok(Stop::Start);
+            }
+        }
+        while self.machine.steps() < step {
+            match self.step()? {
+                Stop::Stepped | Stop::Watchpoint(_) => (),
+                stop => return Ok(stop),
+            }
+        }
+        Ok(if step == 0 { Stop::Start } else { Stop::Stepped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::library;
+
+    // reads two numbers, outputs their sum and then their product,
+    // keeping both in a scratch cell at 19.
+    const PROGRAM: [i64; 20] = [3, 17, 3, 18, 1, 17, 18, 19, 4, 19, 2, 17, 18, 19, 4, 19, 99, 0, 0, 0];
+
+    fn debugger() -> Debugger {
+        let mut debugger = Debugger::new(Machine::new(&PROGRAM)).expect("debugger");
+        debugger.push_input(3);
+        debugger.push_input(4);
+        debugger
+    }
+
+    #[test]
+    fn test_reverse_step_restores_state() {
+        let mut debugger = debugger();
+        let mut states = vec![];
+        loop {
+            let machine = debugger.machine();
+            states.push((
+                machine.pc(),
+                machine.steps(),
+                machine.memory(0..32).expect("memory").to_vec(),
+                debugger.output().to_vec(),
+                debugger.pending_input().cloned().collect::<Vec<_>>(),
+            ));
+            if debugger.step().expect("step") == Stop::Halted {
+                break;
+            }
+        }
+        assert_eq!(debugger.output(), &[7, 12]);
+        assert!(debugger.machine().has_halted());
+        while let Some(state) = states.pop() {
+            let machine = debugger.machine();
+            assert_eq!(machine.pc(), state.0);
+            assert_eq!(machine.steps(), state.1);
+            assert_eq!(machine.memory(0..32).expect("memory"), &state.2[..]);
+            assert_eq!(debugger.output(), &state.3[..]);
+            assert_eq!(debugger.pending_input().cloned().collect::<Vec<_>>(), state.4);
+            debugger.reverse_step();
+        }
+        assert_eq!(debugger.reverse_step(), Stop::Start);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger();
+        debugger.set_breakpoint(8);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Breakpoint(8));
+        assert_eq!(debugger.output(), &[] as &[i64]);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Halted);
+        assert_eq!(debugger.output(), &[7, 12]);
+        assert_eq!(debugger.reverse_resume(), Stop::Breakpoint(8));
+        assert_eq!(debugger.machine().steps(), 3);
+        assert_eq!(debugger.output(), &[] as &[i64]);
+        assert!(debugger.clear_breakpoint(8));
+        assert_eq!(debugger.reverse_resume(), Stop::Start);
+        assert_eq!(debugger.pending_input().cloned().collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_resume_for() {
+        let mut debugger = debugger();
+        debugger.set_breakpoint(14);
+        assert_eq!(debugger.resume_for(3).expect("resume"), Stop::Stepped);
+        assert_eq!(debugger.machine().steps(), 3);
+        assert_eq!(debugger.resume_for(0).expect("resume"), Stop::Stepped);
+        assert_eq!(debugger.resume_for(100).expect("resume"), Stop::Breakpoint(14));
+        assert_eq!(debugger.resume_for(100).expect("resume"), Stop::Halted);
+    }
+
+    #[test]
+    fn test_reverse_to_corrupting_write() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.resume().expect("resume"), Stop::Halted);
+        debugger.watch(19);
+        // the multiply is the last write to the scratch cell.
+        assert_eq!(debugger.reverse_resume(), Stop::Watchpoint(19));
+        assert_eq!(debugger.machine().pc(), 10);
+        assert_eq!(debugger.machine().read(19), Some(&7));
+        assert_eq!(debugger.reverse_resume(), Stop::Watchpoint(19));
+        assert_eq!(debugger.machine().pc(), 4);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Watchpoint(19));
+        assert_eq!(debugger.machine().read(19), Some(&7));
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.seek(4).expect("seek"), Stop::Stepped);
+        assert_eq!(debugger.output(), &[7]);
+        assert_eq!(debugger.seek(1).expect("seek"), Stop::Stepped);
+        assert_eq!(debugger.machine().pc(), 2);
+        assert_eq!(debugger.pending_input().cloned().collect::<Vec<_>>(), vec![4]);
+        assert_eq!(debugger.seek(100).expect("seek"), Stop::Halted);
+        assert_eq!(debugger.machine().steps(), 6);
+        assert_eq!(debugger.seek(0).expect("seek"), Stop::Start);
+        assert_eq!(debugger.machine().pc(), 0);
+    }
+
+    #[test]
+    fn test_seek_before_debugging_started() {
+        // the first input ran before the debugger took over, so it
+        // can't be reversed.
+        let mut machine = Machine::new(&PROGRAM);
+        machine.step([3].iter()).expect("step");
+        let mut debugger = Debugger::new(machine).expect("debugger");
+        assert_eq!(debugger.seek(0).expect("seek"), Stop::Start);
+        assert_eq!(debugger.machine().steps(), 1);
+        assert_eq!(debugger.machine().pc(), 2);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut debugger = debugger();
+        debugger.set_history_limit(2);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Halted);
+        assert_eq!(debugger.machine().history().len(), 2);
+        assert_eq!(debugger.reverse_step(), Stop::Stepped);
+        assert_eq!(debugger.reverse_step(), Stop::Stepped);
+        assert_eq!(debugger.reverse_step(), Stop::Start);
+        assert_eq!(debugger.machine().steps(), 4);
+        assert_eq!(debugger.output(), &[7]);
+        // shrinking the limit forgets the oldest instructions.
+        assert_eq!(debugger.resume().expect("resume"), Stop::Halted);
+        debugger.set_history_limit(1);
+        assert_eq!(debugger.machine().history().len(), 1);
+        assert_eq!(debugger.seek(0).expect("seek"), Stop::Start);
+        assert_eq!(debugger.machine().steps(), 5);
+    }
+
+    #[test]
+    fn test_finishing_calls() {
+        let assembly = library::build::<i64>(library::PREBUILT[0].main, &[&library::MULTIPLY]).expect("build");
+        let back = assembly.symbols["main.back"];
+        let mut debugger = Debugger::new(Machine::new(&assembly.program)).expect("debugger");
+        debugger.push_input(6);
+        debugger.push_input(7);
+        // the jump to multiply, after main has moved the relative base
+        // to multiply's frame.
+        debugger.set_breakpoint(back - 3);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Breakpoint(back - 3));
+        let base = debugger.machine().relative_base();
+        let (stop, frame) = debugger.step_in().expect("step");
+        assert_eq!(stop, Stop::Stepped);
+        assert_eq!(debugger.machine().pc(), assembly.symbols["multiply"]);
+        let frame = frame.expect("a call");
+        assert_eq!(frame, Frame { base, return_address: back });
+        assert_eq!(debugger.frame(), Some(frame));
+        // multiply's own recursive calls return to it, not to main.
+        assert_eq!(debugger.finish(frame).expect("finish"), Stop::Returned);
+        assert_eq!(debugger.machine().pc(), back);
+        assert_eq!(debugger.machine().relative_base(), base - 2);
+        assert_eq!(debugger.step_in().expect("step"), (Stop::Stepped, None));
+        assert_eq!(debugger.output(), &[42]);
+    }
+
+    #[test]
+    fn test_awaiting_input() {
+        let mut debugger = Debugger::new(Machine::new(&PROGRAM)).expect("debugger");
+        debugger.push_input(3);
+        assert_eq!(debugger.resume().expect("resume"), Stop::AwaitingInput);
+        assert_eq!(debugger.machine().pc(), 2);
+        debugger.push_input(5);
+        assert_eq!(debugger.resume().expect("resume"), Stop::Halted);
+        assert_eq!(debugger.output(), &[8, 15]);
+    }
+}
@@ -0,0 +1,698 @@
+//! Lifts an intcode program into C-like pseudocode.
+//!
+//! Code is found by following control flow from address 0, so
+//! anything that's never reached is treated as data.  Jump targets
+//! are taken from immediate operands, or from position operands whose
+//! cells nothing writes to; a jump through anything else is computed
+//! and shown as `goto *target`.  Conditional jumps become `if`,
+//! `while` and `do ... while` wherever the jumps nest cleanly, and
+//! `goto` everywhere else.  A jump made just after storing the
+//! address that follows it is a `call`, and that address is code.
+//!
+//! Operands are named after what they refer to: `const_N` for cells
+//! that are read but never written, `var_N` for cells that are
+//! written, `mem[N]` for cells inside the code itself and `local_N`
+//! (or `local_mN` for negative offsets) for relative base slots.
+//! Relative mode writes are assumed to stay on the stack.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use super::{parameters, Word};
+
+// how many values of a data region to show.
+const DATA_PREVIEW: usize = 8;
+
+// how many times the analysis follows control flow again before
+// giving up on it settling.
+const ROUNDS: usize = 8;
+
+fn address<W: Word>(word: &W) -> Option<usize> {
+    word.to_i64().and_then(|w| usize::try_from(w).ok())
+}
+
+pub(super) struct Op<W> {
+    pub(super) address: usize,
+    pub(super) opcode: i64,
+    pub(super) modes: [i64; 3],
+    pub(super) params: Vec<W>,
+}
+
+impl<W: Word> Op<W> {
+    pub(super) fn decode(program: &[W], address: usize) -> Option<Self> {
+        let instruction = program.get(address)?.to_i64()?;
+        if instruction < 0 {
+            return None;
+        }
+        let opcode = instruction % 100;
+        let n = parameters(opcode)?;
+        let modes = [instruction / 100 % 10, instruction / 1000 % 10, instruction / 10000 % 10];
+        if instruction / 100 >= 10i64.pow(n as u32) || modes.iter().any(|m| *m > 2) {
+            return None;
+        }
+        let op = Op {
+            address,
+            opcode,
+            modes,
+            params: program.get(address + 1..address + 1 + n)?.to_vec(),
+        };
+        match op.destination() {
+            Some(n) if op.modes[n] == 1 => None,
+            _ => Some(op),
+        }
+    }
+
+    pub(super) fn next(&self) -> usize {
+        self.address + 1 + self.params.len()
+    }
+
+    // the parameter the instruction writes through.
+    pub(super) fn destination(&self) -> Option<usize> {
+        match self.opcode {
+            1 | 2 | 7 | 8 => Some(2),
+            3 => Some(0),
+            _ => None,
+        }
+    }
+
+    pub(super) fn written(&self) -> Option<usize> {
+        match self.destination() {
+            Some(n) if self.modes[n] == 0 => address(&self.params[n]),
+            _ => None,
+        }
+    }
+
+    pub(super) fn is_jump(&self) -> bool {
+        self.opcode == 5 || self.opcode == 6
+    }
+
+    // whether a jump is taken, if that's known without running it.
+    pub(super) fn taken(&self) -> Option<bool> {
+        if !self.is_jump() || self.modes[0] != 1 {
+            return None;
+        }
+        Some((self.params[0] != W::from(0)) == (self.opcode == 5))
+    }
+
+    fn target(&self, program: &[W], written: &BTreeSet<usize>) -> Option<usize> {
+        match self.modes[1] {
+            1 => address(&self.params[1]),
+            0 => match address(&self.params[1]) {
+                Some(cell) if !written.contains(&cell) => program.get(cell).and_then(address),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// follows control flow from address 0 and `entries`, returning the
+// instructions found by address.
+fn reach<W: Word>(
+    program: &[W],
+    written: &BTreeSet<usize>,
+    entries: &BTreeSet<usize>,
+) -> BTreeMap<usize, Op<W>> {
+    let mut ops = BTreeMap::new();
+    let mut pending: Vec<usize> = entries.iter().copied().collect();
+    pending.push(0);
+    while let Some(address) = pending.pop() {
+        if ops.contains_key(&address) {
+            continue;
+        }
+        let op = match Op::decode(program, address) {
+            Some(op) => op,
+            None => continue,
+        };
+        if op.is_jump() && op.taken() != Some(false) {
+            if let Some(target) = op.target(program, written) {
+                pending.push(target);
+            }
+        }
+        if op.opcode != 99 && op.taken() != Some(true) {
+            pending.push(op.next());
+        }
+        ops.insert(address, op);
+    }
+    ops
+}
+
+// addresses just after an unconditional jump that the program stores
+// as a constant, which is how a call pushes its return address.
+fn return_sites<W: Word>(ops: &BTreeMap<usize, Op<W>>) -> BTreeSet<usize> {
+    let after_jumps: BTreeSet<usize> = ops
+        .values()
+        .filter(|op| op.taken() == Some(true))
+        .map(Op::next)
+        .collect();
+    ops.values()
+        .filter_map(|op| match (op.opcode, op.modes[0], op.modes[1]) {
+            (1, 1, 1) if op.params[0] == W::from(0) => address(&op.params[1]),
+            (1, 1, 1) if op.params[1] == W::from(0) => address(&op.params[0]),
+            (2, 1, 1) if op.params[0] == W::from(1) => address(&op.params[1]),
+            (2, 1, 1) if op.params[1] == W::from(1) => address(&op.params[0]),
+            _ => None,
+        })
+        .filter(|site| after_jumps.contains(site))
+        .collect()
+}
+
+/// What following a program's control flow finds.
+pub(super) struct Analysis<W> {
+    /// the instructions reached, by address.
+    pub(super) ops: BTreeMap<usize, Op<W>>,
+    /// the cells they write to through position mode.
+    pub(super) written: BTreeSet<usize>,
+    /// the return addresses of calls.
+    pub(super) entries: BTreeSet<usize>,
+    /// whether the analysis settled.  If it didn't, there may be code
+    /// and writes it hasn't found.
+    pub(super) settled: bool,
+}
+
+impl<W: Word> Analysis<W> {
+    /// Whether any instruction reached might jump somewhere the
+    /// analysis can't tell, and so to code it hasn't found.
+    pub(super) fn computed_jumps(&self, program: &[W]) -> bool {
+        self.ops.values().any(|op| {
+            op.is_jump() && op.taken() != Some(false) && op.target(program, &self.written).is_none()
+        })
+    }
+}
+
+// finds the program's instructions, along with the cells they write
+// to and the return addresses of calls, following control flow at
+// most `rounds` more times.
+fn settle<W: Word>(program: &[W], rounds: usize) -> Analysis<W> {
+    // jumps through cells that turn out to be written aren't static
+    // after all, and return addresses lead to more code, so keep going
+    // until both settle.
+    let mut written = BTreeSet::new();
+    let mut entries = BTreeSet::new();
+    let mut ops = reach(program, &written, &entries);
+    let mut settled = false;
+    for _ in 0..rounds {
+        let now: BTreeSet<usize> = ops.values().filter_map(Op::written).collect();
+        let sites = return_sites(&ops);
+        if now == written && sites == entries {
+            settled = true;
+            break;
+        }
+        written = now;
+        entries = sites;
+        ops = reach(program, &written, &entries);
+    }
+    Analysis {
+        ops,
+        written,
+        entries,
+        settled,
+    }
+}
+
+pub(super) fn analyse<W: Word>(program: &[W]) -> Analysis<W> {
+    settle(program, ROUNDS)
+}
+
+/// The instructions control flow can be seen to reach, by address.
+/// They may not be all of them; see `Analysis::settled`.
+pub(super) fn instructions<W: Word>(program: &[W]) -> Vec<Op<W>> {
+    analyse(program).ops.into_values().collect()
+}
+
+struct Decompiler<'a, W> {
+    program: &'a [W],
+    ops: Vec<Op<W>>,
+    // cells taken up by instructions.
+    code: BTreeSet<usize>,
+    written: BTreeSet<usize>,
+    // return addresses of calls.
+    returns: BTreeSet<usize>,
+    constants: BTreeSet<usize>,
+    labels: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+    settled: bool,
+    out: String,
+}
+
+impl<'a, W: Word> Decompiler<'a, W> {
+    fn new(program: &'a [W], analysis: Analysis<W>) -> Self {
+        let Analysis {
+            ops,
+            written,
+            entries,
+            settled,
+        } = analysis;
+        let code = ops
+            .values()
+            .flat_map(|op| op.address..op.next())
+            .collect();
+        Decompiler {
+            program,
+            ops: ops.into_values().collect(),
+            code,
+            written,
+            returns: entries,
+            constants: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+            settled,
+            out: String::new(),
+        }
+    }
+
+    fn position(&mut self, word: &W) -> String {
+        match address(word) {
+            Some(cell) if self.code.contains(&cell) => format!("mem[{}]", cell),
+            Some(cell) if self.written.contains(&cell) || cell >= self.program.len() => {
+                format!("var_{}", cell)
+            }
+            Some(cell) => {
+                self.constants.insert(cell);
+                format!("const_{}", cell)
+            }
+            None => format!("mem[{}]", word),
+        }
+    }
+
+    fn operand(&mut self, op: usize, n: usize) -> String {
+        let word = self.ops[op].params[n].clone();
+        match self.ops[op].modes[n] {
+            1 => word.to_string(),
+            2 => match word.to_i64() {
+                Some(offset) if offset < 0 => format!("local_m{}", -(offset as i128)),
+                _ => format!("local_{}", word),
+            },
+            _ => self.position(&word),
+        }
+    }
+
+    // the condition under which a jump is taken, or isn't.
+    fn condition(&mut self, op: usize, taken: bool) -> String {
+        let test = self.operand(op, 0);
+        let nonzero = (self.ops[op].opcode == 5) == taken;
+        format!("{} {} 0", test, if nonzero { "!=" } else { "==" })
+    }
+
+    fn target(&self, op: usize) -> Option<usize> {
+        self.ops[op].target(self.program, &self.written)
+    }
+
+    fn is_call(&self, op: usize) -> bool {
+        self.ops[op].taken() == Some(true) && self.returns.contains(&self.ops[op].next())
+    }
+
+    fn index(&self, address: usize, start: usize, end: usize) -> Option<usize> {
+        (start..end).find(|i| self.ops[*i].address == address)
+    }
+
+    // the address just past a range of instructions.
+    fn end_address(&self, end: usize) -> usize {
+        match self.ops.get(end) {
+            Some(op) => op.address,
+            None => self.ops.last().map(|op| op.next()).unwrap_or(0),
+        }
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        let _ = writeln!(self.out, "{}{}", "    ".repeat(depth), text);
+    }
+
+    fn statement(&mut self, op: usize) -> String {
+        let opcode = self.ops[op].opcode;
+        let comment = match self.ops[op].written() {
+            Some(cell) if self.code.contains(&cell) => " // modifies code",
+            _ => "",
+        };
+        let text = match opcode {
+            1 | 2 | 7 | 8 => {
+                let (a, b) = (self.operand(op, 0), self.operand(op, 1));
+                let destination = self.operand(op, 2);
+                match (opcode, a.as_str(), b.as_str()) {
+                    (1, "0", _) | (2, "1", _) => format!("{} = {};", destination, b),
+                    (1, _, "0") | (2, _, "1") => format!("{} = {};", destination, a),
+                    _ => {
+                        let operator = match opcode {
+                            1 => "+",
+                            2 => "*",
+                            7 => "<",
+                            _ => "==",
+                        };
+                        format!("{} = {} {} {};", destination, a, operator, b)
+                    }
+                }
+            }
+            3 => format!("{} = input();", self.operand(op, 0)),
+            4 => format!("output({});", self.operand(op, 0)),
+            9 => format!("rb += {};", self.operand(op, 0)),
+            _ => "halt;".to_string(),
+        };
+        text + comment
+    }
+
+    // a jump that isn't part of a structured statement.
+    fn jump(&mut self, op: usize, loops: &[(usize, usize)]) -> Option<String> {
+        if self.is_call(op) {
+            return Some(match self.target(op) {
+                Some(target) => {
+                    self.gotos.insert(target);
+                    format!("call label_{};", target)
+                }
+                None => format!("call *{};", self.operand(op, 1)),
+            });
+        }
+        let go = match self.target(op) {
+            Some(target) => match loops.last() {
+                Some((head, _)) if *head == target => "continue;".to_string(),
+                Some((_, exit)) if *exit == target => "break;".to_string(),
+                _ => {
+                    self.gotos.insert(target);
+                    format!("goto label_{};", target)
+                }
+            },
+            None => {
+                let target = self.operand(op, 1);
+                format!("goto *{};", target)
+            }
+        };
+        match self.ops[op].taken() {
+            Some(true) => Some(go),
+            Some(false) => None,
+            None => Some(format!("if ({}) {}", self.condition(op, true), go)),
+        }
+    }
+
+    // the instruction at the end of `start..end` that jumps back to
+    // `start`, if any.
+    fn back_edge(&self, start: usize, end: usize) -> Option<usize> {
+        let head = self.ops[start].address;
+        (start..end).rev().find(|k| {
+            self.ops[*k].is_jump()
+                && self.ops[*k].taken() != Some(false)
+                && !self.is_call(*k)
+                && self.target(*k) == Some(head)
+        })
+    }
+
+    fn block(&mut self, start: usize, end: usize, depth: usize, loops: &mut Vec<(usize, usize)>) {
+        let mut i = start;
+        while i < end {
+            let address = self.ops[i].address;
+            // a loop's label goes before the loop, not inside it.
+            if self.labels.contains(&address) && loops.last().map(|(head, _)| *head) != Some(address) {
+                self.line(depth, &format!("label_{}:", address));
+            }
+            if let Some(k) = self.back_edge(i, end) {
+                i = self.structured_loop(i, k, depth, loops);
+                continue;
+            }
+            if let Some(next) = self.structured_if(i, end, depth, loops) {
+                i = next;
+                continue;
+            }
+            if self.ops[i].is_jump() {
+                let next = self.ops[i].next();
+                if self.ops[i].taken() == Some(true) && self.target(i) == Some(next) {
+                    // jumping to the next instruction does nothing.
+                } else if let Some(text) = self.jump(i, loops) {
+                    self.line(depth, &text);
+                }
+            } else {
+                let text = self.statement(i);
+                self.line(depth, &text);
+            }
+            i += 1;
+        }
+    }
+
+    // a loop whose body is `head..back` and which jumps back from
+    // `back`.  returns the index after the loop.
+    fn structured_loop(
+        &mut self,
+        head: usize,
+        back: usize,
+        depth: usize,
+        loops: &mut Vec<(usize, usize)>,
+    ) -> usize {
+        let exit = self.ops[back].next();
+        loops.push((self.ops[head].address, exit));
+        let first = &self.ops[head];
+        let leading_exit = first.is_jump() && first.taken().is_none() && self.target(head) == Some(exit);
+        if self.ops[back].taken() == Some(true) && leading_exit && head < back {
+            let condition = self.condition(head, false);
+            self.line(depth, &format!("while ({}) {{", condition));
+            self.block(head + 1, back, depth + 1, loops);
+            self.line(depth, "}");
+        } else if self.ops[back].taken() == Some(true) {
+            self.line(depth, "loop {");
+            self.block(head, back, depth + 1, loops);
+            self.line(depth, "}");
+        } else {
+            self.line(depth, "do {");
+            self.block(head, back, depth + 1, loops);
+            let condition = self.condition(back, true);
+            self.line(depth, &format!("}} while ({});", condition));
+        }
+        loops.pop();
+        back + 1
+    }
+
+    // a conditional forward jump over the rest of an `if`, possibly
+    // followed by an `else`.  returns the index after the statement.
+    fn structured_if(
+        &mut self,
+        i: usize,
+        end: usize,
+        depth: usize,
+        loops: &mut Vec<(usize, usize)>,
+    ) -> Option<usize> {
+        if !self.ops[i].is_jump() || self.ops[i].taken().is_some() {
+            return None;
+        }
+        let target = self.target(i)?;
+        let loop_jump = loops.last().is_some_and(|(head, exit)| target == *head || target == *exit);
+        if target <= self.ops[i].address || loop_jump {
+            return None;
+        }
+        let range_end = self.end_address(end);
+        let then_end = if target == range_end { end } else { self.index(target, i + 1, end)? };
+        // a then branch that ends by jumping over what follows it has
+        // an else branch.
+        let otherwise = match then_end.checked_sub(1).filter(|last| *last > i) {
+            Some(last) if self.ops[last].taken() == Some(true) => match self.target(last) {
+                Some(e) if e > target && e == range_end => Some((last, end)),
+                Some(e) if e > target => self.index(e, then_end, end).map(|n| (last, n)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match otherwise {
+            // nothing but the jump over the else branch.
+            Some((last, else_end)) if last == i + 1 => {
+                let condition = self.condition(i, true);
+                self.line(depth, &format!("if ({}) {{", condition));
+                self.block(then_end, else_end, depth + 1, loops);
+                self.line(depth, "}");
+                Some(else_end)
+            }
+            Some((last, else_end)) => {
+                let condition = self.condition(i, false);
+                self.line(depth, &format!("if ({}) {{", condition));
+                self.block(i + 1, last, depth + 1, loops);
+                self.line(depth, "} else {");
+                self.block(then_end, else_end, depth + 1, loops);
+                self.line(depth, "}");
+                Some(else_end)
+            }
+            None => {
+                let condition = self.condition(i, false);
+                self.line(depth, &format!("if ({}) {{", condition));
+                self.block(i + 1, then_end, depth + 1, loops);
+                self.line(depth, "}");
+                Some(then_end)
+            }
+        }
+    }
+
+    fn body(&mut self) -> String {
+        self.out.clear();
+        let end = self.ops.len();
+        self.block(0, end, 0, &mut vec![]);
+        self.out.clone()
+    }
+
+    fn header(&self) -> String {
+        let mut header = String::new();
+        if !self.settled {
+            let _ = writeln!(header, "// the analysis didn't settle: some code may be shown as data");
+        }
+        for cell in &self.constants {
+            let _ = writeln!(header, "const const_{} = {};", cell, self.program[*cell]);
+        }
+        let mut cell = 0;
+        while cell < self.program.len() {
+            if self.code.contains(&cell) {
+                cell += 1;
+                continue;
+            }
+            let start = cell;
+            while cell < self.program.len() && !self.code.contains(&cell) {
+                cell += 1;
+            }
+            let words: Vec<String> = self.program[start..cell]
+                .iter()
+                .take(DATA_PREVIEW)
+                .map(|w| w.to_string())
+                .collect();
+            let more = if cell - start > DATA_PREVIEW { ", ..." } else { "" };
+            let _ = writeln!(header, "data {}..{} = [{}{}];", start, cell, words.join(", "), more);
+        }
+        header
+    }
+}
+
+/// Decompiles `program` into pseudocode.  If following its control
+/// flow doesn't settle, the pseudocode starts with a comment saying
+/// so.
+pub fn decompile<W: Word>(program: &[W]) -> String {
+    render(program, analyse(program))
+}
+
+fn render<W: Word>(program: &[W], analysis: Analysis<W>) -> String {
+    let mut decompiler = Decompiler::new(program, analysis);
+    // the first pass finds which addresses need labels.
+    decompiler.body();
+    decompiler.labels = decompiler.gotos.clone();
+    let body = decompiler.body();
+    let header = decompiler.header();
+    if header.is_empty() {
+        body
+    } else {
+        format!("{}\n{}", header, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::parse_program;
+
+    fn lines(program: &str) -> Vec<String> {
+        decompile(&parse_program(program)).lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_while() {
+        // counts down from the input.
+        assert_eq!(
+            lines("3,100,1006,100,14,4,100,1001,100,-1,100,1105,1,2,99"),
+            vec![
+                "var_100 = input();",
+                "while (var_100 != 0) {",
+                "    output(var_100);",
+                "    var_100 = var_100 + -1;",
+                "}",
+                "halt;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_do_while_and_locals() {
+        assert_eq!(
+            lines("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99"),
+            vec![
+                "do {",
+                "    rb += 1;",
+                "    output(local_m1);",
+                "    var_100 = var_100 + 1;",
+                "    var_101 = var_100 == 16;",
+                "} while (var_101 == 0);",
+                "halt;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constants_and_data() {
+        assert_eq!(
+            lines("3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9"),
+            vec![
+                "const const_14 = 1;",
+                "data 12..16 = [-1, 0, 1, 9];",
+                "",
+                "var_12 = input();",
+                "if (var_12 != 0) {",
+                "    var_13 = var_13 + const_14;",
+                "}",
+                "output(var_13);",
+                "halt;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_if_else() {
+        // outputs 1 for a positive input and 0 otherwise.
+        assert_eq!(
+            lines("3,100,1007,100,1,101,1005,101,16,104,1,1105,1,18,99,99,104,0,99"),
+            vec![
+                "data 14..16 = [99, 99];",
+                "",
+                "var_100 = input();",
+                "var_101 = var_100 < 1;",
+                "if (var_101 == 0) {",
+                "    output(1);",
+                "} else {",
+                "    output(0);",
+                "}",
+                "halt;",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        assert_eq!(lines("1101,1,98,4,1,0,0,0")[0], "mem[4] = 1 + 98; // modifies code");
+    }
+
+    #[test]
+    fn test_unsettled_analysis() {
+        // the store to 100 is only found on the first round.
+        let program = parse_program("3,100,4,100,99");
+        assert!(!settle(&program, 1).settled);
+        assert!(settle(&program, 2).settled);
+        let text = render(&program, settle(&program, 1));
+        assert!(text.starts_with("// the analysis didn't settle"), "{}", text);
+        assert!(!decompile(&program).contains("didn't settle"));
+    }
+
+    #[test]
+    fn test_boost() {
+        let boost = decompile(&parse_program(include_str!("../../input/2019/day9.txt").trim()));
+        // the recursive function that part 2 spends its time in.
+        let function = [
+            "label_922:",
+            "rb += 3;",
+            "var_63 = local_m2 < 3;",
+            "if (var_63 == 0) {",
+            "    local_1 = local_m2 + -1;",
+            "    local_0 = 942;",
+            "    call label_922;",
+            "    local_m1 = local_1;",
+            "    local_1 = local_m2 + -3;",
+            "    local_0 = 957;",
+            "    call label_922;",
+            "    local_m2 = local_1 + local_m1;",
+            "} else {",
+            "    local_m2 = local_m2;",
+            "}",
+            "rb += -3;",
+            "goto *local_0;",
+        ]
+        .join("\n");
+        assert!(boost.contains(&function), "{}", boost);
+        assert!(boost.contains("local_0 = 915;\ncall label_922;\n"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Peripherals mapped into an intcode machine's memory.
+//!
+//! A device attached with `Machine::attach` takes over a range of
+//! addresses: position and relative mode reads and writes in that
+//! range go to the device instead of to memory.  Instructions are
+//! still fetched from memory, as are immediate parameters, and
+//! `Machine::read` and friends look at memory too, so inspecting a
+//! machine never disturbs its devices.
+//!
+//! What a device does can't be undone, so a machine with devices
+//! attached can't keep an undo log, and can't be debugged.  Reading a
+//! device is a side effect of its own: an instruction that reads a
+//! device and then fails has still done the read.
+//!
+//! Devices must be `Send` and `Sync`, like the rest of a machine.  A
+//! device that's attached by way of an `Arc<Mutex<_>>` can still be
+//! looked at from outside, for instance to render a framebuffer after
+//! the program has drawn to it.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::result;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use image::{DynamicImage, ImageBuffer, ImageError, ImageFormat, Rgb, RgbImage};
+
+use super::{FileError, Word};
+
+pub trait Device<W: Word = i64>: Send + Sync {
+    /// The number of addresses the device occupies.
+    fn size(&self) -> usize;
+
+    /// Reads the cell `offset` addresses into the device.
+    fn read(&mut self, offset: usize) -> W;
+
+    fn write(&mut self, offset: usize, value: W);
+
+    /// Called after every instruction the machine runs.
+    fn tick(&mut self) {}
+
+    /// Called when the machine is reset.
+    fn reset(&mut self) {}
+}
+
+// a device that panicked part way through is still the best there is.
+fn lock<D>(device: &Mutex<D>) -> MutexGuard<'_, D> {
+    device.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<W: Word, D: Device<W>> Device<W> for Arc<Mutex<D>> {
+    fn size(&self) -> usize {
+        lock(self).size()
+    }
+
+    fn read(&mut self, offset: usize) -> W {
+        lock(self).read(offset)
+    }
+
+    fn write(&mut self, offset: usize, value: W) {
+        lock(self).write(offset, value)
+    }
+
+    fn tick(&mut self) {
+        lock(self).tick()
+    }
+
+    fn reset(&mut self) {
+        lock(self).reset()
+    }
+}
+
+// a device and the addresses it's attached at.
+pub(super) struct Mapping<W: Word> {
+    pub(super) start: usize,
+    pub(super) end: usize,
+    pub(super) device: Box<dyn Device<W>>,
+}
+
+// the device mapped at `address`, if any, and the offset into it.
+pub(super) fn mapped<W: Word>(
+    devices: &mut [Mapping<W>],
+    address: usize,
+) -> Option<(&mut Box<dyn Device<W>>, usize)> {
+    devices
+        .iter_mut()
+        .find(|m| m.start <= address && address < m.end)
+        .map(|m| (&mut m.device, address - m.start))
+}
+
+/// A grid of pixels, one cell each, stored row by row.  A pixel's
+/// value is its colour as `0xRRGGBB`; anything outside that range is
+/// drawn black.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<i64>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<i64> {
+        if x < self.width {
+            self.pixels.get(y * self.width + x).copied()
+        } else {
+            None
+        }
+    }
+
+    pub fn render(&self) -> RgbImage {
+        ImageBuffer::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let colour = self.pixels[y as usize * self.width + x as usize];
+            if (0..=0xff_ffff).contains(&colour) {
+                Rgb([(colour >> 16) as u8, (colour >> 8) as u8, colour as u8])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        })
+    }
+
+    /// Renders the framebuffer to an image file, in whatever format
+    /// the path's extension names.  The file isn't created if the
+    /// format isn't one that can be written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> result::Result<(), FileError> {
+        let path = path.as_ref();
+        let io = |e| FileError::Io(path.to_path_buf(), e);
+        let encoding = |e| match e {
+            ImageError::IoError(e) => io(e),
+            e => FileError::Image(path.to_path_buf(), e),
+        };
+        let format = ImageFormat::from_path(path).map_err(encoding)?;
+        let mut file = BufWriter::new(File::create(path).map_err(io)?);
+        DynamicImage::ImageRgb8(self.render())
+            .write_to(&mut file, format)
+            .map_err(encoding)?;
+        file.flush().map_err(io)
+    }
+}
+
+impl<W: Word> Device<W> for Framebuffer {
+    fn size(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn read(&mut self, offset: usize) -> W {
+        W::from(self.pixels[offset])
+    }
+
+    fn write(&mut self, offset: usize, value: W) {
+        self.pixels[offset] = value.to_i64().unwrap_or(-1);
+    }
+
+    fn reset(&mut self) {
+        for pixel in &mut self.pixels {
+            *pixel = 0;
+        }
+    }
+}
+
+/// A single cell counting the instructions run since it was attached
+/// or the machine was last reset.  Writing to it sets the count to the
+/// value written, or to 0 if that doesn't fit in an i64, and it goes
+/// on counting from there.
+#[derive(Debug, Default)]
+pub struct Clock {
+    count: i64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Clock::default()
+    }
+}
+
+impl<W: Word> Device<W> for Clock {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> W {
+        W::from(self.count)
+    }
+
+    fn write(&mut self, _: usize, value: W) {
+        self.count = value.to_i64().unwrap_or(0);
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+}
+
+/// A single cell that reads as a different non-negative number each
+/// time, from a xorshift generator.  Writing to it reseeds it, so a
+/// program can make its own runs repeatable.
+#[derive(Debug)]
+pub struct Random {
+    seed: u64,
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        let mut random = Random { seed: 0, state: 0 };
+        random.reseed(seed);
+        random
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // xorshift gets stuck at zero.
+        self.seed = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+        self.state = self.seed;
+    }
+
+    fn next(&mut self) -> i64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 1) as i64
+    }
+}
+
+impl<W: Word> Device<W> for Random {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> W {
+        W::from(self.next())
+    }
+
+    fn write(&mut self, _: usize, value: W) {
+        self.reseed(value.to_i64().unwrap_or(0) as u64);
+    }
+
+    fn reset(&mut self) {
+        self.state = self.seed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use crate::intcode::{Exit, IntcodeError, Machine};
+
+    #[test]
+    fn test_framebuffer() {
+        // draws a red pixel at (1, 0) and a white one at (0, 1), then
+        // reads the red one back out.
+        let program: [i64; 11] = [1101, 0, 0xff0000, 1001, 1101, 0, 0xffffff, 1002, 4, 1001, 99];
+        let screen = Arc::new(Mutex::new(Framebuffer::new(2, 2)));
+        let mut machine = Machine::new(&program);
+        machine.attach(1000, screen.clone()).expect("attach");
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Output(0xff0000));
+        assert_eq!(machine.read(1001), Some(&0));
+        let screen = screen.lock().expect("lock");
+        assert_eq!(screen.pixel(1, 0), Some(0xff0000));
+        let image = screen.render();
+        assert_eq!(image.get_pixel(1, 0), &Rgb([255, 0, 0]));
+        assert_eq!(image.get_pixel(0, 1), &Rgb([255, 255, 255]));
+        assert_eq!(image.get_pixel(0, 0), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn test_save_framebuffer() {
+        let mut screen = Framebuffer::new(2, 1);
+        Device::<i64>::write(&mut screen, 0, 0x00ff00);
+        let directory = env::temp_dir();
+        let path = directory.join(format!("intcode-framebuffer-{}.png", process::id()));
+        screen.save(&path).expect("save");
+        let image = image::open(&path).expect("open").to_rgb();
+        fs::remove_file(&path).expect("remove");
+        assert_eq!(image.get_pixel(0, 0), &Rgb([0, 255, 0]));
+        let unknown = directory.join(format!("intcode-framebuffer-{}.unknown", process::id()));
+        match screen.save(&unknown) {
+            Err(FileError::Image(path, ImageError::UnsupportedError(_))) => assert_eq!(path, unknown),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(!unknown.exists());
+    }
+
+    #[test]
+    fn test_clock() {
+        // outputs the clock, runs two more instructions and outputs it
+        // again.
+        let program: [i64; 9] = [4, 50, 1101, 0, 0, 49, 4, 50, 99];
+        let mut machine = Machine::new(&program);
+        machine.attach(50, Clock::new()).expect("attach");
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Output(0));
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Output(2));
+        machine.reset();
+        assert_eq!(machine.execute([].iter()).expect("execute"), Exit::Output(0));
+    }
+
+    #[test]
+    fn test_random() {
+        // seeds the generator from the input and outputs two numbers.
+        let program: [i64; 7] = [3, 20, 4, 20, 4, 20, 99];
+        let run = |seed: i64| {
+            let mut machine = Machine::new(&program);
+            machine.attach(20, Random::new(1)).expect("attach");
+            let seed = [seed];
+            let mut input = seed.iter();
+            let mut output = vec![];
+            while let Exit::Output(value) = machine.execute(&mut input).expect("execute") {
+                output.push(value);
+            }
+            output
+        };
+        let output = run(7);
+        assert_ne!(output[0], output[1]);
+        assert!(output.iter().all(|n| *n >= 0));
+        assert_eq!(run(7), output);
+        assert_ne!(run(8), output);
+    }
+
+    #[test]
+    fn test_attach_rejects_overlaps() {
+        let mut machine = Machine::<i64>::new(&[99]);
+        machine.attach(10, Framebuffer::new(4, 4)).expect("attach");
+        assert!(machine.attach(26, Clock::new()).is_ok());
+        assert!(machine.attach(20, Clock::new()).is_err());
+        assert!(machine.attach(5, Framebuffer::new(3, 3)).is_err());
+        assert!(machine.attach(machine.memory_len(), Clock::new()).is_err());
+    }
+
+    #[test]
+    fn test_devices_and_history_exclude_each_other() {
+        let mut machine = Machine::<i64>::new(&[99]);
+        machine.attach(10, Clock::new()).expect("attach");
+        match machine.set_history(true) {
+            Err(IntcodeError::DevicesAttached) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let mut machine = Machine::<i64>::new(&[99]);
+        machine.set_history(true).expect("history");
+        assert!(machine.attach(10, Clock::new()).is_err());
+        machine.set_history(false).expect("history");
+        machine.attach(10, Clock::new()).expect("attach");
+    }
+}
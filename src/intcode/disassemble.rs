@@ -0,0 +1,210 @@
+//! Lists an intcode program as one instruction per line.
+//!
+//! Instructions are those the decompiler finds by following control
+//! flow from address 0, along with any extra entry points the caller
+//! knows about, such as addresses a run actually executed.  Every
+//! other cell is listed as data, a few words to a line.
+//!
+//! Operands are written `5` for immediate mode, `[5]` for position
+//! mode and `[rb+5]` or `[rb-5]` for relative mode:
+//!
+//! ```text
+//!     0  in [9]
+//!     2  eq [9], [10], [9]
+//!     6  out [9]
+//!     8  halt
+//!     9  data -1, 8
+//! ```
+//!
+//! Given a source map, the listing also shows each label and the
+//! source line each instruction or `data` directive came from.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::decompile::{self, Op};
+use super::sourcemap::SourceMap;
+use super::Word;
+
+// how many words of data to a line.
+const DATA_WIDTH: usize = 8;
+
+pub(super) fn mnemonic(opcode: i64) -> &'static str {
+    match opcode {
+        1 => "add",
+        2 => "mul",
+        3 => "in",
+        4 => "out",
+        5 => "jnz",
+        6 => "jz",
+        7 => "lt",
+        8 => "eq",
+        9 => "arb",
+        _ => "halt",
+    }
+}
+
+pub(super) fn operand<W: Word>(mode: i64, word: &W) -> String {
+    match mode {
+        0 => format!("[{}]", word),
+        1 => word.to_string(),
+        _ => match word.to_i64() {
+            Some(offset) if offset < 0 => format!("[rb-{}]", -(offset as i128)),
+            _ => format!("[rb+{}]", word),
+        },
+    }
+}
+
+/// One line of a listing: an instruction, or a run of data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    /// how many cells the line covers.
+    pub len: usize,
+    /// the opcode, if the line is an instruction.
+    pub opcode: Option<i64>,
+    pub text: String,
+}
+
+pub(super) fn instruction<W: Word>(op: &Op<W>) -> Line {
+    let operands: Vec<String> = op
+        .params
+        .iter()
+        .enumerate()
+        .map(|(n, word)| operand(op.modes[n], word))
+        .collect();
+    let mut text = mnemonic(op.opcode).to_string();
+    if !operands.is_empty() {
+        text = format!("{} {}", text, operands.join(", "));
+    }
+    Line {
+        address: op.address,
+        len: op.next() - op.address,
+        opcode: Some(op.opcode),
+        text,
+    }
+}
+
+// lists the data from `start` to `end`, starting new lines at
+// `breaks` as well as every `DATA_WIDTH` words.
+fn data<W: Word>(program: &[W], start: usize, end: usize, breaks: &BTreeSet<usize>, lines: &mut Vec<Line>) {
+    let mut address = start;
+    while address < end {
+        let next = breaks.range(address + 1..end).next().copied().unwrap_or(end);
+        let len = (next - address).min(DATA_WIDTH);
+        let words: Vec<String> = program[address..address + len].iter().map(|w| w.to_string()).collect();
+        lines.push(Line {
+            address,
+            len,
+            opcode: None,
+            text: format!("data {}", words.join(", ")),
+        });
+        address += len;
+    }
+}
+
+// the instructions of `program`, as `lines` finds them.
+pub(super) fn ops<W: Word>(program: &[W], entries: &BTreeSet<usize>) -> Vec<Op<W>> {
+    let mut ops = decompile::instructions(program);
+    let mut taken: BTreeSet<usize> = ops.iter().flat_map(|op| op.address..op.next()).collect();
+    for entry in entries {
+        if let Some(op) = Op::decode(program, *entry) {
+            if (op.address..op.next()).all(|cell| !taken.contains(&cell)) {
+                taken.extend(op.address..op.next());
+                ops.push(op);
+            }
+        }
+    }
+    ops.sort_by_key(|op| op.address);
+    ops
+}
+
+/// Lists `program`, treating `entries` as code as well as whatever
+/// is reachable from address 0.  Where instructions overlap, the one
+/// found first wins, and static ones are found before entries.
+pub fn lines<W: Word>(program: &[W], entries: &BTreeSet<usize>) -> Vec<Line> {
+    listing(program, entries, &BTreeSet::new())
+}
+
+fn listing<W: Word>(program: &[W], entries: &BTreeSet<usize>, breaks: &BTreeSet<usize>) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = 0;
+    for op in &ops(program, entries) {
+        data(program, address, op.address, breaks, &mut lines);
+        lines.push(instruction(op));
+        address = op.next();
+    }
+    data(program, address, program.len(), breaks, &mut lines);
+    lines
+}
+
+pub fn disassemble<W: Word>(program: &[W]) -> String {
+    let mut out = String::new();
+    for line in lines(program, &BTreeSet::new()) {
+        let _ = writeln!(out, "{:>5}  {}", line.address, line.text);
+    }
+    out
+}
+
+/// Lists `program` with the labels and source lines from `map`.  Data
+/// is split where the source's directives were, so each line starts
+/// a span of the map where it can.
+pub fn disassemble_mapped<W: Word>(program: &[W], map: &SourceMap) -> String {
+    let breaks = map.spans.iter().map(|span| span.address).collect();
+    let mut out = String::new();
+    for line in listing(program, &BTreeSet::new(), &breaks) {
+        let span = map.span(line.address).filter(|span| span.address == line.address);
+        if let Some(symbol) = span.and_then(|span| span.symbol.as_ref()) {
+            let _ = writeln!(out, "{}:", symbol);
+        }
+        match span {
+            Some(span) => {
+                let _ = writeln!(out, "{:>5}  {:<24}; {}:{}", line.address, line.text, span.file, span.line);
+            }
+            None => {
+                let _ = writeln!(out, "{:>5}  {}", line.address, line.text);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble::assemble;
+    use crate::intcode::parse_program;
+
+    #[test]
+    fn test_disassemble() {
+        let program: Vec<i64> = parse_program("3,9,8,9,10,9,4,9,99,-1,8");
+        assert_eq!(
+            disassemble(&program),
+            "    0  in [9]\n    2  eq [9], [10], [9]\n    6  out [9]\n    8  halt\n    9  data -1, 8\n"
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        // the output is only reached through a jump not shown here.
+        let program: Vec<i64> = vec![109, -3, 204, -1, 1105, 1, 10, 104, 5, 99, 1106, 0, 9];
+        let text = |lines: Vec<Line>| lines.into_iter().map(|l| l.text).collect::<Vec<_>>();
+        assert_eq!(
+            text(lines(&program, &BTreeSet::new())),
+            vec!["arb -3", "out [rb-1]", "jnz 1, 10", "data 104, 5", "halt", "jz 0, 9"]
+        );
+        let listed = lines(&program, &[7, 8].iter().copied().collect());
+        assert_eq!(listed[3], Line { address: 7, len: 2, opcode: Some(4), text: "out 5".to_string() });
+        assert_eq!(text(listed)[4..], ["halt", "jz 0, 9"]);
+    }
+
+    #[test]
+    fn test_disassemble_mapped() {
+        let assembly = assemble::<i64>("loop:   in [n]\n        out [n]\n        halt\nn:      data 0\nm:      data 5").expect("assemble");
+        let map = SourceMap::new("echo.asm", &assembly);
+        assert_eq!(
+            disassemble_mapped(&assembly.program, &map),
+            "loop:\n    0  in [5]                  ; echo.asm:1\n    2  out [5]                 ; echo.asm:2\n    4  halt                    ; echo.asm:3\nn:\n    5  data 0                  ; echo.asm:4\nm:\n    6  data 5                  ; echo.asm:5\n"
+        );
+    }
+}
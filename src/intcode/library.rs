@@ -0,0 +1,294 @@
+//! A calling convention for intcode, and routines that follow it.
+//!
+//! Calls keep their state in frames on a stack that grows up from the
+//! end of the program, and the relative base points at the frame of
+//! whatever is running.  A frame starts with two words:
+//!
+//! - `[rb+0]`, the address to return to;
+//! - `[rb+1]`, the saved base: what to add to the relative base to get
+//!   back to the caller's frame.
+//!
+//! The arguments follow from `[rb+2]`, and a routine leaves its
+//! results in the same place, overwriting them.  Everything above that
+//! is the routine's own; everything below is the caller's, and stays
+//! as it was.  To call a routine with its frame `n` words into its
+//! own, a caller stores the arguments from `[rb+n+2]`, then:
+//!
+//! ```text
+//!         add back, 0, [rb+n]
+//!         add -n, 0, [rb+n+1]
+//!         arb n
+//!         jnz 1, routine
+//! back:   ; results are at [rb+n+2] on
+//! ```
+//!
+//! and a routine returns by restoring the base, keeping the return
+//! address in `rt.return` while it does:
+//!
+//! ```text
+//!         add [rb+0], 0, [rt.return]
+//!         arb [rb+1]
+//!         jnz 1, [rt.return]
+//! ```
+//!
+//! A program is `main`, called with the first frame, along with the
+//! routines it uses.  `source` puts those together with the start and
+//! end that every program needs, which is also what `compile`
+//! generates.  Each part exports the labels the others use, so the
+//! parts can also be assembled and linked as separate modules.
+
+use std::result;
+
+use super::assemble::{self, Assembly};
+use super::{parse_program_as, FileError, Word};
+
+/// Calls `main`, and halts when it returns.
+pub(super) const START: &str = "\
+; calls main with its frame at the bottom of the stack.
+        arb rt.stack
+        add rt.exit, 0, [rb+0]
+        add 0, 0, [rb+1]
+        jnz 1, main
+rt.exit:
+        halt
+";
+
+/// The cells the convention needs, which must come last.
+pub(super) const END: &str = "
+        export rt.return, rt.stack
+; where a return keeps its address while it restores the base.
+rt.return:
+        data 0
+; the stack grows up from here into the rest of memory.
+rt.stack:
+        data 0
+";
+
+/// A routine's assembler source, and the routines it calls.
+#[derive(Debug)]
+pub struct Routine {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub requires: &'static [&'static Routine],
+}
+
+pub const MULTIPLY: Routine = Routine {
+    name: "multiply",
+    source: include_str!("library/multiply.asm"),
+    requires: &[],
+};
+
+pub const DIVIDE: Routine = Routine {
+    name: "divide",
+    source: include_str!("library/divide.asm"),
+    requires: &[],
+};
+
+pub const PRINT_NUMBER: Routine = Routine {
+    name: "print_number",
+    source: include_str!("library/print_number.asm"),
+    requires: &[&DIVIDE],
+};
+
+pub const READ_LINE: Routine = Routine {
+    name: "read_line",
+    source: include_str!("library/read_line.asm"),
+    requires: &[],
+};
+
+pub const MEMCPY: Routine = Routine {
+    name: "memcpy",
+    source: include_str!("library/memcpy.asm"),
+    requires: &[],
+};
+
+pub const ROUTINES: [&Routine; 5] = [&MULTIPLY, &DIVIDE, &PRINT_NUMBER, &READ_LINE, &MEMCPY];
+
+/// The source of a program made of `main`, `routines` and whatever
+/// they call, each routine once.
+pub fn source(main: &str, routines: &[&Routine]) -> String {
+    let mut included: Vec<&Routine> = vec![];
+    let mut pending = routines.to_vec();
+    while let Some(routine) = pending.pop() {
+        if included.iter().all(|r| r.name != routine.name) {
+            included.push(routine);
+            pending.extend(routine.requires);
+        }
+    }
+    included.sort_by_key(|routine| ROUTINES.iter().position(|r| r.name == routine.name));
+    let mut source = START.to_string();
+    source.push_str(main);
+    for routine in included {
+        source.push('\n');
+        source.push_str(routine.source);
+    }
+    source.push_str(END);
+    source
+}
+
+/// Assembles a program made of `main` and `routines`.
+pub fn build<W: Word>(main: &str, routines: &[&Routine]) -> result::Result<Assembly<W>, FileError> {
+    assemble::assemble(&source(main, routines))
+}
+
+/// A program that runs one of the routines on its input, ready built.
+#[derive(Debug)]
+pub struct Prebuilt {
+    pub name: &'static str,
+    pub main: &'static str,
+    pub routines: &'static [&'static Routine],
+    pub image: &'static str,
+}
+
+impl Prebuilt {
+    pub fn source(&self) -> String {
+        source(self.main, self.routines)
+    }
+
+    pub fn program<W: Word>(&self) -> Vec<W> {
+        parse_program_as(self.image.trim())
+    }
+}
+
+pub const PREBUILT: [Prebuilt; 5] = [
+    Prebuilt {
+        name: "multiply",
+        main: include_str!("library/programs/multiply.asm"),
+        routines: &[&MULTIPLY],
+        image: include_str!("library/programs/multiply.intcode"),
+    },
+    Prebuilt {
+        name: "divide",
+        main: include_str!("library/programs/divide.asm"),
+        routines: &[&DIVIDE],
+        image: include_str!("library/programs/divide.intcode"),
+    },
+    Prebuilt {
+        name: "print_number",
+        main: include_str!("library/programs/print_number.asm"),
+        routines: &[&PRINT_NUMBER],
+        image: include_str!("library/programs/print_number.intcode"),
+    },
+    Prebuilt {
+        name: "read_line",
+        main: include_str!("library/programs/read_line.asm"),
+        routines: &[&READ_LINE, &PRINT_NUMBER],
+        image: include_str!("library/programs/read_line.intcode"),
+    },
+    Prebuilt {
+        name: "memcpy",
+        main: include_str!("library/programs/memcpy.asm"),
+        routines: &[&READ_LINE, &MEMCPY],
+        image: include_str!("library/programs/memcpy.intcode"),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::execute_with_input;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    fn run(name: &str, input: &[i64]) -> Vec<i64> {
+        let prebuilt = PREBUILT.iter().find(|p| p.name == name).expect("prebuilt");
+        let mut program = prebuilt.program();
+        execute_with_input(&mut program, input).map_err(|e| e.error).expect("run")
+    }
+
+    fn text(output: Vec<i64>) -> String {
+        output.into_iter().map(|c| c as u8 as char).collect()
+    }
+
+    fn ascii(text: &str) -> Vec<i64> {
+        text.bytes().map(i64::from).collect()
+    }
+
+    // run with `INTCODE_BLESS=1` to rebuild the programs after
+    // changing their sources.
+    #[test]
+    fn test_prebuilt_programs_are_current() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/intcode/library/programs");
+        for prebuilt in &PREBUILT {
+            let program = build::<i64>(prebuilt.main, prebuilt.routines).expect("build").program;
+            let image = program.iter().map(i64::to_string).collect::<Vec<_>>().join(",") + "\n";
+            let path = directory.join(format!("{}.intcode", prebuilt.name));
+            if env::var_os("INTCODE_BLESS").is_some() {
+                fs::write(&path, image).expect("write program");
+            } else {
+                assert!(image == prebuilt.image, "{} is out of date; rerun with INTCODE_BLESS=1", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_multiply() {
+        for (a, b) in &[
+            (0, 5),
+            (7, 0),
+            (6, 7),
+            (-6, 7),
+            (6, -7),
+            (-6, -7),
+            (1, 1 << 40),
+            (123456789, 987654321),
+            (1, i64::MIN),
+            (i64::MIN, 1),
+            (-1, i64::MAX),
+            (i64::MIN + 1, -1),
+        ] {
+            assert_eq!(run("multiply", &[*a, *b]), vec![a * b], "{} * {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_divide() {
+        for (n, d) in &[
+            (0, 5),
+            (7, 2),
+            (100, 7),
+            (-100, 7),
+            (100, -7),
+            (-100, -7),
+            (5, 9),
+            (i64::MAX, 3),
+            (1 << 62, 1),
+            (i64::MIN, 3),
+            (i64::MIN, -3),
+            (i64::MIN, 1),
+            (i64::MIN, i64::MIN),
+            (i64::MAX, i64::MIN),
+            (-5, i64::MIN),
+        ] {
+            assert_eq!(run("divide", &[*n, *d]), vec![n / d, n % d], "{} / {}", n, d);
+        }
+        assert_eq!(run("divide", &[42, 0]), vec![0, 42]);
+        assert_eq!(run("divide", &[i64::MIN, 0]), vec![0, i64::MIN]);
+    }
+
+    #[test]
+    fn test_print_number() {
+        for n in &[0, 7, 10, 1234567890, -45, -7, -10, i64::MAX, i64::MIN] {
+            assert_eq!(text(run("print_number", &[*n])), format!("{}\n", n));
+        }
+    }
+
+    #[test]
+    fn test_read_line_and_memcpy() {
+        assert_eq!(text(run("read_line", &ascii("hello\n"))), "5\n");
+        assert_eq!(text(run("read_line", &ascii("\n"))), "0\n");
+        assert_eq!(text(run("read_line", &ascii("twenty characters...\n"))), "16\n");
+        assert_eq!(text(run("memcpy", &ascii("copy me\n"))), "copy me\n");
+        assert_eq!(text(run("memcpy", &ascii("much too long to fit\n"))), "much too long to\n");
+    }
+
+    #[test]
+    fn test_source_includes_requirements_once() {
+        let source = source("main: halt", &[&PRINT_NUMBER, &DIVIDE]);
+        assert_eq!(source.matches("\ndivide:").count(), 1);
+        assert!(source.find("\ndivide:") < source.find("\nprint_number:"));
+        assert!(source.ends_with("rt.stack:\n        data 0\n"));
+        assemble::assemble::<i64>(&source).expect("assemble");
+    }
+}
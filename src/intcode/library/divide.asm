@@ -0,0 +1,98 @@
+; divide(n, d) -> (quotient, remainder)
+;
+; Rounds towards zero, as Rust's `/` and `%` do, so the remainder
+; takes n's sign.  Dividing by zero gives (0, n).  The work is binary
+; long division on n and d made positive: a helper doubles d on the
+; way down its recursion, and takes off whatever doubled d still fits
+; on the way back.
+        export divide
+divide:
+        jz [rb+3], divide.nothing
+        eq [rb+3], -9223372036854775808, [rb+6]
+        jnz [rb+6], divide.by_min
+        lt [rb+2], 0, [rb+5]
+        lt [rb+3], 0, [rb+4]
+        eq [rb+4], [rb+5], [rb+4]
+        lt [rb+3], 0, [rb+6]
+        jz [rb+6], divide.d_positive
+        mul [rb+3], -1, [rb+3]
+divide.d_positive:
+        ; -n would overflow for the most negative number, so that has
+        ; one d taken off first, and [rb+6] says so.
+        eq [rb+2], -9223372036854775808, [rb+6]
+        jz [rb+6], divide.n_taken
+        add [rb+2], [rb+3], [rb+2]
+divide.n_taken:
+        jz [rb+5], divide.n_positive
+        mul [rb+2], -1, [rb+2]
+divide.n_positive:
+        add [rb+2], 0, [rb+9]
+        add [rb+3], 0, [rb+10]
+        add divide.back, 0, [rb+7]
+        add -7, 0, [rb+8]
+        arb 7
+        jnz 1, divide.step
+divide.back:
+        add [rb+9], 0, [rb+2]
+        add [rb+10], 0, [rb+3]
+        jnz [rb+4], divide.same_signs
+        mul [rb+2], -1, [rb+2]
+        mul [rb+6], -1, [rb+6]
+divide.same_signs:
+        ; puts back the d taken off the most negative number.
+        add [rb+2], [rb+6], [rb+2]
+        jz [rb+5], divide.return
+        mul [rb+3], -1, [rb+3]
+divide.return:
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
+divide.nothing:
+        add [rb+2], 0, [rb+3]
+        add 0, 0, [rb+2]
+        jnz 1, divide.return
+divide.by_min:
+        ; -d would overflow, but only the most negative number itself
+        ; is big enough to divide by it.
+        eq [rb+2], -9223372036854775808, [rb+6]
+        jz [rb+6], divide.nothing
+        add 1, 0, [rb+2]
+        add 0, 0, [rb+3]
+        jnz 1, divide.return
+
+; divide.step(n, d) -> (quotient, remainder), for n >= 0 and d > 0.
+divide.step:
+        lt [rb+2], [rb+3], [rb+4]
+        jz [rb+4], divide.step.fits
+        add [rb+2], 0, [rb+3]
+        add 0, 0, [rb+2]
+        jnz 1, divide.step.return
+divide.step.fits:
+        ; n < 2d, so d goes in once.  this also keeps 2d from
+        ; overflowing.
+        mul [rb+3], -1, [rb+5]
+        add [rb+2], [rb+5], [rb+6]
+        lt [rb+6], [rb+3], [rb+4]
+        jz [rb+4], divide.step.double
+        add 1, 0, [rb+2]
+        add [rb+6], 0, [rb+3]
+        jnz 1, divide.step.return
+divide.step.double:
+        add [rb+2], 0, [rb+9]
+        mul [rb+3], 2, [rb+10]
+        add divide.step.back, 0, [rb+7]
+        add -7, 0, [rb+8]
+        arb 7
+        jnz 1, divide.step
+divide.step.back:
+        mul [rb+9], 2, [rb+2]
+        lt [rb+10], [rb+3], [rb+4]
+        jnz [rb+4], divide.step.done
+        add [rb+2], 1, [rb+2]
+        add [rb+10], [rb+5], [rb+10]
+divide.step.done:
+        add [rb+10], 0, [rb+3]
+divide.step.return:
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
@@ -0,0 +1,21 @@
+; memcpy(dest, src, count) -> dest
+;
+; Copies count words from address src to address dest, first to
+; last, so it's safe for overlapping ranges when dest is below src.
+; Patches its own copy instruction with each address.
+        export memcpy
+memcpy:
+        add 0, 0, [rb+5]
+memcpy.next:
+        lt [rb+5], [rb+4], [rb+6]
+        jz [rb+6], memcpy.done
+        add [rb+3], [rb+5], [memcpy.copy+1]
+        add [rb+2], [rb+5], [memcpy.copy+3]
+memcpy.copy:
+        add [0], 0, [0]
+        add [rb+5], 1, [rb+5]
+        jnz 1, memcpy.next
+memcpy.done:
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
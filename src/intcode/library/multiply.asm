@@ -0,0 +1,68 @@
+; multiply(a, b) -> a * b
+;
+; What `mul` does, by doubling and adding: a helper doubles a and a
+; power of two on the way down its recursion, and adds up the
+; doubled a for each bit of b on the way back.
+        export multiply
+multiply:
+        ; [rb+4] is whether b is negative, in which case a * b is
+        ; -a * -(b + 1) - a: -b would overflow for the most negative
+        ; number, but -(b + 1) can't.
+        lt [rb+3], 0, [rb+4]
+        jz [rb+4], multiply.positive
+        add [rb+3], 1, [rb+3]
+        mul [rb+3], -1, [rb+3]
+        mul [rb+2], -1, [rb+2]
+multiply.positive:
+        add [rb+2], 0, [rb+7]
+        add 1, 0, [rb+8]
+        add [rb+3], 0, [rb+9]
+        add multiply.back, 0, [rb+5]
+        add -5, 0, [rb+6]
+        arb 5
+        jnz 1, multiply.step
+multiply.back:
+        jz [rb+4], multiply.return
+        add [rb+7], [rb+2], [rb+7]
+multiply.return:
+        add [rb+7], 0, [rb+2]
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
+
+; multiply.step(x, p, b) -> (sum, rest), where x is a * p: the sum
+; of x doubled for each bit of b from p up, and the bits below p.
+multiply.step:
+        lt [rb+4], [rb+3], [rb+5]
+        jz [rb+5], multiply.step.fits
+        add [rb+4], 0, [rb+3]
+        add 0, 0, [rb+2]
+        jnz 1, multiply.step.return
+multiply.step.fits:
+        ; b < 2p, so p is b's top bit.
+        mul [rb+3], -1, [rb+6]
+        add [rb+4], [rb+6], [rb+7]
+        lt [rb+7], [rb+3], [rb+5]
+        jz [rb+5], multiply.step.double
+        add [rb+7], 0, [rb+3]
+        jnz 1, multiply.step.return
+multiply.step.double:
+        mul [rb+2], 2, [rb+10]
+        mul [rb+3], 2, [rb+11]
+        add [rb+4], 0, [rb+12]
+        add multiply.step.back, 0, [rb+8]
+        add -8, 0, [rb+9]
+        arb 8
+        jnz 1, multiply.step
+multiply.step.back:
+        lt [rb+11], [rb+3], [rb+5]
+        jnz [rb+5], multiply.step.done
+        add [rb+10], [rb+2], [rb+10]
+        add [rb+11], [rb+6], [rb+11]
+multiply.step.done:
+        add [rb+10], 0, [rb+2]
+        add [rb+11], 0, [rb+3]
+multiply.step.return:
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
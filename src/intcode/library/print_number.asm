@@ -0,0 +1,63 @@
+; print_number(n)
+;
+; Outputs n in decimal as ASCII, with a `-` if it's negative and no
+; newline.  Needs divide.
+        export print_number
+print_number:
+        lt [rb+2], 0, [rb+3]
+        jz [rb+3], print_number.positive
+        out 45
+        ; -n would overflow for the most negative number, so the last
+        ; digit comes off while n is still negative, and [rb+2] keeps
+        ; it.
+        add [rb+2], 0, [rb+5]
+        add 10, 0, [rb+6]
+        add print_number.split, 0, [rb+3]
+        add -3, 0, [rb+4]
+        arb 3
+        jnz 1, divide
+print_number.split:
+        mul [rb+6], -1, [rb+2]
+        mul [rb+5], -1, [rb+5]
+        jz [rb+5], print_number.rest
+        add print_number.rest, 0, [rb+3]
+        add -3, 0, [rb+4]
+        arb 3
+        jnz 1, print_number.digits
+print_number.rest:
+        add [rb+2], 48, [rb+2]
+        out [rb+2]
+        jnz 1, print_number.back
+print_number.positive:
+        add [rb+2], 0, [rb+5]
+        add print_number.back, 0, [rb+3]
+        add -3, 0, [rb+4]
+        arb 3
+        jnz 1, print_number.digits
+print_number.back:
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
+
+; print_number.digits(n), for n >= 0: the leading digits first, by
+; recursion, then the last.
+print_number.digits:
+        add [rb+2], 0, [rb+5]
+        add 10, 0, [rb+6]
+        add print_number.divided, 0, [rb+3]
+        add -3, 0, [rb+4]
+        arb 3
+        jnz 1, divide
+print_number.divided:
+        add [rb+6], 48, [rb+7]
+        jz [rb+5], print_number.last
+        add [rb+5], 0, [rb+10]
+        add print_number.last, 0, [rb+8]
+        add -8, 0, [rb+9]
+        arb 8
+        jnz 1, print_number.digits
+print_number.last:
+        out [rb+7]
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
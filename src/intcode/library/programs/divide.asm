@@ -0,0 +1,15 @@
+; reads n and d, and outputs n / d and n % d.
+        export main
+main:
+        in [rb+4]
+        in [rb+5]
+        add main.back, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, divide
+main.back:
+        out [rb+4]
+        out [rb+5]
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
@@ -0,0 +1,36 @@
+; reads a line of up to 16 characters, copies it to another buffer,
+; and outputs the copy.
+        export main
+main:
+        add line, 0, [rb+7]
+        add 16, 0, [rb+8]
+        add main.read, 0, [rb+5]
+        add -5, 0, [rb+6]
+        arb 5
+        jnz 1, read_line
+main.read:
+        add [rb+7], 0, [rb+2]
+        add copy, 0, [rb+7]
+        add line, 0, [rb+8]
+        add [rb+2], 0, [rb+9]
+        add main.copied, 0, [rb+5]
+        add -5, 0, [rb+6]
+        arb 5
+        jnz 1, memcpy
+main.copied:
+        add 0, 0, [rb+3]
+main.next:
+        lt [rb+3], [rb+2], [rb+4]
+        jz [rb+4], main.done
+        add copy, [rb+3], [main.out+1]
+main.out:
+        out [0]
+        add [rb+3], 1, [rb+3]
+        jnz 1, main.next
+main.done:
+        out 10
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
+line:   data 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+copy:   data 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
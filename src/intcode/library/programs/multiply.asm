@@ -0,0 +1,14 @@
+; reads a and b, and outputs a * b.
+        export main
+main:
+        in [rb+4]
+        in [rb+5]
+        add main.back, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, multiply
+main.back:
+        out [rb+4]
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
@@ -0,0 +1,13 @@
+; reads a number and prints it on a line of its own.
+        export main
+main:
+        in [rb+4]
+        add main.back, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, print_number
+main.back:
+        out 10
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
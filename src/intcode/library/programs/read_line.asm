@@ -0,0 +1,20 @@
+; reads a line into a 16 word buffer and prints how much it stored.
+        export main
+main:
+        add line, 0, [rb+4]
+        add 16, 0, [rb+5]
+        add main.read, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, read_line
+main.read:
+        add main.printed, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, print_number
+main.printed:
+        out 10
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
+line:   data 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
@@ -0,0 +1,26 @@
+; read_line(buffer, size) -> length
+;
+; Reads ASCII input up to a newline, storing as much as fits in the
+; size words at address buffer, and returns how much it stored.  The
+; newline is read but not stored.  Writes to the buffer by patching
+; its own instruction, as there's no other way to reach an address
+; known only at run time.
+        export read_line
+read_line:
+        add 0, 0, [rb+4]
+read_line.next:
+        in [rb+5]
+        eq [rb+5], 10, [rb+6]
+        jnz [rb+6], read_line.done
+        lt [rb+4], [rb+3], [rb+6]
+        jz [rb+6], read_line.next
+        add [rb+2], [rb+4], [read_line.store+3]
+read_line.store:
+        add [rb+5], 0, [0]
+        add [rb+4], 1, [rb+4]
+        jnz 1, read_line.next
+read_line.done:
+        add [rb+4], 0, [rb+2]
+        add [rb+0], 0, [rt.return]
+        arb [rb+1]
+        jnz 1, [rt.return]
@@ -0,0 +1,414 @@
+//! Relocatable intcode objects, and a linker that puts them together.
+//!
+//! An object is a module's code laid out from address 0, with the
+//! labels it defines, the ones of those it exports, and a relocation
+//! for every cell that holds an address.  A relocation either adds
+//! the address the module ends up at, for the module's own labels, or
+//! the address of a symbol that some module exports, plus whatever
+//! the cell already holds.  Labels that aren't exported are local to
+//! their module, so two modules can each have their own `loop`.
+//! Objects are saved as text.  This one, from an echo loop that jumps
+//! to a `done` it doesn't define, has its labels, its exports, the
+//! addresses in its operands to fix up and the lines its statements
+//! came from:
+//!
+//! ```text
+//! # intcode object
+//! module echo
+//! code 3,10,1006,10,0,4,10,1105,1,0,0
+//! define loop 0
+//! define n 10
+//! export loop
+//! relocate 1
+//! relocate 3
+//! relocate 4 done
+//! relocate 6
+//! relocate 9
+//! line 0 1
+//! line 2 2
+//! line 5 3
+//! line 7 4
+//! line 10 5
+//! ```
+//!
+//! `assemble` makes an object from assembler source, leaving labels it
+//! doesn't define for the linker, and notes the source line of each
+//! statement as `line <offset> <number>`.  `link` lays objects out one
+//! after another, in the order given, and makes a source map of the
+//! result with each module's name standing for its file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::result;
+
+use super::assemble::{self, encode, expressions, Parsed};
+use super::sourcemap::{self, SourceMap};
+use super::{FileError, Word};
+
+const HEADER: &str = "# intcode object";
+
+/// Why objects couldn't be linked.
+#[derive(Debug)]
+pub enum LinkError {
+    /// a symbol the given module uses isn't defined by any module
+    /// being linked.
+    UnresolvedSymbol(String, String),
+    /// a symbol is defined by both of the given modules.
+    DuplicateSymbol(String, String, String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::UnresolvedSymbol(symbol, module) => write!(f, "{} uses {}, which isn't defined", module, symbol),
+            LinkError::DuplicateSymbol(symbol, first, second) => {
+                write!(f, "{} is defined by both {} and {}", symbol, first, second)
+            }
+        }
+    }
+}
+
+impl error::Error for LinkError {}
+
+/// A cell of an object that holds an address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset: usize,
+    /// the symbol whose address the cell is relative to, or none for
+    /// the start of the module.
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object<W = i64> {
+    pub name: String,
+    pub code: Vec<W>,
+    /// the labels the module defines, by offset.
+    pub symbols: BTreeMap<String, usize>,
+    /// the labels other modules can use.
+    pub exports: BTreeSet<String>,
+    pub relocations: Vec<Relocation>,
+    /// the source line of each statement, by the offset it starts at.
+    pub lines: BTreeMap<usize, usize>,
+}
+
+impl<W: Word> Object<W> {
+    pub fn load<P: AsRef<Path>>(path: P) -> result::Result<Self, FileError> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => Object::parse(&text),
+            Err(e) => Err(FileError::Io(path.to_path_buf(), e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> result::Result<(), FileError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|e| FileError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn parse(text: &str) -> result::Result<Self, FileError> {
+        let mut object = Object {
+            name: String::new(),
+            code: vec![],
+            symbols: BTreeMap::new(),
+            exports: BTreeSet::new(),
+            relocations: vec![],
+            lines: BTreeMap::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| FileError::Line(number + 1, line.to_string(), reason.into());
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let offset = |field: &str, limit: usize| match field.parse() {
+                Ok(offset) if offset <= limit => Ok(offset),
+                _ => Err(invalid("offset is outside the code")),
+            };
+            match fields[..] {
+                ["module", name] => object.name = name.to_string(),
+                ["code"] => object.code = vec![],
+                ["code", words] => {
+                    object.code = words
+                        .split(',')
+                        .map(|word| W::parse_word(word).map_err(|e| FileError::Line(number + 1, line.to_string(), e)))
+                        .collect::<result::Result<_, _>>()?;
+                }
+                ["define", name, at] if assemble::is_label(name) => {
+                    let at = offset(at, object.code.len())?;
+                    if object.symbols.insert(name.to_string(), at).is_some() {
+                        return Err(invalid("symbol is already defined"));
+                    }
+                }
+                ["export", name] => {
+                    if !object.symbols.contains_key(name) {
+                        return Err(invalid("symbol isn't defined"));
+                    }
+                    object.exports.insert(name.to_string());
+                }
+                ["relocate", at] | ["relocate", at, _] => {
+                    let symbol = fields.get(2).map(|s| s.to_string());
+                    if symbol.as_deref().is_some_and(|s| !assemble::is_label(s)) {
+                        return Err(invalid("invalid symbol"));
+                    }
+                    let at = offset(at, object.code.len().saturating_sub(1))?;
+                    object.relocations.push(Relocation { offset: at, symbol });
+                }
+                ["line", at, source] => {
+                    let at = offset(at, object.code.len().saturating_sub(1))?;
+                    let source = source.parse().map_err(|_| invalid("invalid line"))?;
+                    object.lines.insert(at, source);
+                }
+                _ => return Err(invalid("expected module, code, define, export, relocate or line")),
+            }
+        }
+        Ok(object)
+    }
+}
+
+impl<W: Word> fmt::Display for Object<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "module {}", self.name)?;
+        let code: Vec<String> = self.code.iter().map(W::to_string).collect();
+        writeln!(f, "code {}", code.join(","))?;
+        for (name, offset) in &self.symbols {
+            writeln!(f, "define {} {}", name, offset)?;
+        }
+        for name in &self.exports {
+            writeln!(f, "export {}", name)?;
+        }
+        for relocation in &self.relocations {
+            match &relocation.symbol {
+                Some(symbol) => writeln!(f, "relocate {} {}", relocation.offset, symbol)?,
+                None => writeln!(f, "relocate {}", relocation.offset)?,
+            }
+        }
+        for (offset, line) in &self.lines {
+            writeln!(f, "line {} {}", offset, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Assembles `source` into an object called `name`.  Labels the
+/// source uses but doesn't define are left for `link` to resolve.
+pub fn assemble<W: Word>(name: &str, source: &str) -> result::Result<Object<W>, FileError> {
+    let Parsed {
+        symbols,
+        exports,
+        lines,
+        statements,
+        len,
+    } = assemble::parse(source)?;
+    let mut code = Vec::with_capacity(len);
+    let mut relocations = vec![];
+    for (_, _, statement) in &statements {
+        // an instruction's operands start after its opcode.
+        let first = code.len() + if let assemble::Statement::Instruction(..) = statement { 1 } else { 0 };
+        let mut values = vec![];
+        for (n, e) in expressions(statement).into_iter().enumerate() {
+            values.push(match &e.label {
+                None => e.offset,
+                Some(label) => {
+                    let (symbol, base) = match symbols.get(label) {
+                        Some(address) => (None, *address as i64),
+                        None => (Some(label.clone()), 0),
+                    };
+                    relocations.push(Relocation {
+                        offset: first + n,
+                        symbol,
+                    });
+                    base + e.offset
+                }
+            });
+        }
+        code.extend(encode(statement, &values));
+    }
+    Ok(Object {
+        name: name.to_string(),
+        code,
+        symbols,
+        exports,
+        relocations,
+        lines,
+    })
+}
+
+/// A linked program and where its symbols ended up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linked<W = i64> {
+    pub program: Vec<W>,
+    /// the exported symbols; local labels are in the source map.
+    pub symbols: BTreeMap<String, usize>,
+    pub source_map: SourceMap,
+}
+
+/// Writes the program as `parse_program` reads it.
+impl<W: Word> fmt::Display for Linked<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words: Vec<String> = self.program.iter().map(W::to_string).collect();
+        write!(f, "{}", words.join(","))
+    }
+}
+
+/// Links `objects` into one program, laid out in the order given.
+pub fn link<W: Word>(objects: &[Object<W>]) -> result::Result<Linked<W>, LinkError> {
+    let mut symbols = BTreeMap::new();
+    // which module defined each symbol, for reporting duplicates.
+    let mut definers: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut bases = vec![];
+    let mut len = 0;
+    for object in objects {
+        bases.push(len);
+        for name in &object.exports {
+            let offset = match object.symbols.get(name) {
+                Some(offset) => offset,
+                None => return Err(LinkError::UnresolvedSymbol(name.clone(), object.name.clone())),
+            };
+            if let Some(first) = definers.insert(name, &object.name) {
+                return Err(LinkError::DuplicateSymbol(name.clone(), first.to_string(), object.name.clone()));
+            }
+            symbols.insert(name.clone(), len + offset);
+        }
+        len += object.code.len();
+    }
+
+    let mut program = Vec::with_capacity(len);
+    let mut source_map = SourceMap::default();
+    for (object, base) in objects.iter().zip(bases) {
+        source_map.spans.extend(sourcemap::spans(
+            &object.name,
+            base,
+            object.code.len(),
+            &object.lines,
+            &object.symbols,
+        ));
+        let mut code = object.code.clone();
+        for relocation in &object.relocations {
+            let address = match &relocation.symbol {
+                None => base,
+                Some(symbol) => match symbols.get(symbol) {
+                    Some(address) => *address,
+                    None => return Err(LinkError::UnresolvedSymbol(symbol.clone(), object.name.clone())),
+                },
+            };
+            let cell = &mut code[relocation.offset];
+            *cell = cell.wrapping_add(&W::from(address as i64));
+        }
+        program.extend(code);
+    }
+    Ok(Linked {
+        program,
+        symbols,
+        source_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::library::{self, DIVIDE, PRINT_NUMBER, PREBUILT};
+    use crate::intcode::{execute_with_input, parse_program};
+
+    const CALLER: &str = "\
+        in [n]
+        out [n]
+        jnz 1, done
+n:      data 0";
+
+    #[test]
+    fn test_link() {
+        let caller = assemble::<i64>("caller", CALLER).expect("assemble caller");
+        assert_eq!(caller.relocations[2], Relocation { offset: 6, symbol: Some("done".to_string()) });
+        let done = assemble::<i64>("done", "export done\ndone: halt").expect("assemble done");
+        let linked = link(&[caller, done]).expect("link");
+        assert_eq!(linked.symbols["done"], 8);
+        let mut program: Vec<i64> = parse_program(&linked.to_string());
+        assert_eq!(program, vec![3, 7, 4, 7, 1105, 1, 8, 0, 99]);
+        assert_eq!(execute_with_input(&mut program, &[6]).expect("run"), vec![6]);
+        let location = linked.source_map.locate(8).expect("located");
+        assert_eq!(location.to_string(), "done:2 in done");
+        assert_eq!(linked.source_map.locate(5).expect("located").to_string(), "caller:3");
+    }
+
+    #[test]
+    fn test_object_text() {
+        let object = assemble::<i64>("caller", CALLER).expect("assemble");
+        let text = object.to_string();
+        assert!(text.starts_with("# intcode object\nmodule caller\ncode 3,7,4,7,1105,1,0,0\n"));
+        assert!(text.contains("\nrelocate 6 done\n"));
+        assert!(text.ends_with("\nline 0 1\nline 2 2\nline 4 3\nline 7 4\n"));
+        assert_eq!(Object::parse(&text).expect("parse"), object);
+        let empty = assemble::<i64>("empty", "export start\nstart:").expect("assemble");
+        assert!(empty.to_string().contains("\ncode \ndefine start 0\nexport start\n"));
+        assert_eq!(Object::parse(&empty.to_string()).expect("parse"), empty);
+
+        let error = |text: &str| match Object::<i64>::parse(text) {
+            Err(e) => format!("{}: {}", e, std::error::Error::source(&e).expect("source")),
+            Ok(_) => panic!("parsed {:?}", text),
+        };
+        assert_eq!(error("code 1,2\nrelocate 2"), "line 2, can't parse \"relocate 2\": offset is outside the code");
+        assert!(error("code 1\ndefine 9x 0").ends_with("expected module, code, define, export, relocate or line"));
+        assert!(error("code 1\nexport x").ends_with("symbol isn't defined"));
+        assert!(error("code 1,x").starts_with("line 1, can't parse \"code 1,x\""));
+    }
+
+    #[test]
+    fn test_errors() {
+        let caller = assemble::<i64>("caller", CALLER).expect("assemble");
+        match link(std::slice::from_ref(&caller)) {
+            Err(LinkError::UnresolvedSymbol(symbol, module)) => assert_eq!((&*symbol, &*module), ("done", "caller")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // done is there, but only for its own module.
+        let hidden = assemble::<i64>("hidden", "done: halt").expect("assemble");
+        match link(&[caller, hidden]) {
+            Err(LinkError::UnresolvedSymbol(symbol, module)) => assert_eq!((&*symbol, &*module), ("done", "caller")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        let once = assemble::<i64>("once", "export n\nn: data 1").expect("assemble");
+        let twice = assemble::<i64>("twice", "export n\nn: data 2").expect("assemble");
+        match link(&[once, twice]) {
+            Err(e @ LinkError::DuplicateSymbol(..)) => assert_eq!(e.to_string(), "n is defined by both once and twice"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_local_labels() {
+        // each module outputs its own n.
+        let first = assemble::<i64>("first", "first:  out [n]\n        jnz 1, second\nn:      data 1").expect("assemble");
+        let second =
+            assemble::<i64>("second", "        export second\nsecond: out [n]\n        halt\nn:      data 2").expect("assemble");
+        let linked = link(&[first, second]).expect("link");
+        assert_eq!(linked.symbols.keys().collect::<Vec<_>>(), vec!["second"]);
+        let mut program = linked.program.clone();
+        assert_eq!(execute_with_input(&mut program, &[]).expect("run"), vec![1, 2]);
+        assert_eq!(linked.source_map.locate(9).expect("located").to_string(), "second:4 in n");
+    }
+
+    #[test]
+    fn test_library_links_like_source() {
+        // the library's parts as separate modules, in the order
+        // `library::source` puts them.
+        let print = &PREBUILT[2];
+        let modules = [
+            ("start", library::START),
+            ("main", print.main),
+            ("divide", DIVIDE.source),
+            ("print_number", PRINT_NUMBER.source),
+            ("end", library::END),
+        ];
+        let objects: Vec<Object> = modules
+            .iter()
+            .map(|(name, source)| assemble(name, source).expect("assemble"))
+            .collect();
+        let linked = link(&objects).expect("link");
+        assert_eq!(linked.program, print.program::<i64>());
+    }
+}
@@ -0,0 +1,452 @@
+//! Rewrites an intcode program into an equivalent one that runs in
+//! fewer steps.
+//!
+//! Every instruction stays at its address, so nothing that refers to
+//! an address needs to change.  The rewrites, repeated until none of
+//! them applies:
+//!
+//! - position mode reads of cells that nothing writes to become
+//!   immediates;
+//! - arithmetic and comparisons on immediates become a copy of the
+//!   result, `add n, 0, dest`;
+//! - jumps to unconditional jumps go straight to where those lead,
+//!   and an unconditional jump to a `halt` becomes one;
+//! - unreachable code after a `halt` is cleared to zeros, up to the
+//!   first cell anything might still refer to, which also takes out
+//!   constants whose reads have all become immediates.
+//!
+//! Code and the cells it writes are found the way the decompiler
+//! finds them, and no devices may be attached.  A relative mode write
+//! could land on any cell, so a program with one keeps its position
+//! mode reads and whatever follows its halts.  Programs that write to
+//! their own instructions are left as they are, as are programs with
+//! a jump whose target can't be worked out, programs the analysis
+//! doesn't settle on, and any instructions a program reads as data.
+//! `check` runs a program and its optimized version side by side to
+//! confirm they agree.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+
+use super::decompile::{self, Op};
+use super::{memory_size, Exit, IntcodeError, Machine, Word};
+
+// how many times to repeat the rewrites before settling.
+const ROUNDS: usize = 16;
+
+// how many instructions `check` lets a run take.
+const STEP_LIMIT: usize = 50_000_000;
+
+fn address<W: Word>(word: &W) -> Option<usize> {
+    word.to_i64().and_then(|w| usize::try_from(w).ok())
+}
+
+fn encode<W: Word>(op: &Op<W>, program: &mut [W]) {
+    let modes = op.modes.iter().enumerate().map(|(n, m)| m * 10i64.pow(n as u32 + 2)).sum::<i64>();
+    program[op.address] = W::from(op.opcode + modes);
+    program[op.address + 1..op.next()].clone_from_slice(&op.params);
+}
+
+// the cells instructions take up, and the cells they read through
+// position mode.
+fn cells<W: Word>(ops: &BTreeMap<usize, Op<W>>) -> (BTreeSet<usize>, BTreeSet<usize>) {
+    let code = ops.values().flat_map(|op| op.address..op.next()).collect();
+    let read = ops
+        .values()
+        .flat_map(|op| {
+            (0..op.params.len())
+                .filter(move |n| op.modes[*n] == 0 && op.destination() != Some(*n))
+                .filter_map(move |n| address(&op.params[n]))
+        })
+        .collect();
+    (code, read)
+}
+
+// reads of cells that are never written become immediates.
+fn immediates<W: Word>(program: &mut [W], ops: &BTreeMap<usize, Op<W>>, written: &BTreeSet<usize>) {
+    // memory goes on past the program, full of zeros.
+    let memory = memory_size(program.len());
+    for op in ops.values() {
+        let mut rewritten = Op {
+            address: op.address,
+            opcode: op.opcode,
+            modes: op.modes,
+            params: op.params.clone(),
+        };
+        for n in 0..op.params.len() {
+            if op.modes[n] != 0 || op.destination() == Some(n) {
+                continue;
+            }
+            match address(&op.params[n]) {
+                Some(cell) if cell < memory && !written.contains(&cell) => {
+                    rewritten.params[n] = program.get(cell).cloned().unwrap_or_else(|| W::from(0));
+                    rewritten.modes[n] = 1;
+                }
+                _ => (),
+            }
+        }
+        encode(&rewritten, program);
+    }
+}
+
+// arithmetic on immediates becomes a copy of its result.
+fn fold<W: Word>(program: &mut [W], ops: &BTreeMap<usize, Op<W>>) {
+    for op in ops.values() {
+        if op.modes[0] != 1 || op.modes[1] != 1 {
+            continue;
+        }
+        let (a, b) = (&op.params[0], &op.params[1]);
+        let value = match op.opcode {
+            1 => a.checked_add(b),
+            2 => a.checked_mul(b),
+            7 => Some(W::from((a < b) as i64)),
+            8 => Some(W::from((a == b) as i64)),
+            _ => None,
+        };
+        if let Some(value) = value {
+            let folded = Op {
+                address: op.address,
+                opcode: 1,
+                modes: op.modes,
+                params: vec![value, W::from(0), op.params[2].clone()],
+            };
+            encode(&folded, program);
+        }
+    }
+}
+
+// where jumping to `target` really ends up, skipping unconditional
+// jumps.
+fn destination<W: Word>(ops: &BTreeMap<usize, Op<W>>, mut target: usize) -> usize {
+    let mut seen = BTreeSet::new();
+    while seen.insert(target) {
+        match ops.get(&target) {
+            Some(op) if op.taken() == Some(true) && op.modes[1] == 1 => match address(&op.params[1]) {
+                Some(next) => target = next,
+                None => break,
+            },
+            _ => break,
+        }
+    }
+    target
+}
+
+// jumps to jumps go straight to the end of the chain.
+fn thread<W: Word>(program: &mut [W], jumps: &BTreeMap<usize, Op<W>>, ops: &BTreeMap<usize, Op<W>>) {
+    for op in jumps.values() {
+        if !op.is_jump() || op.modes[1] != 1 || op.taken() == Some(false) {
+            continue;
+        }
+        let target = match address(&op.params[1]) {
+            Some(target) => destination(ops, target),
+            None => continue,
+        };
+        if op.taken() == Some(true) && ops.get(&target).is_some_and(|t| t.opcode == 99) {
+            program[op.address] = W::from(99);
+            continue;
+        }
+        let mut params = op.params.clone();
+        params[1] = W::from(target as i64);
+        let threaded = Op {
+            address: op.address,
+            opcode: op.opcode,
+            modes: op.modes,
+            params,
+        };
+        encode(&threaded, program);
+    }
+}
+
+// clears what follows each halt, as far as the next instruction or
+// the first cell that's read or written, or whose address appears as
+// a constant and so might be jumped to.
+fn clear<W: Word>(program: &mut [W], ops: &BTreeMap<usize, Op<W>>, referenced: &BTreeSet<usize>) {
+    let (code, _) = cells(ops);
+    for op in ops.values().filter(|op| op.opcode == 99) {
+        let mut cell = op.next();
+        while cell < program.len() && !code.contains(&cell) && !referenced.contains(&cell) {
+            program[cell] = W::from(0);
+            cell += 1;
+        }
+    }
+}
+
+/// Optimizes `program`, leaving it unchanged if it writes to its own
+/// instructions or might jump to code the analysis hasn't found.
+pub fn optimize<W: Word>(program: &[W]) -> Vec<W> {
+    let mut program = program.to_vec();
+    for _ in 0..ROUNDS {
+        let before = program.clone();
+        let analysis = decompile::analyse(&program);
+        // code the analysis hasn't found might read or be any cell.
+        if !analysis.settled || analysis.computed_jumps(&program) {
+            break;
+        }
+        let decompile::Analysis {
+            ops, written, entries, ..
+        } = analysis;
+        let (code, read) = cells(&ops);
+        if code.iter().any(|cell| written.contains(cell)) {
+            break;
+        }
+        // where a relative mode write goes depends on the relative
+        // base, so any cell might be written.
+        let anywhere = ops.values().any(|op| op.destination().is_some_and(|n| op.modes[n] == 2));
+        let mut referenced: BTreeSet<usize> = read.union(&written).chain(&entries).copied().collect();
+        referenced.extend(ops.values().flat_map(|op| op.params.iter().filter_map(address)));
+
+        // instructions the program reads as data have to stay as they
+        // are, though they still count for finding the rest.
+        let rewritable = |program: &[W]| -> BTreeMap<usize, Op<W>> {
+            ops.values()
+                .filter(|op| !(op.address..op.next()).any(|cell| read.contains(&cell)))
+                .filter_map(|op| Op::decode(program, op.address))
+                .map(|op| (op.address, op))
+                .collect()
+        };
+        // each rewrite sees the program as the analysis found it; the
+        // next round picks up what they exposed in each other.
+        if !anywhere {
+            let rewrites = rewritable(&program);
+            immediates(&mut program, &rewrites, &written);
+        }
+        let rewrites = rewritable(&program);
+        fold(&mut program, &rewrites);
+        thread(&mut program, &rewrites, &ops);
+        if !anywhere {
+            clear(&mut program, &ops, &referenced);
+        }
+        if program == before {
+            break;
+        }
+    }
+    program
+}
+
+/// What a program did with one input.
+#[derive(Debug)]
+pub struct Run<W = i64> {
+    pub output: Vec<W>,
+    pub steps: usize,
+    /// why it stopped, if it didn't halt.
+    pub error: Option<IntcodeError<W>>,
+}
+
+impl<W: Word> Run<W> {
+    fn new(program: &[W], input: &[W]) -> Self {
+        let mut machine = Machine::new(program);
+        machine.set_step_limit(Some(STEP_LIMIT));
+        let mut input = input.iter();
+        let mut output = vec![];
+        let error = loop {
+            match machine.execute(&mut input) {
+                Ok(Exit::Output(value)) => output.push(value),
+                Ok(Exit::Halted { .. }) => break None,
+                Ok(Exit::AwaitingInput { pc }) => break Some(IntcodeError::MissingInput(pc)),
+                Err(e) => break Some(e.into_kind()),
+            }
+        };
+        Run {
+            output,
+            steps: machine.steps(),
+            error,
+        }
+    }
+
+    // the same output, ending the same way.
+    fn agrees(&self, other: &Run<W>) -> bool {
+        self.output == other.output && self.error.as_ref().map(mem::discriminant) == other.error.as_ref().map(mem::discriminant)
+    }
+}
+
+/// An input on which a program and its optimized version disagree.
+#[derive(Debug)]
+pub struct Mismatch<W = i64> {
+    pub input: Vec<W>,
+    pub original: Box<Run<W>>,
+    pub optimized: Box<Run<W>>,
+}
+
+impl<W: Word> fmt::Display for Mismatch<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |run: &Run<W>| match &run.error {
+            None => format!("output {:?} and halted", run.output),
+            Some(e) => format!("output {:?} and failed with {}", run.output, e),
+        };
+        write!(
+            f,
+            "with input {:?}, the original {} but the optimized program {}",
+            self.input,
+            describe(&self.original),
+            describe(&self.optimized)
+        )
+    }
+}
+
+/// Runs `original` and `optimized` on each input, and checks they
+/// give the same output and stop the same way.  Returns how many
+/// steps each took over all the inputs.
+pub fn check<W: Word>(original: &[W], optimized: &[W], inputs: &[Vec<W>]) -> Result<(usize, usize), Mismatch<W>> {
+    let mut steps = (0, 0);
+    for input in inputs {
+        let (before, after) = (Run::new(original, input), Run::new(optimized, input));
+        if !before.agrees(&after) {
+            return Err(Mismatch {
+                input: input.clone(),
+                original: Box::new(before),
+                optimized: Box::new(after),
+            });
+        }
+        steps.0 += before.steps;
+        steps.1 += after.steps;
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble::assemble;
+    use crate::intcode::parse_program;
+
+    fn program(source: &str) -> Vec<i64> {
+        assemble::<i64>(source).expect("assemble").program
+    }
+
+    #[test]
+    fn test_immediates_and_folding() {
+        let original = program(
+            "\
+        add [two], [three], [sum]
+        mul [sum], [ten], [sum]
+        out [sum]
+        lt [two], [three], [sum]
+        out [sum]
+        halt
+two:    data 2
+three:  data 3
+ten:    data 10
+sum:    data 0",
+        );
+        let optimized = optimize(&original);
+        let expected = program(
+            "\
+        add 5, 0, [sum]
+        mul [sum], 10, [sum]
+        out [sum]
+        add 1, 0, [sum]
+        out [sum]
+        halt
+        data 0, 0, 0
+sum:    data 0",
+        );
+        assert_eq!(optimized, expected);
+        assert_eq!(check(&original, &optimized, &[vec![]]).expect("agree"), (5, 5));
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        let original = program(
+            "\
+        in [n]
+        jz [n], first
+        out [n]
+        jnz 1, done
+first:  jnz 1, second
+second: jnz 1, output
+output: out 7
+        jnz 1, first2
+first2: jnz 1, done
+done:   halt
+        out 1
+        halt
+n:      data 0",
+        );
+        // the jumps that were skipped are unreachable now, and cleared.
+        let expected = vec![
+            3, 28, 1006, 28, 16, 4, 28, 99, 0, 0, 0, 0, 0, 0, 0, 0, 104, 7, 99, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let optimized = optimize(&original);
+        assert_eq!(optimized, expected);
+        let (before, after) = check(&original, &optimized, &[vec![0], vec![4]]).expect("agree");
+        assert_eq!((before, after), (11, 6));
+    }
+
+    #[test]
+    fn test_constant_conditions_cut_off_code() {
+        // the flag is never written, so the jump is never taken and
+        // the write that kept `x` variable is dead.
+        let original = program(
+            "\
+        jnz [flag], set
+        out [x]
+        halt
+set:    add 1, 0, [x]
+        halt
+flag:   data 0
+x:      data 5",
+        );
+        let optimized = optimize(&original);
+        assert_eq!(&optimized[..6], &[1105, 0, 6, 104, 5, 99]);
+        check(&original, &optimized, &[vec![]]).expect("agree");
+    }
+
+    #[test]
+    fn test_self_modifying_left_alone() {
+        let original: Vec<i64> = parse_program("1,1,1,4,99,5,6,0,99");
+        assert_eq!(optimize(&original), original);
+        // the first instruction is read, so stays; the second isn't.
+        let reads_itself = program("out [0]\nout [x]\nhalt\nx: data 7");
+        assert_eq!(optimize(&reads_itself), vec![4, 0, 104, 7, 99, 0]);
+    }
+
+    #[test]
+    fn test_puzzles_agree() {
+        // day 5 stores its input into an instruction, and day 7 into
+        // a jump table index.
+        for source in &[include_str!("../../input/2019/day5.txt"), include_str!("../../input/2019/day7.txt")] {
+            let program: Vec<i64> = parse_program(source.trim());
+            assert_eq!(optimize(&program), program);
+        }
+
+        // day 9 returns from calls through the stack.
+        let day9: Vec<i64> = parse_program(include_str!("../../input/2019/day9.txt").trim());
+        assert_eq!(optimize(&day9), day9);
+    }
+
+    #[test]
+    fn test_computed_jumps_left_alone() {
+        // jumps through the relative base to 10, which puts 5 in 31
+        // and goes back to output it.
+        let mut original: Vec<i64> = vec![2006, 32, 30, 4, 31, 99, 0, 0, 0, 0, 1101, 5, 0, 31, 1105, 1, 3];
+        original.resize(33, 0);
+        original[30] = 10;
+        original[31] = 7;
+        let optimized = optimize(&original);
+        assert_eq!(optimized, original);
+        check(&original, &optimized, &[vec![]]).expect("agree");
+        assert_eq!(Run::new(&original, &[]).output, vec![5]);
+    }
+
+    #[test]
+    fn test_relative_writes_keep_reads() {
+        // the add writes 5 to cell 9 through the relative base.
+        let original: Vec<i64> = vec![109, 9, 21101, 5, 0, 0, 4, 9, 99, 0];
+        let optimized = optimize(&original);
+        assert_eq!(optimized, original);
+        check(&original, &optimized, &[vec![]]).expect("agree");
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let mismatch = check::<i64>(&[104, 1, 99], &[104, 2, 99], &[vec![]]).expect_err("differ");
+        assert_eq!(
+            mismatch.to_string(),
+            "with input [], the original output [1] and halted but the optimized program output [2] and halted"
+        );
+        let missing = check::<i64>(&[3, 0, 99], &[99], &[vec![]]).expect_err("differ");
+        assert!(missing.to_string().ends_with("the optimized program output [] and halted"));
+    }
+}
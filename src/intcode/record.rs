@@ -0,0 +1,365 @@
+//! Records the I/O of an intcode run so it can be replayed exactly.
+//!
+//! A recording is a text file with one event per line, giving the
+//! step the instruction ran at, its pc, whether it consumed input or
+//! produced output, and the value:
+//!
+//! ```text
+//! # step pc direction value
+//! 0 0 in 5
+//! 6 14 out 25
+//! ```
+//!
+//! Replaying feeds the recorded input back to a fresh machine and
+//! checks every output against the recording, failing with
+//! `RecordError::Diverged` at the first instruction that doesn't
+//! match.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use super::{Exit, FileError, IntcodeError, Machine, Word};
+
+const HEADER: &str = "# step pc direction value";
+
+/// Why recording or replaying a run failed.
+#[derive(Debug)]
+pub enum RecordError<W = i64> {
+    /// the log couldn't be written.
+    File(FileError),
+    /// the machine itself failed.
+    Machine(IntcodeError<W>),
+    /// a replayed run stopped matching its recording at the given
+    /// pc and step.
+    Diverged(usize, usize, String),
+}
+
+impl<W> From<FileError> for RecordError<W> {
+    fn from(error: FileError) -> Self {
+        RecordError::File(error)
+    }
+}
+
+impl<W> From<IntcodeError<W>> for RecordError<W> {
+    fn from(error: IntcodeError<W>) -> Self {
+        RecordError::Machine(error)
+    }
+}
+
+impl<W: Word> fmt::Display for RecordError<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::File(e) => e.fmt(f),
+            RecordError::Machine(e) => e.fmt(f),
+            RecordError::Diverged(pc, step, what) => {
+                write!(f, "pc: {}, replay diverged at step {}: {}", pc, step, what)
+            }
+        }
+    }
+}
+
+impl<W: Word> error::Error for RecordError<W> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // the wrapped errors are shown as they are, so skip to theirs.
+        match self {
+            RecordError::File(e) => error::Error::source(e),
+            RecordError::Machine(e) => error::Error::source(e),
+            RecordError::Diverged(..) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<W = i64> {
+    Input { step: usize, pc: usize, value: W },
+    Output { step: usize, pc: usize, value: W },
+}
+
+impl<W> Event<W> {
+    pub fn step(&self) -> usize {
+        match self {
+            Event::Input { step, .. } | Event::Output { step, .. } => *step,
+        }
+    }
+}
+
+impl<W: Word> fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { step, pc, value } => write!(f, "{} {} in {}", step, pc, value),
+            Event::Output { step, pc, value } => write!(f, "{} {} out {}", step, pc, value),
+        }
+    }
+}
+
+fn parse_event<W: Word>(number: usize, line: &str) -> result::Result<Event<W>, FileError> {
+    let invalid = |reason: &str| FileError::Line(number, line.to_string(), reason.into());
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() != 4 {
+        return Err(invalid("expected step, pc, direction and value"));
+    }
+    let step = fields[0].parse().map_err(|_| invalid("invalid step"))?;
+    let pc = fields[1].parse().map_err(|_| invalid("invalid pc"))?;
+    let value = W::parse_word(fields[3]).map_err(|e| FileError::Line(number, line.to_string(), e))?;
+    match fields[2] {
+        "in" => Ok(Event::Input { step, pc, value }),
+        "out" => Ok(Event::Output { step, pc, value }),
+        _ => Err(invalid("direction must be in or out")),
+    }
+}
+
+/// Runs a machine like `Machine::execute`, logging its I/O to a file
+/// as it goes.
+pub struct Recorder<W: Word = i64> {
+    machine: Machine<W>,
+    path: PathBuf,
+    log: BufWriter<File>,
+}
+
+impl<W: Word> Recorder<W> {
+    pub fn create<P: AsRef<Path>>(machine: Machine<W>, path: P) -> result::Result<Self, FileError> {
+        let path = path.as_ref().to_path_buf();
+        let mut recorder = match File::create(&path) {
+            Ok(file) => Recorder {
+                machine,
+                path,
+                log: BufWriter::new(file),
+            },
+            Err(e) => return Err(FileError::Io(path, e)),
+        };
+        recorder.log_line(&HEADER)?;
+        Ok(recorder)
+    }
+
+    pub fn machine(&self) -> &Machine<W> {
+        &self.machine
+    }
+
+    fn log_line(&mut self, line: &dyn fmt::Display) -> result::Result<(), FileError> {
+        writeln!(self.log, "{}", line).map_err(|e| FileError::Io(self.path.clone(), e))
+    }
+
+    /// Executes the machine, recording every input it consumes and
+    /// output it produces.  The log is flushed before returning, so
+    /// it survives the process dying between calls.
+    pub fn execute<'a, I>(&mut self, mut input: I) -> result::Result<Exit<W>, RecordError<W>>
+    where I: Iterator<Item = &'a W> {
+        let exit = self.run(&mut input);
+        self.log.flush().map_err(|e| FileError::Io(self.path.clone(), e))?;
+        exit
+    }
+
+    fn run<'a, I>(&mut self, input: &mut I) -> result::Result<Exit<W>, RecordError<W>>
+    where I: Iterator<Item = &'a W> {
+        loop {
+            let (step, pc) = (self.machine.steps(), self.machine.pc());
+            let mut consumed = None;
+            let exit = self.machine.step(input.inspect(|value| consumed = Some((*value).clone())))?;
+            if let Some(value) = consumed {
+                self.log_line(&Event::Input { step, pc, value })?;
+            }
+            match exit {
+                Some(Exit::Output(value)) => {
+                    self.log_line(&Event::Output { step, pc, value: value.clone() })?;
+                    return Ok(Exit::Output(value));
+                }
+                Some(exit) => return Ok(exit),
+                None => (),
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> result::Result<Machine<W>, FileError> {
+        match self.log.flush() {
+            Ok(()) => Ok(self.machine),
+            Err(e) => Err(FileError::Io(self.path, e)),
+        }
+    }
+}
+
+/// The I/O events of a recorded run, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording<W = i64> {
+    pub events: Vec<Event<W>>,
+}
+
+impl<W: Word> Recording<W> {
+    pub fn load<P: AsRef<Path>>(path: P) -> result::Result<Self, FileError> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => Recording::parse(&text),
+            Err(e) => Err(FileError::Io(path.to_path_buf(), e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> result::Result<Self, FileError> {
+        let mut events = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            events.push(parse_event(number + 1, line)?);
+        }
+        Ok(Recording { events })
+    }
+
+    /// Runs `machine` to completion on the recorded input, checking
+    /// that it produces the recorded output at the recorded steps.
+    /// Returns the output on success.
+    pub fn replay(&self, machine: &mut Machine<W>) -> result::Result<Vec<W>, RecordError<W>> {
+        let mut events: VecDeque<&Event<W>> = self.events.iter().collect();
+        let mut output = vec![];
+        loop {
+            let (step, pc) = (machine.steps(), machine.pc());
+            let diverged = |what: String| Err(RecordError::Diverged(pc, step, what));
+            let expected = |events: &VecDeque<&Event<W>>| match events.front() {
+                Some(event) => format!("expected event {}", event),
+                None => "recording ended".to_string(),
+            };
+            // only offer the input the recording says this step read.
+            let input = match events.front() {
+                Some(Event::Input { step: s, value, .. }) if *s == step => Some(value),
+                _ => None,
+            };
+            let mut consumed = false;
+            let exit = machine.step(input.into_iter().inspect(|_| consumed = true))?;
+            if consumed {
+                if let Some(Event::Input { pc: recorded, .. }) = events.pop_front() {
+                    if *recorded != pc {
+                        return diverged(format!("expected a read at pc {}, got one here", recorded));
+                    }
+                }
+                continue;
+            }
+            match exit {
+                None => match events.front() {
+                    Some(event) if event.step() <= step => {
+                        return diverged(format!("{}, got no i/o", expected(&events)))
+                    }
+                    _ => (),
+                },
+                Some(Exit::AwaitingInput { .. }) => {
+                    return diverged(format!("{}, got a read", expected(&events)))
+                }
+                Some(Exit::Halted { .. }) if events.is_empty() => return Ok(output),
+                Some(Exit::Halted { .. }) => {
+                    return diverged(format!("{}, got a halt", expected(&events)))
+                }
+                Some(Exit::Output(value)) => {
+                    let actual = Event::Output { step, pc, value };
+                    if events.front() != Some(&&actual) {
+                        return diverged(format!("{}, got {}", expected(&events), actual));
+                    }
+                    events.pop_front();
+                    if let Event::Output { value, .. } = actual {
+                        output.push(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    // reads a number and outputs whether it's 8, then reads another
+    // and outputs one more than it.
+    fn program() -> Vec<i64> {
+        vec![3, 100, 1008, 100, 8, 101, 4, 101, 3, 102, 1001, 102, 1, 102, 4, 102, 99]
+    }
+
+    fn path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("intcode-{}-{}.log", process::id(), name))
+    }
+
+    fn record(name: &str, input: &[i64]) -> PathBuf {
+        let path = path(name);
+        let mut recorder = Recorder::create(Machine::new(&program()), &path).expect("create");
+        let mut input = input.iter();
+        let mut output = vec![];
+        while let Exit::Output(value) = recorder.execute(&mut input).expect("execute") {
+            output.push(value);
+        }
+        recorder.finish().expect("finish");
+        assert_eq!(output.len(), 2);
+        path
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = record("replay", &[9, 42]);
+        let recording = Recording::load(&path).expect("load");
+        assert_eq!(
+            recording.events,
+            vec![
+                Event::Input { step: 0, pc: 0, value: 9 },
+                Event::Output { step: 2, pc: 6, value: 0 },
+                Event::Input { step: 3, pc: 8, value: 42 },
+                Event::Output { step: 5, pc: 14, value: 43 },
+            ]
+        );
+        let text = fs::read_to_string(&path).expect("read");
+        assert!(text.starts_with(HEADER));
+        assert_eq!(Recording::parse(&text).expect("parse"), recording);
+        let output = recording.replay(&mut Machine::new(&program())).expect("replay");
+        assert_eq!(output, vec![0, 43]);
+        fs::remove_file(&path).expect("remove");
+    }
+
+    #[test]
+    fn test_replay_reports_first_divergence() {
+        let path = record("divergence", &[8, 42]);
+        let recording = Recording::load(&path).expect("load");
+        fs::remove_file(&path).expect("remove");
+
+        // add two instead of one.
+        let mut changed = program();
+        changed[12] = 2;
+        match recording.replay(&mut Machine::new(&changed)) {
+            Err(RecordError::Diverged(14, 5, ref what)) if what.ends_with("out 44") => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // output instead of reading the second input.
+        let mut changed = program();
+        changed[8] = 104;
+        match recording.replay(&mut Machine::new(&changed)) {
+            Err(RecordError::Diverged(8, 3, _)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a recording that stops early.
+        let mut short = recording.clone();
+        short.events.pop();
+        match short.replay(&mut Machine::new(&program())) {
+            Err(RecordError::Diverged(14, 5, ref what)) if what.starts_with("recording ended") => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a machine that fails says why, rather than diverging.
+        match recording.replay(&mut Machine::new(&[42])) {
+            Err(RecordError::Machine(e)) => assert!(matches!(e.kind(), IntcodeError::UnknownOpcode(0, 42))),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |text| Recording::<i64>::parse(text).map_err(|e| e.to_string());
+        assert_eq!(parse("# nothing\n\n"), Ok(Recording { events: vec![] }));
+        assert_eq!(parse("0 0 in 5\n1 2 sideways 5"), Err("line 2, can't parse \"1 2 sideways 5\"".to_string()));
+        assert!(parse("0 0 in").is_err());
+        assert!(parse("0 0 in x").is_err());
+        assert!(Recording::<i64>::load(path("missing")).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Spreads intcode trials across worker threads.
+//!
+//! The search space is split into fixed-size blocks which workers
+//! claim in order.  Results are always reported in search space
+//! order, so the answer doesn't depend on how many workers there are
+//! or how the threads were scheduled.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use super::batch::{Batch, Lane, Outcome};
+use super::sourcemap::SourceMap;
+use super::{Isa, Pool, Word};
+
+const BLOCK: usize = 64;
+
+pub struct Search<W: Word = i64> {
+    program: Vec<W>,
+    isa: Isa,
+    workers: usize,
+    step_limit: Option<usize>,
+    source_map: Option<Arc<SourceMap>>,
+}
+
+impl<W: Word + Send + Sync> Search<W> {
+    pub fn new(program: &[W], isa: Isa) -> Self {
+        Search {
+            program: program.to_vec(),
+            isa,
+            workers: thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            step_limit: None,
+            source_map: None,
+        }
+    }
+
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    /// Fails any trial run that goes on for more than `limit`
+    /// instructions with `IntcodeError::StepLimit`, so a trial that
+    /// never halts can't stall the search.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Gives the batches and machines that run the trials `map`, so
+    /// their errors say where they happened.
+    pub fn set_source_map(&mut self, map: Option<Arc<SourceMap>>) {
+        self.source_map = map;
+    }
+
+    /// Runs each lane to completion and returns the earliest one
+    /// whose outcome satisfies `goal`.
+    pub fn first<G>(&self, lanes: &[Lane<W>], goal: G) -> Option<(usize, Outcome<W>)>
+    where
+        G: Fn(&Lane<W>, &Outcome<W>) -> bool + Sync,
+    {
+        self.lanes(lanes, true, goal).pop()
+    }
+
+    /// Runs each lane to completion and returns every one whose
+    /// outcome satisfies `goal`.
+    pub fn all<G>(&self, lanes: &[Lane<W>], goal: G) -> Vec<(usize, Outcome<W>)>
+    where
+        G: Fn(&Lane<W>, &Outcome<W>) -> bool + Sync,
+    {
+        self.lanes(lanes, false, goal)
+    }
+
+    /// Like `first`, for trials that need more than a single run.
+    /// `evaluate` is handed a pool of machines loaded with the
+    /// program, one pool per worker.
+    pub fn first_with<T, R, E, G>(&self, space: &[T], evaluate: E, goal: G) -> Option<(usize, R)>
+    where
+        T: Sync,
+        R: Send,
+        E: Fn(&mut Pool<W>, &T) -> R + Sync,
+        G: Fn(&T, &R) -> bool + Sync,
+    {
+        self.trials(space, true, evaluate, goal).pop()
+    }
+
+    /// Like `all`, for trials that need more than a single run.
+    pub fn all_with<T, R, E, G>(&self, space: &[T], evaluate: E, goal: G) -> Vec<(usize, R)>
+    where
+        T: Sync,
+        R: Send,
+        E: Fn(&mut Pool<W>, &T) -> R + Sync,
+        G: Fn(&T, &R) -> bool + Sync,
+    {
+        self.trials(space, false, evaluate, goal)
+    }
+
+    fn lanes<G>(&self, lanes: &[Lane<W>], first: bool, goal: G) -> Vec<(usize, Outcome<W>)>
+    where
+        G: Fn(&Lane<W>, &Outcome<W>) -> bool + Sync,
+    {
+        self.schedule(
+            lanes,
+            first,
+            || {
+                let mut batch = Batch::new(&self.program, self.isa);
+                batch.set_step_limit(self.step_limit);
+                batch.set_source_map(self.source_map.clone());
+                batch
+            },
+            |batch, start, block| {
+                batch
+                    .run(block)
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, outcome)| goal(&block[*i], outcome))
+                    .map(|(i, outcome)| (start + i, outcome))
+                    .collect()
+            },
+        )
+    }
+
+    fn trials<T, R, E, G>(&self, space: &[T], first: bool, evaluate: E, goal: G) -> Vec<(usize, R)>
+    where
+        T: Sync,
+        R: Send,
+        E: Fn(&mut Pool<W>, &T) -> R + Sync,
+        G: Fn(&T, &R) -> bool + Sync,
+    {
+        self.schedule(
+            space,
+            first,
+            || {
+                let mut pool = Pool::new(&self.program, self.isa);
+                pool.set_step_limit(self.step_limit);
+                pool.set_source_map(self.source_map.clone());
+                pool
+            },
+            |pool, start, block| {
+                let mut found = vec![];
+                for (i, trial) in block.iter().enumerate() {
+                    let result = evaluate(pool, trial);
+                    if goal(trial, &result) {
+                        found.push((start + i, result));
+                        if first {
+                            break;
+                        }
+                    }
+                }
+                found
+            },
+        )
+    }
+
+    // hands out blocks of `space` to the workers, each of which keeps
+    // its own state across the blocks it processes.  when only the
+    // first match is wanted, blocks that start after the best match
+    // so far are skipped, and the result holds at most one match.
+    fn schedule<T, R, S, I, F>(&self, space: &[T], first: bool, init: I, process: F) -> Vec<(usize, R)>
+    where
+        T: Sync,
+        R: Send,
+        I: Fn() -> S + Sync,
+        F: Fn(&mut S, usize, &[T]) -> Vec<(usize, R)> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let best = AtomicUsize::new(usize::MAX);
+        let mut found: Vec<(usize, R)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut state = init();
+                        let mut found = vec![];
+                        loop {
+                            let start = next.fetch_add(1, Ordering::SeqCst) * BLOCK;
+                            if start >= space.len() || (first && start > best.load(Ordering::SeqCst)) {
+                                return found;
+                            }
+                            let end = (start + BLOCK).min(space.len());
+                            let block_found = process(&mut state, start, &space[start..end]);
+                            if let Some((i, _)) = block_found.first() {
+                                if first {
+                                    best.fetch_min(*i, Ordering::SeqCst);
+                                }
+                            }
+                            found.extend(block_found);
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("search worker panicked"))
+                .collect()
+        });
+        found.sort_by_key(|(i, _)| *i);
+        if first {
+            found.truncate(1);
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Exit, IntcodeError};
+
+    // outputs the input doubled.
+    const DOUBLE: [i64; 9] = [3, 9, 1002, 9, 2, 9, 4, 9, 99];
+
+    fn lanes(n: i64) -> Vec<Lane> {
+        (0..n).map(|i| Lane::new(vec![i])).collect()
+    }
+
+    #[test]
+    fn test_first_is_earliest_match() {
+        for workers in 1..5 {
+            let mut search = Search::new(&DOUBLE, Isa::Day5);
+            search.set_workers(workers);
+            let (i, outcome) = search
+                .first(&lanes(1000), |_, outcome| outcome.output[0] % 3 == 0 && outcome.output[0] > 500)
+                .expect("match");
+            assert_eq!(i, 252);
+            assert_eq!(outcome.output, vec![504]);
+        }
+    }
+
+    #[test]
+    fn test_all_is_ordered() {
+        let space = lanes(1000);
+        let mut search = Search::new(&DOUBLE, Isa::Day5);
+        search.set_workers(4);
+        let found = search.all(&space, |lane, _| lane.input[0] % 7 == 0);
+        let indexes: Vec<usize> = found.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, (0..1000).step_by(7).collect::<Vec<_>>());
+        for (i, outcome) in found {
+            assert_eq!(outcome.output, vec![2 * i as i64]);
+        }
+    }
+
+    #[test]
+    fn test_no_match() {
+        let search = Search::new(&DOUBLE, Isa::Day5);
+        assert!(search.first(&lanes(100), |_, outcome| outcome.output[0] == 1).is_none());
+        assert!(search.all(&lanes(100), |_, outcome| outcome.output[0] == 1).is_empty());
+    }
+
+    #[test]
+    fn test_step_limit() {
+        // loops forever unless its input is zero.
+        let program = [3, 9, 1005, 9, 2, 4, 9, 99, 0, 0];
+        let space: Vec<Lane> = (0..100).map(|i| Lane::new(vec![(i + 1) % 50])).collect();
+        let mut search = Search::new(&program, Isa::Day5);
+        search.set_step_limit(Some(1000));
+        let (i, outcome) = search.first(&space, |_, outcome| outcome.error.is_none()).expect("match");
+        assert_eq!((i, outcome.output), (49, vec![0]));
+        let stuck = search.all_with(
+            &space,
+            |pool, lane| pool.take().execute(lane.input.iter()).map_err(|e| e.into_kind()),
+            |_, result| matches!(result, Err(IntcodeError::StepLimit(2, 1000))),
+        );
+        assert_eq!(stuck.len(), 98);
+    }
+
+    #[test]
+    fn test_trials_with_pool() {
+        let space: Vec<i64> = (0..500).collect();
+        let mut search = Search::new(&DOUBLE, Isa::Day5);
+        search.set_workers(3);
+        let evaluate = |pool: &mut Pool, i: &i64| {
+            // feed each run's output into the next run.
+            let mut value = *i;
+            for _ in 0..3 {
+                let mut machine = pool.take();
+                value = match machine.execute([value].iter()).expect("execute") {
+                    Exit::Output(output) => output,
+                    other => panic!("unexpected exit {:?}", other),
+                };
+                pool.give(machine);
+            }
+            value
+        };
+        let (i, value) = search.first_with(&space, evaluate, |_, value| *value > 1000).expect("match");
+        assert_eq!((i, value), (126, 1008));
+        let found = search.all_with(&space, evaluate, |_, value| value % 100 == 0);
+        let indexes: Vec<usize> = found.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, (0..500).step_by(25).collect::<Vec<_>>());
+    }
+}
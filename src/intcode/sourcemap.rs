@@ -0,0 +1,301 @@
+//! Source maps, which relate the addresses of an assembled program
+//! back to the source it came from.
+//!
+//! A map is a sidecar file kept next to the program it describes,
+//! named after it with `.map` on the end, so `echo.intcode` has
+//! `echo.intcode.map`.  It lists each instruction or `data` directive
+//! as the address it starts at, how many cells it covers, the file
+//! and line it came from, and the label defined there, if any:
+//!
+//! ```text
+//! # intcode source map
+//! # address length file line [symbol]
+//! 0 2 echo.asm 2 loop
+//! 2 3 echo.asm 3
+//! 5 2 echo.asm 4
+//! 7 3 echo.asm 5
+//! 10 1 echo.asm 7 done
+//! 11 1 echo.asm 8 n
+//! ```
+//!
+//! File names can't contain whitespace.  A machine given a map with
+//! `Machine::set_source_map` says where in the source its errors
+//! happened, as do the machines from a `Pool` or `Search` given one,
+//! and the disassembler and DAP server use maps to show source lines
+//! and labels.  `execute_with_input` and batches don't take a map, so
+//! their errors only give the pc.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::result;
+
+use super::assemble::{self, Assembly};
+use super::FileError;
+
+const HEADER: &str = "# intcode source map\n# address length file line [symbol]";
+
+/// The cells assembled from one line of source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub address: usize,
+    pub len: usize,
+    pub file: String,
+    pub line: usize,
+    /// the label defined at `address`.
+    pub symbol: Option<String>,
+}
+
+/// Where an address came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    /// the closest label at or before the address in the same file,
+    /// and how far past it the address is.
+    pub symbol: Option<(String, usize)>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)?;
+        match &self.symbol {
+            Some((symbol, 0)) => write!(f, " in {}", symbol),
+            Some((symbol, offset)) => write!(f, " in {}+{}", symbol, offset),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    /// the spans, in address order and not overlapping.
+    pub spans: Vec<Span>,
+}
+
+// the spans of code laid out from `base`, given the line each
+// statement starts at and the labels, both by offset.
+pub(super) fn spans(
+    file: &str,
+    base: usize,
+    len: usize,
+    lines: &BTreeMap<usize, usize>,
+    symbols: &BTreeMap<String, usize>,
+) -> Vec<Span> {
+    let starts: Vec<(usize, usize)> = lines.iter().map(|(offset, line)| (*offset, *line)).collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, (offset, line))| {
+            let end = starts.get(i + 1).map_or(len, |(next, _)| *next);
+            // of several labels for one address, the last, as
+            // `Assembly::symbol` picks.
+            let symbol = symbols.iter().rev().find(|(_, at)| *at == offset).map(|(name, _)| name.clone());
+            Span {
+                address: base + offset,
+                len: end - offset,
+                file: file.to_string(),
+                line: *line,
+                symbol,
+            }
+        })
+        .filter(|span| span.len > 0)
+        .collect()
+}
+
+impl SourceMap {
+    /// The map of a program assembled from `file`.
+    pub fn new<W>(file: &str, assembly: &Assembly<W>) -> Self {
+        SourceMap {
+            spans: spans(file, 0, assembly.program.len(), &assembly.lines, &assembly.symbols),
+        }
+    }
+
+    /// Where the map for the program at `program` is kept.
+    pub fn sidecar<P: AsRef<Path>>(program: P) -> PathBuf {
+        let mut path = program.as_ref().as_os_str().to_os_string();
+        path.push(".map");
+        PathBuf::from(path)
+    }
+
+    /// Loads the map kept next to the program at `program`, if there
+    /// is one.
+    pub fn load_sidecar<P: AsRef<Path>>(program: P) -> result::Result<Option<Self>, FileError> {
+        let path = SourceMap::sidecar(program);
+        if path.exists() {
+            SourceMap::load(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> result::Result<Self, FileError> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => SourceMap::parse(&text),
+            Err(e) => Err(FileError::Io(path.to_path_buf(), e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> result::Result<(), FileError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string()).map_err(|e| FileError::Io(path.to_path_buf(), e))
+    }
+
+    pub fn parse(text: &str) -> result::Result<Self, FileError> {
+        let mut map = SourceMap::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| FileError::Line(number + 1, line.to_string(), reason.into());
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (address, len, file, line_number, symbol) = match fields[..] {
+                [address, len, file, line] => (address, len, file, line, None),
+                [address, len, file, line, symbol] if assemble::is_label(symbol) => {
+                    (address, len, file, line, Some(symbol.to_string()))
+                }
+                _ => return Err(invalid("expected address, length, file, line and optionally a symbol")),
+            };
+            let address = address.parse().map_err(|_| invalid("invalid address"))?;
+            let len = match len.parse() {
+                Ok(len) if len > 0 => len,
+                _ => return Err(invalid("invalid length")),
+            };
+            let line_number = line_number.parse().map_err(|_| invalid("invalid line"))?;
+            if map.spans.last().is_some_and(|last| last.address + last.len > address) {
+                return Err(invalid("span overlaps the one before"));
+            }
+            map.spans.push(Span {
+                address,
+                len,
+                file: file.to_string(),
+                line: line_number,
+                symbol,
+            });
+        }
+        Ok(map)
+    }
+
+    fn index(&self, address: usize) -> Option<usize> {
+        let i = self.spans.partition_point(|span| span.address <= address).checked_sub(1)?;
+        let span = &self.spans[i];
+        if address < span.address + span.len {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// The span that covers `address`.
+    pub fn span(&self, address: usize) -> Option<&Span> {
+        self.index(address).map(|i| &self.spans[i])
+    }
+
+    /// Where `address` came from.
+    pub fn locate(&self, address: usize) -> Option<Location> {
+        let i = self.index(address)?;
+        let span = &self.spans[i];
+        let symbol = self.spans[..=i]
+            .iter()
+            .rev()
+            .take_while(|s| s.file == span.file)
+            .find_map(|s| s.symbol.as_ref().map(|symbol| (symbol.clone(), address - s.address)));
+        Some(Location {
+            file: span.file.clone(),
+            line: span.line,
+            symbol,
+        })
+    }
+
+    /// The first address assembled from `line` of `file` or any line
+    /// after it.
+    pub fn address<P: AsRef<Path>>(&self, file: P, line: usize) -> Option<usize> {
+        self.spans
+            .iter()
+            .filter(|span| Path::new(&span.file) == file.as_ref() && span.line >= line)
+            .min_by_key(|span| (span.line, span.address))
+            .map(|span| span.address)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for span in &self.spans {
+            write!(f, "{} {} {} {}", span.address, span.len, span.file, span.line)?;
+            match &span.symbol {
+                Some(symbol) => writeln!(f, " {}", symbol)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble::assemble;
+    use crate::intcode::{Isa, Machine, Pool};
+    use std::sync::Arc;
+
+    const ECHO: &str = "\
+; echoes its input until it reads a zero.
+loop:   in [n]
+        jz [n], done
+        out [n]
+        jnz 1, loop
+
+done:   halt
+n:      data 0
+";
+
+    fn map() -> SourceMap {
+        SourceMap::new("echo.asm", &assemble::<i64>(ECHO).expect("assemble"))
+    }
+
+    #[test]
+    fn test_map() {
+        let map = map();
+        let text = map.to_string();
+        assert!(text.ends_with("\n0 2 echo.asm 2 loop\n2 3 echo.asm 3\n5 2 echo.asm 4\n7 3 echo.asm 5\n10 1 echo.asm 7 done\n11 1 echo.asm 8 n\n"));
+        assert_eq!(SourceMap::parse(&text).expect("parse"), map);
+        assert_eq!(map.locate(8).map(|l| l.to_string()), Some("echo.asm:5 in loop+8".to_string()));
+        assert_eq!(map.locate(10).map(|l| l.to_string()), Some("echo.asm:7 in done".to_string()));
+        assert_eq!(map.locate(12), None);
+        assert_eq!(map.address("echo.asm", 6), Some(10));
+        assert_eq!(map.address("other.asm", 1), None);
+        assert_eq!(SourceMap::sidecar("day9.intcode"), PathBuf::from("day9.intcode.map"));
+
+        let error = |text: &str| match SourceMap::parse(text) {
+            Err(e) => std::error::Error::source(&e).expect("source").to_string(),
+            Ok(_) => panic!("parsed {:?}", text),
+        };
+        assert_eq!(error("0 2 a.asm 1\n1 1 a.asm 2"), "span overlaps the one before");
+        assert_eq!(error("0 0 a.asm 1"), "invalid length");
+        assert!(error("0 1 a.asm").starts_with("expected address"));
+    }
+
+    #[test]
+    fn test_errors_say_where() {
+        // falls through the halt into n, which isn't an instruction.
+        let assembly = assemble::<i64>("start:  jnz 1, n\n        halt\nn:      data 77").expect("assemble");
+        let mut machine = Machine::new(&assembly.program);
+        machine.set_source_map(Some(Arc::new(SourceMap::new("bad.asm", &assembly))));
+        let error = machine.execute([].iter()).expect_err("unknown opcode");
+        assert!(
+            error.to_string().starts_with("pc: 4, unknown opcode 77 (bad.asm:3 in n); instruction 77"),
+            "{}",
+            error
+        );
+        assert_eq!(error.context().and_then(|c| c.location.as_ref()).map(|l| l.line), Some(3));
+
+        let mut pool = Pool::new(&assembly.program, Isa::default());
+        pool.set_source_map(machine.source_map().cloned().map(Arc::new));
+        let error = pool.take().execute([].iter()).expect_err("unknown opcode");
+        assert!(error.to_string().contains("(bad.asm:3 in n)"), "{}", error);
+    }
+}
@@ -0,0 +1,354 @@
+//! Translates an intcode program into Rust source ahead of time.
+//!
+//! The generated module holds a `Program` type with the same
+//! `execute` interface as `Machine`.  Each instruction the decompiler
+//! can see being reached becomes an arm of a `match` on the pc, which
+//! does the happy path of that instruction directly on the machine's
+//! memory.  Whenever an arm can't, because the pc isn't a compiled
+//! address, the instruction has been overwritten since, or running it
+//! would fail, the `Runtime` hands that one instruction to the
+//! interpreter and then carries on in compiled code.  Errors therefore
+//! come from the interpreter and are exactly the ones it reports.
+//!
+//! Generated programs use i64 words.  Compiled arithmetic is checked
+//! and falls back on overflow, so the machine's overflow policy still
+//! applies.
+
+use std::fmt::Write;
+
+use super::decompile::{self, Op};
+use super::{Exit, ExitResult, Isa, Machine, Overflow};
+
+/// The compiled half of a generated program: runs instructions until
+/// it produces an exit, or returns `None` to have the interpreter run
+/// the instruction at the pc.
+pub type Compiled = for<'a> fn(&mut Runtime, &mut dyn Iterator<Item = &'a i64>) -> Option<Exit>;
+
+/// The state a generated program runs on.  Its methods are what the
+/// generated code is written in terms of; those that return `Option`
+/// return `None` when the interpreter should take over.
+pub struct Runtime {
+    machine: Machine,
+    // which cells hold compiled instructions.
+    code: Vec<bool>,
+    // compiled cells that have been overwritten with something else.
+    stale: Vec<bool>,
+    any_stale: bool,
+}
+
+impl Runtime {
+    /// `code` lists the address and length of each compiled
+    /// instruction.
+    pub fn new(program: &[i64], code: &[(usize, usize)]) -> Self {
+        let mut machine = Machine::with_isa(program, Isa::Day9);
+        // the undo log is how writes made by the interpreter are seen.
+        machine.set_history(true).expect("no devices are attached");
+        let mut cells = vec![false; program.len()];
+        for (address, length) in code {
+            for cell in &mut cells[*address..address + length] {
+                *cell = true;
+            }
+        }
+        Runtime {
+            machine,
+            stale: vec![false; cells.len()],
+            code: cells,
+            any_stale: false,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.machine.set_overflow(overflow);
+    }
+
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.machine.set_step_limit(limit);
+    }
+
+    pub fn execute<'a, I>(&mut self, mut input: I, compiled: Compiled) -> ExitResult
+    where I: Iterator<Item = &'a i64> {
+        loop {
+            if let Some(exit) = compiled(self, &mut input) {
+                return Ok(exit);
+            }
+            let exit = self.machine.step(&mut input)?;
+            for undo in self.machine.take_history() {
+                if let Some((address, _)) = undo.write {
+                    self.wrote(address);
+                }
+            }
+            if let Some(exit) = exit {
+                return Ok(exit);
+            }
+        }
+    }
+
+    fn wrote(&mut self, address: usize) {
+        if self.code.get(address) == Some(&true) {
+            self.stale[address] = true;
+            self.any_stale = true;
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        self.machine.pc()
+    }
+
+    /// Starts the compiled instruction at `pc`, which is `length`
+    /// cells long.
+    pub fn enter(&self, pc: usize, length: usize) -> Option<()> {
+        if let Some(limit) = self.machine.step_limit() {
+            if self.machine.steps() >= limit {
+                return None;
+            }
+        }
+        if self.any_stale && self.stale[pc..pc + length].contains(&true) {
+            return None;
+        }
+        Some(())
+    }
+
+    pub fn load(&self, address: usize) -> Option<i64> {
+        self.machine.read(address).copied()
+    }
+
+    pub fn position(&self, word: i64) -> Option<usize> {
+        if word < 0 {
+            None
+        } else {
+            Some(word as usize)
+        }
+    }
+
+    pub fn relative(&self, offset: i64) -> Option<usize> {
+        self.position(self.machine.relative_base().checked_add(offset)?)
+    }
+
+    /// Checks that `address` can be stored to, for instructions that
+    /// must know before they consume input.
+    pub fn writable(&self, address: usize) -> Option<usize> {
+        if address < self.machine.memory_len() {
+            Some(address)
+        } else {
+            None
+        }
+    }
+
+    /// The address a jump to `word` goes to, if it's inside memory.
+    pub fn target(&self, word: i64) -> Option<usize> {
+        self.writable(self.position(word)?)
+    }
+
+    pub fn store(&mut self, address: usize, value: i64) -> Option<()> {
+        if *self.machine.read(address)? != value {
+            self.machine.write(address, value).ok()?;
+            self.wrote(address);
+        }
+        Some(())
+    }
+
+    pub fn adjust(&mut self, offset: i64) -> Option<()> {
+        let base = self.relative(offset)?;
+        self.machine.set_relative_base(base).ok()
+    }
+
+    /// Finishes an instruction, carrying on at `pc`, which must be
+    /// inside memory.
+    pub fn next(&mut self, pc: usize) {
+        self.machine.set_pc(pc).expect("compiled code stays inside memory");
+        self.machine.set_steps(self.machine.steps() + 1);
+    }
+
+    pub fn output(&mut self, pc: usize, value: i64) -> Exit {
+        self.next(pc);
+        Exit::Output(value)
+    }
+
+    pub fn awaiting_input(&self) -> Exit {
+        Exit::AwaitingInput { pc: self.machine.pc() }
+    }
+}
+
+// an expression for the value of parameter `n`, or `None` if it can't
+// be compiled.
+fn read(op: &Op<i64>, n: usize) -> Option<String> {
+    let word = op.params[n];
+    match op.modes[n] {
+        0 if word >= 0 => Some(format!("rt.load({})?", word)),
+        1 => Some(format!("{}", word)),
+        2 => Some(format!("rt.load(rt.relative({})?)?", word)),
+        _ => None,
+    }
+}
+
+// an expression for the address parameter `n` writes to.
+fn destination(op: &Op<i64>, n: usize) -> Option<String> {
+    let word = op.params[n];
+    match op.modes[n] {
+        0 if word >= 0 => Some(format!("{}", word)),
+        2 => Some(format!("rt.relative({})?", word)),
+        _ => None,
+    }
+}
+
+// the body of the match arm for `op`.
+fn arm(op: &Op<i64>) -> Option<Vec<String>> {
+    let next = op.next();
+    let mut lines = vec![];
+    match op.opcode {
+        1 | 2 | 7 | 8 => {
+            lines.push(format!("let a: i64 = {};", read(op, 0)?));
+            lines.push(format!("let b: i64 = {};", read(op, 1)?));
+            lines.push(format!("let d = {};", destination(op, 2)?));
+            lines.push(
+                match op.opcode {
+                    1 => "rt.store(d, a.checked_add(b)?)?;",
+                    2 => "rt.store(d, a.checked_mul(b)?)?;",
+                    7 => "rt.store(d, (a < b) as i64)?;",
+                    _ => "rt.store(d, (a == b) as i64)?;",
+                }
+                .to_string(),
+            );
+            lines.push(format!("rt.next({});", next));
+        }
+        3 => {
+            lines.push(format!("let d = rt.writable({})?;", destination(op, 0)?));
+            lines.push("let value = match input.next() {".to_string());
+            lines.push("    Some(value) => *value,".to_string());
+            lines.push("    None => return Some(rt.awaiting_input()),".to_string());
+            lines.push("};".to_string());
+            lines.push("rt.store(d, value)?;".to_string());
+            lines.push(format!("rt.next({});", next));
+        }
+        4 => {
+            lines.push(format!("let a: i64 = {};", read(op, 0)?));
+            lines.push(format!("return Some(rt.output({}, a));", next));
+        }
+        5 | 6 => {
+            let target = read(op, 1)?;
+            match op.taken() {
+                // an immediate condition goes the same way every time.
+                Some(true) => {
+                    lines.push(format!("let target = rt.target({})?;", target));
+                    lines.push("rt.next(target);".to_string());
+                }
+                Some(false) => lines.push(format!("rt.next({});", next)),
+                None => {
+                    let test = if op.opcode == 5 { "!=" } else { "==" };
+                    lines.push(format!("if {} {} 0 {{", read(op, 0)?, test));
+                    lines.push(format!("    let target = rt.target({})?;", target));
+                    lines.push("    rt.next(target);".to_string());
+                    lines.push("} else {".to_string());
+                    lines.push(format!("    rt.next({});", next));
+                    lines.push("}".to_string());
+                }
+            }
+        }
+        9 => {
+            lines.push(format!("rt.adjust({})?;", read(op, 0)?));
+            lines.push(format!("rt.next({});", next));
+        }
+        // the interpreter halts the machine.
+        _ => return None,
+    }
+    Some(lines)
+}
+
+/// Generates a Rust module that runs `program`.  The module refers to
+/// this crate as `aoc2019`.
+pub fn transpile(program: &[i64]) -> String {
+    let mut arms = vec![];
+    for op in decompile::instructions(program) {
+        if let Some(lines) = arm(&op) {
+            arms.push((op, lines));
+        }
+    }
+    let mut out = String::new();
+    let words: Vec<String> = program.iter().map(|w| w.to_string()).collect();
+    let code: Vec<String> = arms
+        .iter()
+        .map(|(op, _)| format!("({}, {})", op.address, op.next() - op.address))
+        .collect();
+    let _ = write!(
+        out,
+        "\
+//! Generated from an intcode program by `intcode::transpile`; don't
+//! edit it by hand.
+
+#![allow(dead_code, unused_variables, unreachable_code, clippy::never_loop, clippy::match_single_binding)]
+
+use aoc2019::intcode::transpile::Runtime;
+use aoc2019::intcode::{{Exit, ExitResult, Machine, Overflow}};
+
+const PROGRAM: [i64; {}] = [{}];
+
+// the address and length of each compiled instruction.
+const CODE: [(usize, usize); {}] = [{}];
+
+pub struct Program {{
+    runtime: Runtime,
+}}
+
+impl Program {{
+    pub fn new() -> Self {{
+        Program {{
+            runtime: Runtime::new(&PROGRAM, &CODE),
+        }}
+    }}
+
+    pub fn machine(&self) -> &Machine {{
+        self.runtime.machine()
+    }}
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {{
+        self.runtime.set_overflow(overflow);
+    }}
+
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {{
+        self.runtime.set_step_limit(limit);
+    }}
+
+    pub fn execute<'a, I>(&mut self, input: I) -> ExitResult
+    where I: Iterator<Item = &'a i64> {{
+        self.runtime.execute(input, compiled)
+    }}
+}}
+
+impl Default for Program {{
+    fn default() -> Self {{
+        Program::new()
+    }}
+}}
+
+fn compiled(rt: &mut Runtime, input: &mut dyn Iterator<Item = &i64>) -> Option<Exit> {{
+    loop {{
+        match rt.pc() {{
+",
+        program.len(),
+        words.join(", "),
+        code.len(),
+        code.join(", "),
+    );
+    for (op, lines) in &arms {
+        let words: Vec<String> = program[op.address..op.next()].iter().map(|w| w.to_string()).collect();
+        let _ = writeln!(out, "            // {}", words.join(","));
+        let _ = writeln!(out, "            {} => {{", op.address);
+        let _ = writeln!(out, "                rt.enter({}, {})?;", op.address, op.next() - op.address);
+        for line in lines {
+            let _ = writeln!(out, "                {}", line);
+        }
+        let _ = writeln!(out, "            }}");
+    }
+    out.push_str(
+        "            _ => return None,
+        }
+    }
+}
+",
+    );
+    out
+}
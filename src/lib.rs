@@ -3,7 +3,7 @@ extern crate aoc_runner;
 #[macro_use]
 extern crate aoc_runner_derive;
 
-mod intcode;
+pub mod intcode;
 
 pub mod day1;
 pub mod day2;
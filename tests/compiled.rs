@@ -0,0 +1,85 @@
+//! Compiles the programs in `tests/compiled` with `intcode::compile`
+//! and runs them on the interpreter, as compiled and after
+//! `intcode::optimize`.  Each program lists its runs in comments of
+//! the form `// run: <input> -> <output>`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use aoc2019::intcode::{compile, execute_with_input, optimize, parse_program};
+
+fn words(text: &str) -> Vec<i64> {
+    let text = text.trim();
+    if text.is_empty() {
+        vec![]
+    } else {
+        parse_program(&text.replace(' ', ""))
+    }
+}
+
+struct Program {
+    path: PathBuf,
+    source: String,
+    /// inputs and the output each should give.
+    runs: Vec<(Vec<i64>, Vec<i64>)>,
+}
+
+fn programs() -> Vec<Program> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/compiled");
+    let mut paths: Vec<_> = fs::read_dir(&directory)
+        .expect("read tests/compiled")
+        .map(|entry| entry.expect("entry").path())
+        .filter(|path| path.extension().is_some_and(|e| e == "ic"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    paths
+        .into_iter()
+        .map(|path| {
+            let source = fs::read_to_string(&path).expect("read program");
+            let runs: Vec<_> = source
+                .lines()
+                .filter_map(|line| line.strip_prefix("// run:"))
+                .map(|run| run.split_once("->").expect("input -> output"))
+                .map(|(input, output)| (words(input), words(output)))
+                .collect();
+            assert!(!runs.is_empty(), "{} has no runs", path.display());
+            Program { path, source, runs }
+        })
+        .collect()
+}
+
+#[test]
+fn compiled_programs_run() {
+    for Program { path, source, runs } in programs() {
+        let assembly = compile::build::<i64>(&source).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        for (input, output) in runs {
+            let mut program = assembly.program.clone();
+            match execute_with_input(&mut program, &input) {
+                Ok(result) => assert_eq!(result, output, "{} with input {:?}", path.display(), input),
+                Err(e) => panic!("{} with input {:?}: {}", path.display(), input, e.error),
+            }
+        }
+    }
+}
+
+#[test]
+fn optimized_programs_agree() {
+    for Program { path, source, runs } in programs() {
+        let program = compile::build::<i64>(&source).expect("build").program;
+        let optimized = optimize::optimize(&program);
+        let inputs: Vec<_> = runs.into_iter().map(|(input, _)| input).collect();
+        let (before, after) =
+            optimize::check(&program, &optimized, &inputs).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert!(after <= before, "{}: {} steps became {}", path.display(), before, after);
+    }
+}
+
+#[test]
+fn compiled_output_is_assembler_source() {
+    let source = "fn main() { output(double(input())); }\nfn double(x) { return x + x; }";
+    let text = compile::compile(source).expect("compile");
+    assert!(text.contains("\ndouble:\n"));
+    let mut program = compile::build::<i64>(source).expect("build").program;
+    assert_eq!(execute_with_input(&mut program, &[21]).ok(), Some(vec![42]));
+}
@@ -0,0 +1,339 @@
+//! Drives the `intcode-dap` binary through a debugging session, the
+//! way an editor would.
+
+use std::env;
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value};
+
+use aoc2019::intcode::assemble::assemble;
+use aoc2019::intcode::dap::{read_message, write_message};
+use aoc2019::intcode::sourcemap::SourceMap;
+
+const SOURCE: &str = "\
+; reads numbers and outputs their running total until it reads a zero.
+loop:   in [n]
+        jz [n], done
+        add [total], [n], [total]
+        out [total]
+        jnz 1, loop
+done:   halt
+n:      data 0
+total:  data 0
+";
+
+// reads a number and outputs it doubled, doubling it in a routine
+// that follows the library's calling convention.
+const CALLS: &str = "\
+        arb stack
+        in [rb+4]
+        add back, 0, [rb+2]
+        add -2, 0, [rb+3]
+        arb 2
+        jnz 1, double
+back:   out [rb+4]
+        halt
+; double(n) -> n + n
+double: add [rb+2], [rb+2], [rb+2]
+        add [rb+0], 0, [ret]
+        arb [rb+1]
+        jnz 1, [ret]
+ret:    data 0
+stack:  data 0
+";
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: i64,
+    // events that have arrived but haven't been looked at.
+    events: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_intcode-dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("start server");
+        Client {
+            stdin: child.stdin.take().expect("stdin"),
+            stdout: BufReader::new(child.stdout.take().expect("stdout")),
+            child,
+            seq: 0,
+            events: vec![],
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.stdout).expect("read").expect("server closed the stream")
+    }
+
+    // sends a request and returns the response, whether it succeeded
+    // or not.
+    fn respond(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({ "seq": self.seq, "type": "request", "command": command, "arguments": arguments });
+        write_message(&mut self.stdin, &request).expect("write");
+        loop {
+            let message = self.receive();
+            match message["type"].as_str() {
+                Some("event") => self.events.push(message),
+                _ if message["request_seq"] == json!(self.seq) => return message,
+                _ => panic!("unexpected message {}", message),
+            }
+        }
+    }
+
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        let message = self.respond(command, arguments);
+        assert_eq!(message["success"], json!(true), "{} failed: {}", command, message);
+        message["body"].clone()
+    }
+
+    fn event(&mut self, name: &str) -> Value {
+        loop {
+            if let Some(i) = self.events.iter().position(|e| e["event"] == json!(name)) {
+                return self.events.remove(i)["body"].clone();
+            }
+            let message = self.receive();
+            self.events.push(message);
+        }
+    }
+
+    fn stopped(&mut self, reason: &str) {
+        assert_eq!(self.event("stopped")["reason"], json!(reason));
+    }
+
+    fn line(&mut self) -> Value {
+        self.request("stackTrace", json!({ "threadId": 1 }))["stackFrames"][0]["line"].clone()
+    }
+
+    fn output(&mut self) -> String {
+        loop {
+            let body = self.event("output");
+            if body["category"] == json!("stdout") {
+                return body["output"].as_str().expect("output").to_string();
+            }
+        }
+    }
+}
+
+fn source(name: &str, text: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("intcode-dap-{}-{}.asm", name, process::id()));
+    fs::write(&path, text).expect("write source");
+    path
+}
+
+#[test]
+fn debugging_session() {
+    let path = source("total", SOURCE);
+    let mut client = Client::start();
+    let capabilities = client.request("initialize", json!({ "adapterID": "intcode" }));
+    assert_eq!(capabilities["supportsStepBack"], json!(true));
+    client.event("initialized");
+    client.request("launch", json!({ "program": path, "stopOnEntry": true, "input": [3] }));
+    let breakpoints = client.request(
+        "setBreakpoints",
+        json!({ "source": { "path": path }, "breakpoints": [{ "line": 5 }, { "line": 9 }, { "line": 40 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0], json!({ "verified": true, "line": 5, "instructionReference": "9" }));
+    assert_eq!(breakpoints["breakpoints"][1]["line"], json!(9));
+    assert_eq!(breakpoints["breakpoints"][2]["verified"], json!(false));
+    client.request("configurationDone", json!({}));
+    client.stopped("entry");
+    assert_eq!(client.line(), json!(2));
+
+    // runs to the output, where the registers and memory can be seen.
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    let frame = client.request("stackTrace", json!({ "threadId": 1 }))["stackFrames"][0].clone();
+    assert_eq!(frame["line"], json!(5));
+    assert_eq!(frame["name"], json!("loop+9"));
+    let scopes = client.request("scopes", json!({ "frameId": 1 }));
+    let registers = scopes["scopes"][0]["variablesReference"].clone();
+    let variables = client.request("variables", json!({ "variablesReference": registers }));
+    assert_eq!(variables["variables"][0], json!({ "name": "pc", "value": "9", "variablesReference": 0 }));
+    let memory = client.request("readMemory", json!({ "memoryReference": "9", "count": 16 }));
+    // out [total], then total's address.
+    assert_eq!(memory["data"], json!("BAAAAAAAAAAQAAAAAAAAAA=="));
+    // what's before the first cell is left out.
+    let memory = client.request("readMemory", json!({ "memoryReference": "0", "offset": -4, "count": 8 }));
+    assert_eq!(memory, json!({ "address": "0", "data": "AwAAAA==", "unreadableBytes": 0 }));
+
+    // runs out of input, so prompts for more.
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.output(), "3\n");
+    client.stopped("input");
+    let queued = client.request("evaluate", json!({ "expression": "4, 0", "context": "repl" }));
+    assert_eq!(queued["result"], json!("queued 2 (2 pending)"));
+    let total = client.request("evaluate", json!({ "expression": "[16]", "context": "repl" }));
+    assert_eq!(total["result"], json!("3"));
+
+    // steps forwards and back over the add.
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    client.request("stepBack", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(4));
+    client.request("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(5));
+
+    // breakpoints on addresses work too.
+    client.request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [] }));
+    client.request("setInstructionBreakpoints", json!({ "breakpoints": [{ "instructionReference": "0" }] }));
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.output(), "7\n");
+    client.stopped("breakpoint");
+    assert_eq!(client.line(), json!(2));
+    client.request("setInstructionBreakpoints", json!({ "breakpoints": [] }));
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.event("exited")["exitCode"], json!(0));
+    client.event("terminated");
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().expect("wait").success());
+    fs::remove_file(&path).expect("remove source");
+}
+
+#[test]
+fn steps_over_and_out_of_calls() {
+    let path = source("calls", CALLS);
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.event("initialized");
+    client.request("launch", json!({ "program": path, "input": [21] }));
+    client.request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": [{ "line": 6 }] }));
+    client.request("configurationDone", json!({}));
+    client.stopped("breakpoint");
+
+    // stepping over the call runs all of it.
+    client.request("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(7));
+    client.request("reverseContinue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    assert_eq!(client.line(), json!(6));
+
+    // stepping in and then over goes a line at a time inside it, and
+    // stepping out finishes it.
+    client.request("stepIn", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(10));
+    client.request("next", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(11));
+    client.request("stepOut", json!({ "threadId": 1 }));
+    client.stopped("step");
+    assert_eq!(client.line(), json!(7));
+    client.request("next", json!({ "threadId": 1 }));
+    assert_eq!(client.output(), "42\n");
+    client.stopped("step");
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().expect("wait").success());
+    fs::remove_file(&path).expect("remove source");
+}
+
+#[test]
+fn source_maps_locate_assembled_programs() {
+    // the program without its source, and a map naming the source
+    // relative to the map.  The map puts the halt and the data in a
+    // second file.
+    let directory = env::temp_dir().join(format!("intcode-dap-map-{}", process::id()));
+    fs::create_dir_all(&directory).expect("create directory");
+    let assembly = assemble::<i64>(SOURCE).expect("assemble");
+    let program = directory.join("total.intcode");
+    let words: Vec<String> = assembly.program.iter().map(i64::to_string).collect();
+    fs::write(&program, words.join(",")).expect("write program");
+    let mut map = SourceMap::new("total.asm", &assembly);
+    for span in map.spans.iter_mut().filter(|span| span.line >= 7) {
+        span.file = "done.asm".to_string();
+    }
+    map.save(SourceMap::sidecar(&program)).expect("write map");
+
+    let source = directory.join("total.asm");
+    let done = directory.join("done.asm");
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.event("initialized");
+    client.request("launch", json!({ "program": program, "input": [3] }));
+    let breakpoints =
+        client.request("setBreakpoints", json!({ "source": { "path": source }, "breakpoints": [{ "line": 5 }] }));
+    assert_eq!(breakpoints["breakpoints"][0]["instructionReference"], json!("9"));
+    client.request("configurationDone", json!({}));
+    client.stopped("breakpoint");
+    let frame = client.request("stackTrace", json!({ "threadId": 1 }))["stackFrames"][0].clone();
+    assert_eq!(frame["source"]["path"], json!(source));
+    assert_eq!(frame["line"], json!(5));
+    assert_eq!(frame["name"], json!("loop+9"));
+
+    // clearing the breakpoints in one file leaves the other's.
+    let breakpoints =
+        client.request("setBreakpoints", json!({ "source": { "path": done }, "breakpoints": [{ "line": 7 }] }));
+    assert_eq!(breakpoints["breakpoints"][0]["instructionReference"], json!("14"));
+    client.request("setBreakpoints", json!({ "source": { "path": source }, "breakpoints": [] }));
+    client.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(client.output(), "3\n");
+    client.stopped("input");
+    client.request("evaluate", json!({ "expression": "0", "context": "repl" }));
+    client.request("continue", json!({ "threadId": 1 }));
+    client.stopped("breakpoint");
+    let frame = client.request("stackTrace", json!({ "threadId": 1 }))["stackFrames"][0].clone();
+    assert_eq!(frame["source"]["path"], json!(done));
+    assert_eq!(frame["name"], json!("done"));
+
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().expect("wait").success());
+    fs::remove_dir_all(&directory).expect("remove directory");
+}
+
+#[test]
+fn pauses_programs_that_never_halt() {
+    let path = env::temp_dir().join(format!("intcode-dap-spin-{}.asm", process::id()));
+    fs::write(&path, "spin:   jnz 1, spin\n").expect("write source");
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.event("initialized");
+    client.request("launch", json!({ "program": path }));
+    client.request("configurationDone", json!({}));
+    client.request("pause", json!({ "threadId": 1 }));
+    client.stopped("pause");
+    let frame = client.request("stackTrace", json!({ "threadId": 1 }))["stackFrames"][0].clone();
+    assert_eq!(frame["name"], json!("spin"));
+    let response = client.respond("readMemory", json!({ "memoryReference": i64::MAX.to_string(), "count": 8 }));
+    assert_eq!(response["success"], json!(false));
+    assert!(response["message"].as_str().expect("message").contains("out of range"));
+
+    // disconnecting works while the program runs, too.
+    client.request("continue", json!({ "threadId": 1 }));
+    client.request("disconnect", json!({}));
+    assert!(client.child.wait().expect("wait").success());
+    fs::remove_file(&path).expect("remove source");
+}
+
+#[test]
+fn reports_errors() {
+    let mut client = Client::start();
+    client.request("initialize", json!({}));
+    client.seq += 1;
+    let request = json!({ "seq": client.seq, "type": "request", "command": "launch",
+                          "arguments": { "program": "/nonexistent/program.asm" } });
+    write_message(&mut client.stdin, &request).expect("write");
+    let response = loop {
+        let message = client.receive();
+        if message["type"] == json!("response") {
+            break message;
+        }
+    };
+    assert_eq!(response["success"], json!(false));
+    assert!(response["message"].as_str().expect("message").contains("/nonexistent/program.asm"));
+    drop(client.stdin);
+    assert!(client.child.wait().expect("wait").success());
+}
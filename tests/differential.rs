@@ -0,0 +1,63 @@
+//! Property tests checking `intcode::Machine` against the reference
+//! interpreter on randomly generated programs.
+
+mod reference;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn machine_matches_reference(
+        program in vec(any::<u64>(), 1..120),
+        input in vec(any::<u64>(), 0..8),
+    ) {
+        reference::check(&reference::generate(&program), &reference::input(&input));
+    }
+}
+
+#[test]
+fn reference_runs_examples() {
+    let quine = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+    assert_eq!(reference::reference(&quine, &[]).output, quine);
+
+    let big_program = vec![
+        3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+        0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+        20, 1105, 1, 46, 98, 99,
+    ];
+    for (input, expected) in &[(7, 999), (8, 1000), (9, 1001)] {
+        assert_eq!(reference::reference(&big_program, &[*input]).output, vec![*expected]);
+        reference::check(&big_program, &[*input]);
+    }
+}
+
+#[test]
+fn errors_match_reference() {
+    let programs: &[&[i64]] = &[
+        // unknown opcode
+        &[42, 99],
+        // immediate write
+        &[11101, 1, 1, 0, 99],
+        // unknown parameter type
+        &[301, 0, 0, 0, 99],
+        // negative position
+        &[1, -1, 0, 0, 99],
+        // negative relative base
+        &[109, -1, 99],
+        // overflow
+        &[1101, i64::MAX, 1, 0, 99],
+        // missing input
+        &[3, 0, 99],
+        // jump outside memory
+        &[1105, 1, 10000, 99],
+        // never halts
+        &[1105, 1, 0],
+    ];
+    for program in programs {
+        assert!(reference::reference(program, &[]).error.is_some(), "{:?}", program);
+        reference::check(program, &[]);
+    }
+}
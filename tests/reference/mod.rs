@@ -0,0 +1,308 @@
+//! A deliberately simple intcode interpreter, for checking
+//! `intcode::Machine` against, and a generator of random but mostly
+//! well-formed programs to check it with.
+//!
+//! The reference only knows the full (day 9) instruction set and
+//! checked arithmetic, and reports errors as the same
+//! `IntcodeError` variants the machine uses so the two can be
+//! compared directly.
+
+#![allow(dead_code)]
+
+use aoc2019::intcode::{Exit, IntcodeError, Machine};
+
+pub const STEP_LIMIT: usize = 10_000;
+
+#[derive(Debug, PartialEq)]
+pub struct Run {
+    pub output: Vec<i64>,
+    pub memory: Vec<i64>,
+    // errors are compared by their debug representation.
+    pub error: Option<String>,
+}
+
+struct Reference {
+    memory: Vec<i64>,
+    pc: usize,
+    base: i64,
+    steps: usize,
+}
+
+type Result<T> = std::result::Result<T, IntcodeError>;
+
+impl Reference {
+    fn load(&self, address: usize) -> Result<i64> {
+        match self.memory.get(address) {
+            Some(value) => Ok(*value),
+            None => Err(IntcodeError::InvalidAddress(address)),
+        }
+    }
+
+    fn store(&mut self, address: usize, value: i64) -> Result<()> {
+        match self.memory.get_mut(address) {
+            Some(cell) => {
+                *cell = value;
+                Ok(())
+            }
+            None => Err(IntcodeError::InvalidAddress(address)),
+        }
+    }
+
+    fn mode(&self, instruction: i64, n: usize) -> i64 {
+        let mut modes = instruction / 100;
+        for _ in 0..n {
+            modes /= 10;
+        }
+        modes % 10
+    }
+
+    fn position(&self, opcode: i64, value: i64) -> Result<usize> {
+        if value < 0 {
+            Err(IntcodeError::NegativePosition(self.pc, opcode, value))
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    // the address a position or relative parameter refers to.
+    fn address(&self, instruction: i64, n: usize) -> Result<usize> {
+        let raw = self.load(self.pc + 1 + n)?;
+        if self.mode(instruction, n) == 0 {
+            return self.position(instruction % 100, raw);
+        }
+        match self.base.checked_add(raw) {
+            Some(address) => self.position(instruction % 100, address),
+            None => Err(IntcodeError::InvalidRelativeBase(self.pc, self.base, raw)),
+        }
+    }
+
+    fn read(&self, instruction: i64, n: usize) -> Result<i64> {
+        match self.mode(instruction, n) {
+            0 | 2 => self.load(self.address(instruction, n)?),
+            1 => self.load(self.pc + 1 + n),
+            mode => Err(IntcodeError::UnknownParameterType(self.pc, mode)),
+        }
+    }
+
+    fn write(&mut self, instruction: i64, n: usize, value: i64) -> Result<()> {
+        match self.mode(instruction, n) {
+            0 | 2 => {
+                let address = self.address(instruction, n)?;
+                self.store(address, value)
+            }
+            1 => Err(IntcodeError::InvalidParameterType(self.pc, 1, "assign")),
+            mode => Err(IntcodeError::UnknownParameterType(self.pc, mode)),
+        }
+    }
+
+    fn run(&mut self, input: &[i64], output: &mut Vec<i64>) -> Result<()> {
+        let mut input = input.iter();
+        loop {
+            if self.steps >= STEP_LIMIT {
+                return Err(IntcodeError::StepLimit(self.pc, STEP_LIMIT));
+            }
+            let instruction = self.load(self.pc)?;
+            let opcode = instruction % 100;
+            let pc = self.pc;
+            let count = match opcode {
+                1 | 2 | 7 | 8 => 3,
+                3 | 4 | 9 => 1,
+                5 | 6 => 2,
+                99 => 0,
+                _ => return Err(IntcodeError::UnknownOpcode(pc, opcode)),
+            };
+            // every parameter's mode is checked, used or not.
+            if let Some(mode) = (0..count).map(|n| self.mode(instruction, n)).find(|&mode| mode > 2) {
+                return Err(IntcodeError::UnknownParameterType(pc, mode));
+            }
+            match opcode {
+                1 | 2 => {
+                    let a = self.read(instruction, 0)?;
+                    let b = self.read(instruction, 1)?;
+                    let result = if opcode == 1 { a.checked_add(b) } else { a.checked_mul(b) };
+                    match result {
+                        Some(result) => self.write(instruction, 2, result)?,
+                        None => return Err(IntcodeError::ArithmeticOverflow(pc, opcode, a, b)),
+                    }
+                    self.pc += 4;
+                }
+                3 => {
+                    let value = match input.next() {
+                        Some(value) => *value,
+                        None => return Err(IntcodeError::MissingInput(pc)),
+                    };
+                    self.write(instruction, 0, value)?;
+                    self.pc += 2;
+                }
+                4 => {
+                    output.push(self.read(instruction, 0)?);
+                    self.pc += 2;
+                }
+                5 | 6 => {
+                    let test = self.read(instruction, 0)?;
+                    if (test != 0) == (opcode == 5) {
+                        let target = self.read(instruction, 1)?;
+                        self.pc = self.position(opcode, target)?;
+                    } else {
+                        self.pc += 3;
+                    }
+                }
+                7 | 8 => {
+                    let a = self.read(instruction, 0)?;
+                    let b = self.read(instruction, 1)?;
+                    let result = if opcode == 7 { a < b } else { a == b };
+                    self.write(instruction, 2, result as i64)?;
+                    self.pc += 4;
+                }
+                9 => {
+                    let offset = self.read(instruction, 0)?;
+                    match self.base.checked_add(offset) {
+                        Some(base) if base >= 0 => self.base = base,
+                        _ => return Err(IntcodeError::InvalidRelativeBase(pc, self.base, offset)),
+                    }
+                    self.pc += 2;
+                }
+                99 => return Ok(()),
+                _ => return Err(IntcodeError::UnknownOpcode(pc, opcode)),
+            }
+            self.steps += 1;
+        }
+    }
+}
+
+pub fn reference(program: &[i64], input: &[i64]) -> Run {
+    let mut memory = program.to_vec();
+    memory.resize(program.len() * 101, 0);
+    let mut reference = Reference {
+        memory,
+        pc: 0,
+        base: 0,
+        steps: 0,
+    };
+    let mut output = vec![];
+    let error = reference.run(input, &mut output).err();
+    Run {
+        output,
+        memory: reference.memory,
+        error: error.map(|e| format!("{:?}", e.kind())),
+    }
+}
+
+pub fn machine(program: &[i64], input: &[i64]) -> Run {
+    let mut machine = Machine::new(program);
+    machine.set_step_limit(Some(STEP_LIMIT));
+    let mut input = input.iter();
+    let mut output = vec![];
+    let error = loop {
+        match machine.execute(&mut input) {
+            Ok(Exit::Output(o)) => output.push(o),
+            Ok(Exit::Halted { .. }) => break None,
+            Ok(Exit::AwaitingInput { pc }) => break Some(IntcodeError::MissingInput(pc)),
+            Err(e) => break Some(e),
+        }
+    };
+    Run {
+        output,
+        memory: machine.memory(0..machine.memory_len()).expect("memory").to_vec(),
+        error: error.map(|e| format!("{:?}", e.kind())),
+    }
+}
+
+/// Runs `program` on both interpreters and panics if they disagree.
+pub fn check(program: &[i64], input: &[i64]) {
+    let expected = reference(program, input);
+    let actual = machine(program, input);
+    assert_eq!(actual.error, expected.error, "program {:?}", program);
+    assert_eq!(actual.output, expected.output, "program {:?}", program);
+    assert!(actual.memory == expected.memory, "memory differs for program {:?}", program);
+}
+
+// positions and jump targets are drawn from the first `SPAN` cells,
+// which generated programs always fill.
+const SPAN: i64 = 64;
+
+struct Choices<'a> {
+    choices: &'a [u64],
+    next: usize,
+}
+
+impl<'a> Choices<'a> {
+    fn next(&mut self) -> u64 {
+        let choice = self.choices.get(self.next).copied().unwrap_or(0);
+        self.next += 1;
+        choice
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn done(&self) -> bool {
+        self.next >= self.choices.len()
+    }
+}
+
+fn value(choices: &mut Choices) -> i64 {
+    match choices.below(20) {
+        0 => i64::MAX,
+        1 => i64::MIN,
+        2 => -(choices.below(SPAN as u64) as i64),
+        _ => choices.below(SPAN as u64 * 2) as i64 - 2,
+    }
+}
+
+fn parameter(choices: &mut Choices, write: bool) -> (i64, i64) {
+    let mode = match choices.below(40) {
+        // occasionally something the machine should reject.
+        0 => 3 + choices.below(7) as i64,
+        1..=3 if write => 1,
+        n if write => (n % 2) as i64 * 2,
+        n => (n % 3) as i64,
+    };
+    let raw = match mode {
+        0 => choices.below(SPAN as u64) as i64,
+        2 => choices.below(24) as i64 - 2,
+        _ => value(choices),
+    };
+    (mode, raw)
+}
+
+/// Builds a program from a stream of arbitrary choices, so that both
+/// property tests and fuzzers can drive it.
+pub fn generate(choices: &[u64]) -> Vec<i64> {
+    let mut choices = Choices { choices, next: 0 };
+    let mut program = vec![];
+    while !choices.done() && program.len() < SPAN as usize * 2 {
+        let opcode = match choices.below(24) {
+            0 => 99,
+            1 => choices.below(100) as i64,
+            n => [1, 2, 3, 4, 5, 6, 7, 8, 9, 1, 2, 7, 8, 5, 6, 4, 1, 2, 9, 3, 4, 1][n as usize - 2],
+        };
+        let parameters: &[bool] = match opcode {
+            1 | 2 | 7 | 8 => &[false, false, true],
+            3 => &[true],
+            4 | 9 => &[false],
+            5 | 6 => &[false, false],
+            _ => &[],
+        };
+        let mut instruction = opcode;
+        let mut raws = vec![];
+        for (n, write) in parameters.iter().enumerate() {
+            let (mode, raw) = parameter(&mut choices, *write);
+            instruction += mode * 10i64.pow(n as u32 + 2);
+            raws.push(raw);
+        }
+        program.push(instruction);
+        program.extend(raws);
+    }
+    program.push(99);
+    while program.len() < SPAN as usize {
+        program.push(value(&mut choices));
+    }
+    program
+}
+
+pub fn input(choices: &[u64]) -> Vec<i64> {
+    let mut choices = Choices { choices, next: 0 };
+    (0..choices.choices.len()).map(|_| value(&mut choices)).collect()
+}
@@ -0,0 +1,35 @@
+//! Translates the program of every conformance case, and the day 9
+//! puzzle input, writing each to its own module in `OUT_DIR` and a
+//! list of the modules for `lib.rs` to include.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use aoc2019::intcode::{self, conformance, transpile};
+
+fn main() {
+    let out = env::var_os("OUT_DIR").expect("OUT_DIR");
+    let out = Path::new(&out);
+    let mut programs = vec![];
+    for path in conformance::cases() {
+        let name = path.file_stem().expect("case name").to_string_lossy().into_owned();
+        let text = fs::read_to_string(&path).expect("read case");
+        let case = conformance::parse_case(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        programs.push((name, case.program));
+    }
+    let boost = fs::read_to_string("../input/2019/day9.txt").expect("read day 9");
+    programs.push(("day9_boost".to_string(), intcode::parse_program(boost.trim())));
+    let mut modules = String::new();
+    let mut names = vec![];
+    for (name, program) in programs {
+        let path = out.join(format!("{}.rs", name));
+        fs::write(&path, transpile::transpile(&program)).expect("write translation");
+        modules.push_str(&format!("#[path = {:?}]\npub mod {};\n", path, name));
+        names.push(name);
+    }
+    modules.push_str(&format!("translations!({});\n", names.join(", ")));
+    fs::write(out.join("modules.rs"), modules).expect("write modules");
+    println!("cargo:rerun-if-changed=../tests/conformance");
+    println!("cargo:rerun-if-changed=../input/2019/day9.txt");
+}
@@ -0,0 +1,45 @@
+//! Programs translated by `intcode::transpile`, one module each,
+//! generated by the build script: one for each conformance case,
+//! named after it, and `day9_boost` for the day 9 puzzle input.
+
+use std::slice;
+
+use aoc2019::intcode::{ExitResult, Machine};
+
+/// A translated program's `Program`, whichever module it's from.
+pub trait Translation {
+    fn execute(&mut self, input: &mut slice::Iter<i64>) -> ExitResult;
+    fn machine(&self) -> &Machine;
+    fn set_step_limit(&mut self, limit: Option<usize>);
+}
+
+macro_rules! translations {
+    ($($name:ident),*) => {
+        $(
+            impl Translation for $name::Program {
+                fn execute(&mut self, input: &mut slice::Iter<i64>) -> ExitResult {
+                    $name::Program::execute(self, input)
+                }
+
+                fn machine(&self) -> &Machine {
+                    $name::Program::machine(self)
+                }
+
+                fn set_step_limit(&mut self, limit: Option<usize>) {
+                    $name::Program::set_step_limit(self, limit)
+                }
+            }
+        )*
+
+        /// A fresh copy of the translation named `name`, if there is
+        /// one.
+        pub fn translation(name: &str) -> Option<Box<dyn Translation>> {
+            match name {
+                $(stringify!($name) => Some(Box::new($name::Program::new())),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+include!(concat!(env!("OUT_DIR"), "/modules.rs"));
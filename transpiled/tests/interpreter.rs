@@ -0,0 +1,111 @@
+//! Checks programs translated by `intcode::transpile` against the
+//! interpreter.
+
+use std::fs;
+use std::slice;
+
+use aoc2019::intcode::{self, conformance, Exit, ExitResult, Machine};
+use aoc2019_transpiled::{translation, Translation};
+
+// either the interpreter or a translation.
+trait Runner {
+    fn execute(&mut self, input: &mut slice::Iter<i64>) -> ExitResult;
+    fn machine(&self) -> &Machine;
+}
+
+impl Runner for Machine {
+    fn execute(&mut self, input: &mut slice::Iter<i64>) -> ExitResult {
+        Machine::execute(self, input)
+    }
+
+    fn machine(&self) -> &Machine {
+        self
+    }
+}
+
+impl Runner for Box<dyn Translation> {
+    fn execute(&mut self, input: &mut slice::Iter<i64>) -> ExitResult {
+        Translation::execute(self.as_mut(), input)
+    }
+
+    fn machine(&self) -> &Machine {
+        Translation::machine(self.as_ref())
+    }
+}
+
+// everything observable about a run.
+#[derive(Debug, PartialEq)]
+struct Run {
+    output: Vec<i64>,
+    exit: String,
+    pc: usize,
+    relative_base: i64,
+    steps: usize,
+    memory: Vec<i64>,
+}
+
+fn run<R: Runner>(runner: &mut R, input: &[i64]) -> Run {
+    let mut input = input.iter();
+    let mut output = vec![];
+    let exit = loop {
+        match runner.execute(&mut input) {
+            Ok(Exit::Output(value)) => output.push(value),
+            other => break format!("{:?}", other),
+        }
+    };
+    let machine = runner.machine();
+    Run {
+        output,
+        exit,
+        pc: machine.pc(),
+        relative_base: machine.relative_base(),
+        steps: machine.steps(),
+        memory: machine.memory(0..machine.memory_len()).expect("memory").to_vec(),
+    }
+}
+
+fn check(name: &str, program: &[i64], inputs: &[&[i64]]) -> Vec<Vec<i64>> {
+    let mut outputs = vec![];
+    for input in inputs {
+        let expected = run(&mut Machine::new(program), input);
+        let actual = run(&mut translation(name).expect("translation"), input);
+        assert!(actual == expected, "{} on {:?}: {:?} != {:?}", name, input, actual, expected);
+        outputs.push(actual.output);
+    }
+    outputs
+}
+
+fn boost() -> Vec<i64> {
+    intcode::parse_program(include_str!("../../input/2019/day9.txt").trim())
+}
+
+#[test]
+fn conformance_cases_match_interpreter() {
+    // translations always run the day 9 ISA, so the cases' own
+    // expectations don't all apply; these cover each of day 5's
+    // comparisons either way.
+    let inputs: &[&[i64]] = &[&[], &[-5], &[0], &[7], &[8], &[9], &[1000]];
+    for path in conformance::cases() {
+        let name = path.file_stem().expect("case name").to_string_lossy().into_owned();
+        let case = conformance::parse_case(&fs::read_to_string(&path).expect("read case")).expect("parse case");
+        check(&name, &case.program, &[&case.input]);
+        check(&name, &case.program, inputs);
+    }
+}
+
+#[test]
+fn day9_matches_interpreter() {
+    let outputs = check("day9_boost", &boost(), &[&[1], &[2], &[3], &[]]);
+    assert_eq!(outputs[1], vec![87721]);
+}
+
+#[test]
+fn translation_honours_limits() {
+    let mut program = translation("day9_boost").expect("translation");
+    program.set_step_limit(Some(1000));
+    let error = program.execute(&mut [2].iter()).expect_err("step limit");
+    let mut machine = Machine::new(&boost());
+    machine.set_step_limit(Some(1000));
+    let expected = machine.execute([2].iter()).expect_err("step limit");
+    assert_eq!(format!("{:?}", error), format!("{:?}", expected));
+}